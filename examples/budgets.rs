@@ -0,0 +1,38 @@
+use camino::Utf8PathBuf;
+use owo_colors::OwoColorize;
+use substance::budgets::Budgets;
+use substance::{BuildContext, BuildRunner};
+
+/// Usage: `budgets [substance.toml] [baseline snapshot]`. Exits with status 1
+/// when a budget is exceeded.
+fn main() -> Result<(), eyre::Error> {
+    env_logger::init();
+
+    let manifest_dir = Utf8PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("analysis-target");
+    let mut args = std::env::args().skip(1);
+    let budgets_path = args
+        .next()
+        .map(Utf8PathBuf::from)
+        .unwrap_or_else(|| manifest_dir.join("substance.toml"));
+    let baseline = args.next().map(BuildContext::load).transpose()?;
+    let budgets = Budgets::load(&budgets_path)?;
+
+    println!(
+        "{} {} {}",
+        "🚀".green(),
+        "Checking budgets of".bright_green(),
+        budgets_path
+    );
+    let result = BuildRunner::for_manifest(manifest_dir.join("Cargo.toml"))
+        .arg("--release")
+        .run()?;
+
+    let report = budgets.evaluate(result.context(), baseline.as_ref());
+    for violation in &report.violations {
+        println!("{} {}", "❌".red(), violation);
+    }
+    if report.is_ok() {
+        println!("{} {}", "✅".green(), "Every budget is met".bright_green());
+    }
+    std::process::exit(report.exit_code());
+}
@@ -0,0 +1,60 @@
+use camino::Utf8PathBuf;
+use owo_colors::OwoColorize;
+use substance::features::FeatureMatrix;
+use substance::formatting::{format_bytes, format_duration_diff, format_size_diff};
+
+fn main() -> Result<(), eyre::Error> {
+    env_logger::init();
+
+    let manifest_path = Utf8PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("analysis-target")
+        .join("Cargo.toml");
+    println!(
+        "{} {} {}",
+        "🚀".green(),
+        "Measuring the features of".bright_green(),
+        manifest_path
+    );
+
+    let report = FeatureMatrix::for_manifest(&manifest_path).run()?;
+
+    println!(
+        "\n{} {} (.text: {})",
+        "📊 Baseline".blue().bold(),
+        report.baseline.context().artifact.name.cyan(),
+        format_bytes(report.baseline.context().text_size.value())
+    );
+
+    for cost in &report.features {
+        println!();
+        println!(
+            "{} {}: file {}, .text {}, {:+} LLVM lines, build {}",
+            "🧩 Feature".purple().bold(),
+            cost.features.join(", ").cyan().bold(),
+            format_size_diff(cost.delta.file_size).bright_red(),
+            format_size_diff(cost.delta.text_size).bright_red(),
+            cost.delta.llvm_lines.bright_blue(),
+            format_duration_diff(cost.delta.build_time.value()).bright_yellow(),
+        );
+        for krate in cost.delta.crates.iter().take(10) {
+            println!(
+                "  {} - {}, {:+} LLVM lines, build {}",
+                krate.crate_name.cyan(),
+                format_size_diff(krate.size),
+                krate.llvm_lines,
+                format_duration_diff(krate.build_time.value()),
+            );
+        }
+    }
+
+    for (features, error) in &report.failed {
+        println!(
+            "{} {}: {}",
+            "❌ Feature".red().bold(),
+            features.join(", ").cyan(),
+            error
+        );
+    }
+
+    Ok(())
+}
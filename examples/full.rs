@@ -1,7 +1,9 @@
 use camino::Utf8PathBuf;
 use itertools::Itertools;
 use owo_colors::OwoColorize;
-use substance::{BuildContext, ByteSize, CrateName, DemangledSymbolWithoutHash, NumberOfCopies};
+use substance::{
+    BuildContext, BuildResult, ByteSize, CrateName, DemangledSymbolWithoutHash, NumberOfCopies,
+};
 
 fn main() -> Result<(), eyre::Error> {
     env_logger::init();
@@ -44,6 +46,8 @@ fn main() -> Result<(), eyre::Error> {
     let a = substance::BuildRunner::for_manifest(&manifest_path)
         .arg("--features")
         .arg("facet-json")
+        .self_profile("facet_json")
+        .print_type_sizes("facet_json")
         .run()?;
 
     let b = substance::BuildRunner::for_manifest(&manifest_path)
@@ -51,9 +55,12 @@ fn main() -> Result<(), eyre::Error> {
         .arg("facet-toml")
         .run()?;
 
-    show_report(&a)?;
-    show_report(&b)?;
-    show_diff(&a, &b)?;
+    show_report(a.context())?;
+    show_critical_path(&a);
+    show_proc_macros(&a);
+    show_report(b.context())?;
+    show_critical_path(&b);
+    show_diff(a.context(), b.context())?;
 
     Ok(())
 }
@@ -63,9 +70,13 @@ fn show_report(context: &BuildContext) -> eyre::Result<()> {
     println!("\n{}", "📊 BUILD REPORT".blue().bold());
     println!("{}", "═".repeat(50).blue());
 
+    let build_duration = match context.wall_duration {
+        Some(d) => format!("{:.2}s", d.as_secs_f64()),
+        None => "n/a".to_string(),
+    };
     println!(
         "Build duration: {}, Binary size: {} (of which {} is .text)",
-        format!("{:.2}s", context.wall_duration.as_secs_f64()).bright_yellow(),
+        build_duration.bright_yellow(),
         format_bytes(context.file_size.value()).bright_green(),
         format_bytes(context.text_size.value()).bright_blue()
     );
@@ -175,6 +186,64 @@ fn show_report(context: &BuildContext) -> eyre::Result<()> {
         );
     }
 
+    println!();
+    println!(
+        "{}",
+        "⚙️  Top 20 crates by codegen tail (time after metadata was ready)"
+            .purple()
+            .bold()
+    );
+    for (i, tail) in substance::reporting::codegen_tails(&context.crates)
+        .into_iter()
+        .take(20)
+        .enumerate()
+    {
+        println!(
+            "{}. {} - {} frontend + {} codegen ({})",
+            (i + 1).yellow(),
+            tail.crate_name.cyan().bold(),
+            format!("{:.2}s", tail.frontend.as_secs_f64()).bright_blue(),
+            format!("{:.2}s", tail.codegen.as_secs_f64()).bright_red(),
+            format!("{:.0}% codegen", tail.codegen_ratio() * 100.0).dimmed(),
+        );
+    }
+
+    let largest_types = substance::reporting::largest_types(&context.crates);
+    if !largest_types.is_empty() {
+        println!();
+        println!("{}", "📐 Top 10 largest types".purple().bold());
+        for (i, layout) in largest_types.into_iter().take(10).enumerate() {
+            println!(
+                "{}. {} - {} ({} padding)",
+                (i + 1).yellow(),
+                layout.name.cyan(),
+                format_bytes(layout.size.value()).bright_green(),
+                format_bytes(layout.total_padding().value()).dimmed(),
+            );
+        }
+    }
+
+    for krate in &context.crates {
+        let Some(profile) = &krate.self_profile else {
+            continue;
+        };
+        println!();
+        println!(
+            "{} {} ({:.2}s profiled)",
+            "🔬 Slowest queries of".purple().bold(),
+            krate.name.cyan().bold(),
+            profile.total().as_secs_f64()
+        );
+        for query in profile.queries.iter().take(10) {
+            println!(
+                "  {} - {} ({} calls)",
+                query.label.bright_blue(),
+                format!("{:.3}s", query.self_time.as_secs_f64()).bright_red(),
+                query.invocations
+            );
+        }
+    }
+
     println!();
     println!("{}", "🏋️  Top 20 largest symbols by size".purple().bold());
 
@@ -307,15 +376,127 @@ fn show_report(context: &BuildContext) -> eyre::Result<()> {
     Ok(())
 }
 
+fn show_proc_macros(result: &BuildResult) {
+    println!();
+    println!("{}", "🪄 Proc macros".purple().bold());
+    for krate in &result.proc_macros {
+        let build = match krate.build_duration() {
+            Some(d) => format!("{:.2}s", d.as_secs_f64()),
+            None => "n/a".to_string(),
+        };
+        println!(
+            "  {} - built in {}, ran for {}",
+            krate.name.cyan().bold(),
+            build.bright_blue(),
+            format!("{:.3}s", krate.expansion_time().as_secs_f64()).bright_red(),
+        );
+        for e in &krate.expansions {
+            println!(
+                "    {} in {}: {:.3}s ({} expansions)",
+                e.expansion.invocation.bright_blue(),
+                e.consumer.cyan(),
+                e.expansion.time.as_secs_f64(),
+                e.expansion.expansions
+            );
+        }
+    }
+    for e in &result.unattributed_expansions {
+        println!(
+            "  {} in {}: {:.3}s {}",
+            e.expansion.invocation.bright_blue(),
+            e.consumer.cyan(),
+            e.expansion.time.as_secs_f64(),
+            "(unknown proc-macro crate)".dimmed()
+        );
+    }
+}
+
+fn show_critical_path(result: &BuildResult) {
+    let Some(cp) = result.critical_path() else {
+        println!("{}", "No unit graph, skipping critical path".dimmed());
+        return;
+    };
+
+    println!();
+    println!(
+        "{} {}",
+        "🧵 Critical path:".purple().bold(),
+        format!("{:.2}s", cp.length.as_secs_f64()).bright_blue()
+    );
+    for &i in &cp.path {
+        let unit = &cp.units[i];
+        println!(
+            "  {} ({}) - {} of {}, {} dependents",
+            unit.unit
+                .target
+                .name
+                .as_deref()
+                .unwrap_or("?")
+                .cyan()
+                .bold(),
+            unit.unit.mode.dimmed(),
+            format!("{:.2}s", unit.contribution.as_secs_f64()).bright_blue(),
+            format!("{:.2}s", unit.duration.as_secs_f64()).dimmed(),
+            unit.dependents.yellow(),
+        );
+    }
+
+    println!();
+    println!("{}", "🚧 Crates blocking parallelism".purple().bold());
+    for (i, unit) in cp.blockers().into_iter().take(10).enumerate() {
+        println!(
+            "{}. {} - {} units wait on it",
+            (i + 1).yellow(),
+            unit.unit
+                .target
+                .name
+                .as_deref()
+                .unwrap_or("?")
+                .cyan()
+                .bold(),
+            unit.dependents,
+        );
+    }
+
+    println!();
+    println!(
+        "{}",
+        "🛋️  Top 10 crates with the most slack".purple().bold()
+    );
+    for (i, unit) in cp
+        .units
+        .iter()
+        .filter(|u| !u.duration.is_zero())
+        .sorted_by(|a, b| b.slack.cmp(&a.slack))
+        .take(10)
+        .enumerate()
+    {
+        println!(
+            "{}. {} - {} slack ({} to build)",
+            (i + 1).yellow(),
+            unit.unit
+                .target
+                .name
+                .as_deref()
+                .unwrap_or("?")
+                .cyan()
+                .bold(),
+            format!("{:.2}s", unit.slack.as_secs_f64()).bright_green(),
+            format!("{:.2}s", unit.duration.as_secs_f64()).dimmed(),
+        );
+    }
+}
+
 fn show_diff(baseline: &BuildContext, current: &BuildContext) -> eyre::Result<()> {
-    use itertools::Itertools;
-    use std::collections::{HashMap, HashSet};
+    use substance::{ChangeKind, DiffOptions};
 
     println!();
     println!("{}", "🆚 DIFF REPORT".yellow().bold());
     println!("{}", "═".repeat(50).yellow());
 
-    // Helper closures --------------------------------------------------------
+    let options = DiffOptions::default();
+    let diff = baseline.compare(current, &options);
+
     let pct = |old: f64, new: f64| -> f64 {
         if old == 0.0 {
             0.0
@@ -323,7 +504,6 @@ fn show_diff(baseline: &BuildContext, current: &BuildContext) -> eyre::Result<()
             (new - old) / old * 100.0
         }
     };
-
     let format_change = |delta: f64| -> String {
         if delta.abs() < 0.01 {
             "≈0%".to_string()
@@ -334,269 +514,165 @@ fn show_diff(baseline: &BuildContext, current: &BuildContext) -> eyre::Result<()
         }
     };
 
-    // Build maps of crates ----------------------------------------------------
-    let base_map: HashMap<_, _> = baseline.crates.iter().map(|c| (&c.name, c)).collect();
-    let curr_map: HashMap<_, _> = current.crates.iter().map(|c| (&c.name, c)).collect();
-
-    // Added / removed crates --------------------------------------------------
-    let added: Vec<_> = curr_map
-        .keys()
-        .filter(|k| !base_map.contains_key(*k))
-        .sorted()
-        .collect();
-    let removed: Vec<_> = base_map
-        .keys()
-        .filter(|k| !curr_map.contains_key(*k))
-        .sorted()
-        .collect();
+    let sizes = &diff.file_size_diff;
+    println!(
+        "Binary size: {} → {} ({}), .text: {} → {} ({})",
+        format_bytes(sizes.file_size_before.value()).bright_blue(),
+        format_bytes(sizes.file_size_after.value()).bright_blue(),
+        format_change(pct(
+            sizes.file_size_before.value() as f64,
+            sizes.file_size_after.value() as f64
+        )),
+        format_bytes(sizes.text_size_before.value()).bright_blue(),
+        format_bytes(sizes.text_size_after.value()).bright_blue(),
+        format_change(pct(
+            sizes.text_size_before.value() as f64,
+            sizes.text_size_after.value() as f64
+        )),
+    );
+    println!();
 
-    if !added.is_empty() {
+    if !diff.crates_added.is_empty() {
         println!("{}", "➕ Crates added:".purple().bold());
-        for name in &added {
+        for name in &diff.crates_added {
             println!("  {}", name.cyan().bold());
         }
         println!();
     }
 
-    if !removed.is_empty() {
+    if !diff.crates_removed.is_empty() {
         println!("{}", "➖ Crates removed:".purple().bold());
-        for name in &removed {
+        for name in &diff.crates_removed {
             println!("  {}", name.cyan().bold());
         }
         println!();
     }
 
-    // Crate size & build-time changes ----------------------------------------
-    println!("{}", "🏗️  Notable crate changes (>5%)".purple().bold());
-
-    let mut interesting = Vec::new();
-
-    for (name, base_crate) in &base_map {
-        if let Some(curr_crate) = curr_map.get(name) {
-            // Size diff
-            let base_size: u64 = base_crate.symbols.values().map(|s| s.size.value()).sum();
-            let curr_size: u64 = curr_crate.symbols.values().map(|s| s.size.value()).sum();
-
-            // Build-time diff (optional)
-            let base_time = base_crate
-                .timing_info
-                .as_ref()
-                .map(|ti| ti.duration)
-                .unwrap_or(0.0);
-            let curr_time = curr_crate
-                .timing_info
-                .as_ref()
-                .map(|ti| ti.duration)
-                .unwrap_or(0.0);
-
-            let size_pct = pct(base_size as f64, curr_size as f64);
-            let time_pct = pct(base_time, curr_time);
-
-            let size_changed = size_pct.abs() > 5.0;
-            let time_changed = time_pct.abs() > 10.0 && curr_time > 0.33;
-
-            if size_changed || time_changed {
-                interesting.push((
-                    *name, base_size, curr_size, size_pct, base_time, curr_time, time_pct,
-                ));
-            }
-        }
-    }
-
-    if interesting.is_empty() {
+    println!(
+        "{}",
+        format!(
+            "🏗️  Notable crate changes (>{}%)",
+            options.crate_size_threshold
+        )
+        .purple()
+        .bold()
+    );
+    let crate_changes: Vec<_> = diff
+        .crate_changes
+        .iter()
+        .filter(|c| c.kind() == ChangeKind::Changed)
+        .collect();
+    if crate_changes.is_empty() {
         println!(
             "{}",
             "No significant crate-level changes found.".bright_black()
         );
-    } else {
-        for (name, b_sz, c_sz, sz_pct, b_t, c_t, t_pct) in
-            interesting.into_iter().sorted_by_key(|t| -t.3.abs() as i64)
-        {
-            let size_line = format!(
-                "{} → {} ({})",
-                format_bytes(b_sz).bright_blue(),
-                format_bytes(c_sz).bright_blue(),
-                format_change(sz_pct)
-            );
-
-            let time_line = if b_t == 0.0 && c_t == 0.0 {
-                "no timing info".to_string()
-            } else {
-                format!("{:.2}s → {:.2}s ({})", b_t, c_t, format_change(t_pct))
-            };
-
-            println!(
-                "  {}  |  {}  |  {}",
-                name.cyan().bold(),
-                size_line,
-                time_line
-            );
-        }
     }
-
-    // Function-level (symbol size) and LLVM IR line differences --------------
-    // We handle symbol-size changes and LLVM-line changes separately because
-    // they come from different data sources.
-
-    // -----------------------------------------------------------------------
-    // 1)  Symbol-level size changes
-    // -----------------------------------------------------------------------
-    println!();
-    println!("{}", "📐 Notable symbol size changes (>5%)".purple().bold());
-
-    // Build a map of (crate, symbol_without_hash) -> total_size
-    let collect_symbol_sizes =
-        |ctx: &BuildContext| -> HashMap<(CrateName, DemangledSymbolWithoutHash), u64> {
-            let mut map = HashMap::new();
-            for krate in &ctx.crates {
-                for sym in krate.symbols.values() {
-                    let key = (krate.name.clone(), sym.name.strip_hash());
-                    map.entry(key)
-                        .and_modify(|v| *v += sym.size.value())
-                        .or_insert(sym.size.value());
-                }
-            }
-            map
+    for change in crate_changes {
+        let (b_sz, c_sz) = (
+            change.size_before.unwrap_or(0),
+            change.size_after.unwrap_or(0),
+        );
+        let size_line = format!(
+            "{} → {} ({})",
+            format_bytes(b_sz).bright_blue(),
+            format_bytes(c_sz).bright_blue(),
+            format_change(pct(b_sz as f64, c_sz as f64))
+        );
+        let time_line = match (change.build_time_before, change.build_time_after) {
+            (Some(b), Some(c)) => format!(
+                "{:.2}s → {:.2}s ({})",
+                b.as_secs_f64(),
+                c.as_secs_f64(),
+                format_change(pct(b.as_secs_f64(), c.as_secs_f64()))
+            ),
+            _ => "no timing info".to_string(),
         };
-
-    type SymMap = HashMap<(CrateName, DemangledSymbolWithoutHash), u64>;
-    let base_sym_map: SymMap = collect_symbol_sizes(baseline);
-    let curr_sym_map: SymMap = collect_symbol_sizes(current);
-
-    // Determine “interesting” symbols: top-20 by size in either build
-    let mut candidate_syms: HashSet<(CrateName, DemangledSymbolWithoutHash)> = HashSet::new();
-    let add_top = |map: &SymMap, set: &mut HashSet<(CrateName, DemangledSymbolWithoutHash)>| {
-        let mut v: Vec<_> = map.iter().collect();
-        v.sort_by_key(|(_, size)| std::cmp::Reverse(**size));
-        for (k, _) in v.into_iter().take(20) {
-            set.insert(k.clone());
-        }
-    };
-    add_top(&base_sym_map, &mut candidate_syms);
-    add_top(&curr_sym_map, &mut candidate_syms);
-
-    // Compare sizes
-    let mut symbol_changes = Vec::new();
-    for key in candidate_syms {
-        let base_size = *base_sym_map.get(&key).unwrap_or(&0);
-        let curr_size = *curr_sym_map.get(&key).unwrap_or(&0);
-        if base_size == curr_size {
-            continue;
-        }
-        let delta_pct = pct(base_size as f64, curr_size as f64);
-        if delta_pct.abs() > 5.0 || base_size == 0 || curr_size == 0 {
-            symbol_changes.push((key, base_size, curr_size, delta_pct));
-        }
+        println!(
+            "  {}  |  {}  |  {}",
+            change.name.cyan().bold(),
+            size_line,
+            time_line
+        );
     }
 
-    if symbol_changes.is_empty() {
+    println!();
+    println!(
+        "{}",
+        format!(
+            "📐 Notable symbol size changes (>{}%)",
+            options.symbol_size_threshold
+        )
+        .purple()
+        .bold()
+    );
+    if diff.symbol_changes.is_empty() {
         println!(
             "{}",
             "No significant symbol-level changes found.".bright_black()
         );
-    } else {
-        symbol_changes.sort_by(|a, b| {
-            b.3.abs()
-                .partial_cmp(&a.3.abs())
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
-
-        for ((crate_name, symbol_name), old_sz, new_sz, pct_chg) in symbol_changes {
-            let line = if old_sz == 0 && new_sz > 0 {
-                format!("added ({})", format_bytes(new_sz).bright_green())
-            } else if old_sz > 0 && new_sz == 0 {
-                "removed".bright_red().to_string()
-            } else {
+    }
+    for change in &diff.symbol_changes {
+        let line = match (change.size_before, change.size_after) {
+            (None, Some(new_sz)) => format!("added ({})", format_bytes(new_sz).bright_green()),
+            (Some(_), None) => "removed".bright_red().to_string(),
+            (old_sz, new_sz) => {
+                let (old_sz, new_sz) = (old_sz.unwrap_or(0), new_sz.unwrap_or(0));
                 format!(
                     "{} → {} ({})",
                     format_bytes(old_sz).bright_blue(),
                     format_bytes(new_sz).bright_blue(),
-                    format_change(pct_chg)
+                    format_change(pct(old_sz as f64, new_sz as f64))
                 )
-            };
-
-            println!("  {}::{}", crate_name.cyan().bold(), symbol_name.blue());
-            println!("      size : {line}");
+            }
+        };
+        println!(
+            "  {}::{}",
+            change.crate_name.cyan().bold(),
+            change.name.blue()
+        );
+        if let Some(previous_name) = &change.previous_name {
+            println!("      was  : {}", previous_name.bright_black());
         }
+        println!("      size : {line}");
     }
 
-    // -----------------------------------------------------------------------
-    // 2)  LLVM-IR line-count changes
-    // -----------------------------------------------------------------------
     println!();
     println!(
         "{}",
-        "🧬 Notable LLVM IR function changes (>5%)".purple().bold()
+        format!(
+            "🧬 Notable LLVM IR function changes (>{}%)",
+            options.llvm_lines_threshold
+        )
+        .purple()
+        .bold()
     );
-
-    // Map of (crate, function_name) -> total_lines
-    let collect_fn_lines =
-        |ctx: &BuildContext| -> HashMap<(CrateName, substance::LlvmFunctionName), usize> {
-            let mut map = HashMap::new();
-            for krate in &ctx.crates {
-                for func in krate.llvm_functions.values() {
-                    let key = (krate.name.clone(), func.name.clone());
-                    map.entry(key)
-                        .and_modify(|v| *v += func.lines.value())
-                        .or_insert(func.lines.value());
-                }
-            }
-            map
-        };
-
-    type FnMap = HashMap<(CrateName, substance::LlvmFunctionName), usize>;
-    let base_fn_map: FnMap = collect_fn_lines(baseline);
-    let curr_fn_map: FnMap = collect_fn_lines(current);
-
-    // Determine interesting functions: top-20 by line count in either build
-    let mut candidate_fns: HashSet<(CrateName, substance::LlvmFunctionName)> = HashSet::new();
-    let add_top_fn = |map: &FnMap, set: &mut HashSet<(CrateName, substance::LlvmFunctionName)>| {
-        let mut v: Vec<_> = map.iter().collect();
-        v.sort_by_key(|&(_, &lines)| std::cmp::Reverse(lines));
-        for (k, _) in v.into_iter().take(20) {
-            set.insert(k.clone());
-        }
-    };
-    add_top_fn(&base_fn_map, &mut candidate_fns);
-    add_top_fn(&curr_fn_map, &mut candidate_fns);
-
-    let mut fn_changes = Vec::new();
-    for key in candidate_fns {
-        let base_lines = *base_fn_map.get(&key).unwrap_or(&0);
-        let curr_lines = *curr_fn_map.get(&key).unwrap_or(&0);
-        if base_lines == curr_lines {
-            continue;
-        }
-        let delta_pct = pct(base_lines as f64, curr_lines as f64);
-        if delta_pct.abs() > 5.0 || base_lines == 0 || curr_lines == 0 {
-            fn_changes.push((key, base_lines, curr_lines, delta_pct));
-        }
-    }
-
-    if fn_changes.is_empty() {
+    if diff.llvm_function_changes.is_empty() {
         println!(
             "{}",
             "No significant LLVM-IR function changes found.".bright_black()
         );
-    } else {
-        fn_changes.sort_by(|a, b| {
-            b.3.abs()
-                .partial_cmp(&a.3.abs())
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
-
-        for ((crate_name, fn_name), old_ln, new_ln, pct_chg) in fn_changes {
-            let line = if old_ln == 0 && new_ln > 0 {
-                format!("added ({new_ln} lines)")
-            } else if old_ln > 0 && new_ln == 0 {
-                "removed".to_string()
-            } else {
-                format!("{} → {} ({})", old_ln, new_ln, format_change(pct_chg))
-            };
-
-            println!("  {}::{}", crate_name.cyan().bold(), fn_name.blue());
-            println!("      lines: {line}");
-        }
+    }
+    for change in &diff.llvm_function_changes {
+        let line = match (change.lines_before, change.lines_after) {
+            (None, Some(new_ln)) => format!("added ({new_ln} lines)"),
+            (Some(_), None) => "removed".to_string(),
+            (old_ln, new_ln) => {
+                let (old_ln, new_ln) = (old_ln.unwrap_or(0), new_ln.unwrap_or(0));
+                format!(
+                    "{} → {} ({})",
+                    old_ln,
+                    new_ln,
+                    format_change(pct(old_ln as f64, new_ln as f64))
+                )
+            }
+        };
+        println!(
+            "  {}::{}",
+            change.crate_name.cyan().bold(),
+            change.name.blue()
+        );
+        println!("      lines: {line}");
     }
 
     Ok(())
@@ -0,0 +1,37 @@
+use camino::Utf8PathBuf;
+use owo_colors::OwoColorize;
+use substance::matrix::BuildOptions;
+use substance::profile_matrix::{ProfileConfig, ProfileMatrix};
+
+fn main() -> Result<(), eyre::Error> {
+    env_logger::init();
+
+    let manifest_path = Utf8PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("analysis-target")
+        .join("Cargo.toml");
+    println!(
+        "{} {} {}",
+        "🚀".green(),
+        "Trying profile settings on".bright_green(),
+        manifest_path
+    );
+
+    let mut configs = ProfileConfig::common();
+    configs.push(
+        ProfileConfig::new("facet-json opt-level=s").set_for_package(
+            "facet-json",
+            "opt-level",
+            "s",
+        ),
+    );
+    let report = ProfileMatrix::new(BuildOptions::for_manifest(&manifest_path).profile("release"))
+        .configs(configs)
+        .run()?;
+
+    println!("\n{}", report.table(3));
+    for (config, error) in &report.failed {
+        println!("{} {}: {}", "❌".red(), config.name.cyan(), error);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use std::fs::File;
+
+use camino::Utf8PathBuf;
+use owo_colors::OwoColorize;
+use substance::html::write_treemap;
+use substance::BuildRunner;
+
+fn main() -> Result<(), eyre::Error> {
+    env_logger::init();
+
+    let manifest_path = Utf8PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("analysis-target")
+        .join("Cargo.toml");
+    let output = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "treemap.html".to_string());
+
+    let result = BuildRunner::for_manifest(&manifest_path)
+        .arg("--release")
+        .run()?;
+    write_treemap(result.context(), File::create(&output)?)?;
+
+    println!(
+        "{} {} {}",
+        "🗺️".green(),
+        "Wrote a treemap to".bright_green(),
+        output
+    );
+    Ok(())
+}
@@ -0,0 +1,423 @@
+//! Analysis of a binary that has already been linked.
+//!
+//! [`BuildRunner`](crate::BuildRunner) drives cargo and then hands its artifacts over
+//! to this module; [`Analyzer`] does the same for binaries that were built elsewhere,
+//! like release binaries produced by CI.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use log::{debug, info, warn};
+use multimap::MultiMap;
+use owo_colors::OwoColorize;
+
+use crate::cargo::TimingInfo;
+use crate::crate_name::{self, StdHandling};
+use crate::env::{collect_rlib_paths, host_triple, stdlibs_dir};
+use crate::errors::SubstanceError;
+use crate::llvm_ir::analyze_llvm_ir_from_target_dir;
+use crate::object::{collect_deps_symbols, collect_self_data};
+use crate::observer::{BuildObserver, BuildPhase, CancellationToken, Hooks};
+use crate::profile::ProfileSettings;
+use crate::self_profile::SelfProfile;
+use crate::types::*;
+
+/// Analyzes a prebuilt binary without running cargo.
+pub struct Analyzer {
+    binary_path: Utf8PathBuf,
+
+    /// Where to look for dependency `.rlib` and `.ll` files.
+    deps_dir: Option<Utf8PathBuf>,
+
+    /// The triple the binary was built for, if it's not the host
+    target: Option<TargetTriple>,
+
+    hooks: Hooks,
+}
+
+impl Analyzer {
+    /// Create an analyzer for a binary that was built ahead of time, e.g.
+    /// `target/release/my-app`.
+    pub fn for_binary(binary_path: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            binary_path: binary_path.into(),
+            deps_dir: None,
+            target: None,
+            hooks: Hooks::default(),
+        }
+    }
+
+    /// Directory to scan for dependency `.rlib` files and `.ll` files.
+    ///
+    /// Defaults to the `deps` directory next to the binary, which is where cargo
+    /// puts them (e.g. `target/release/deps`).
+    pub fn deps_dir(mut self, deps_dir: impl Into<Utf8PathBuf>) -> Self {
+        self.deps_dir = Some(deps_dir.into());
+        self
+    }
+
+    /// The triple the binary was built for, so that symbols are matched against
+    /// the right std libraries. Defaults to the host.
+    pub fn target(mut self, triple: impl Into<TargetTriple>) -> Self {
+        self.target = Some(triple.into());
+        self
+    }
+
+    /// Report progress to `observer`, see [`BuildObserver`].
+    pub fn observer(mut self, observer: Arc<dyn BuildObserver>) -> Self {
+        self.hooks.observer = Some(observer);
+        self
+    }
+
+    /// Stop the analysis between phases when `token` is cancelled.
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.hooks.cancellation = Some(token);
+        self
+    }
+
+    pub fn run(&self) -> Result<BuildContext, SubstanceError> {
+        self.hooks.finished(self.analyze())
+    }
+
+    fn analyze(&self) -> Result<BuildContext, SubstanceError> {
+        let hooks = self.hooks.for_run();
+        if !self.binary_path.exists() {
+            return Err(SubstanceError::OpenFailed(self.binary_path.clone()));
+        }
+
+        let deps_dir = match &self.deps_dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let parent = self.binary_path.parent().unwrap_or(Utf8Path::new("."));
+                let deps = parent.join("deps");
+                if deps.is_dir() {
+                    deps
+                } else {
+                    parent.to_owned()
+                }
+            }
+        };
+        info!("Analyzing prebuilt binary: {}", self.binary_path.blue());
+        info!("Dependency directory: {}", deps_dir.blue());
+
+        let rlib_paths = collect_rlib_paths(&deps_dir);
+        let mut dep_crates: Vec<CrateName> = rlib_paths.iter().map(|v| v.0.clone()).collect();
+        dep_crates.sort();
+        dep_crates.dedup();
+
+        let kind = match self.binary_path.extension() {
+            Some("so" | "dylib" | "dll") => ArtifactKind::CDylib,
+            _ => ArtifactKind::Binary,
+        };
+        let binary = Artifact {
+            kind,
+            name: CrateName::from(self.binary_path.file_stem().unwrap_or("unknown")),
+            path: self.binary_path.clone(),
+        };
+
+        Analysis::new(AnalysisInput {
+            rlib_paths,
+            dep_crates,
+            std_rlibs: None,
+            llvm_ir_dir: &deps_dir,
+            target: self.target.as_deref(),
+            toolchain: None,
+            profile: None,
+            timing_infos: &[],
+            wall_duration: None,
+            self_profiles: HashMap::new(),
+            type_layouts: HashMap::new(),
+            hooks,
+        })?
+        .analyze_binary(&binary)
+    }
+}
+
+/// Everything needed to analyze linked binaries, regardless of where they came from.
+pub(crate) struct AnalysisInput<'a> {
+    /// `.rlib` files of the dependencies, used to attribute symbols to crates
+    pub(crate) rlib_paths: Vec<(CrateName, Utf8PathBuf)>,
+
+    /// Sorted, deduplicated crate names of the dependencies
+    pub(crate) dep_crates: Vec<CrateName>,
+
+    /// `.rlib` files of std crates built with `-Zbuild-std`. When `None`, the
+    /// prebuilt std crates from the sysroot are used.
+    pub(crate) std_rlibs: Option<Vec<(CrateName, Utf8PathBuf)>>,
+
+    /// Directory that is searched (recursively) for `.ll` files
+    pub(crate) llvm_ir_dir: &'a Utf8Path,
+
+    /// The triple we built for, `None` for the host
+    pub(crate) target: Option<&'a TargetTripleRef>,
+
+    /// The rustup toolchain we built with, `None` for the default one
+    pub(crate) toolchain: Option<&'a str>,
+
+    /// Profile used for the build, if the binary was built by us
+    pub(crate) profile: Option<ProfileSettings>,
+
+    /// Timing info reported by cargo, if the binary was built by us
+    pub(crate) timing_infos: &'a [TimingInfo],
+
+    /// Wall time of the build, if the binary was built by us
+    pub(crate) wall_duration: Option<Duration>,
+
+    /// Self-profiles of the crates that were compiled with `-Zself-profile`
+    pub(crate) self_profiles: HashMap<CrateName, SelfProfile>,
+
+    /// Type layouts of the crates that were compiled with `-Zprint-type-sizes`
+    pub(crate) type_layouts: HashMap<CrateName, HashMap<TypeName, TypeLayout>>,
+
+    /// Observer to notify of phase changes, and cancellation token to check between them
+    pub(crate) hooks: Hooks,
+}
+
+/// Data shared by all the artifacts of a build: symbol maps, LLVM IR and timings.
+/// Computed once, then each linked artifact is analyzed against it.
+pub(crate) struct Analysis {
+    target_triple: TargetTriple,
+    profile: Option<ProfileSettings>,
+    std_crates: Vec<CrateName>,
+    dep_crates: Vec<CrateName>,
+    deps_symbols: MultiMap<MangledSymbol, CrateName>,
+    llvm_functions: HashMap<LlvmFunctionName, LlvmFunction>,
+    crate_timings: HashMap<CrateName, TimingInfo>,
+    wall_duration: Option<Duration>,
+    self_profiles: HashMap<CrateName, SelfProfile>,
+    type_layouts: HashMap<CrateName, HashMap<TypeName, TypeLayout>>,
+    hooks: Hooks,
+}
+
+impl Analysis {
+    pub(crate) fn new(input: AnalysisInput<'_>) -> Result<Self, SubstanceError> {
+        let AnalysisInput {
+            mut rlib_paths,
+            dep_crates,
+            std_rlibs,
+            llvm_ir_dir,
+            target,
+            toolchain,
+            profile,
+            timing_infos,
+            wall_duration,
+            self_profiles,
+            type_layouts,
+            hooks,
+        } = input;
+
+        let target_triple = match target {
+            Some(target) => target.to_owned(),
+            None => host_triple(toolchain)?,
+        };
+
+        // Get std crates: the ones built with `-Zbuild-std` if any, the sysroot ones
+        // otherwise. Symbol hashes differ between the two, so we can't mix them.
+        let std_paths = match std_rlibs {
+            Some(std_rlibs) => std_rlibs,
+            None => {
+                let target_dylib_path = stdlibs_dir(target, toolchain)?;
+                collect_rlib_paths(&target_dylib_path)
+            }
+        };
+
+        let mut std_crates: Vec<CrateName> = std_paths.iter().map(|v| v.0.clone()).collect();
+        rlib_paths.extend_from_slice(&std_paths);
+        std_crates.sort();
+
+        // Remove std crates that were explicitly added as dependencies.
+        for c in &dep_crates {
+            if let Some(idx) = std_crates.iter().position(|v| v == c) {
+                std_crates.remove(idx);
+            }
+        }
+
+        // Build symbol mapping
+        hooks.enter_phase(BuildPhase::RlibSymbolScan)?;
+        info!("Building dependency symbol mapping...");
+        let deps_symbols = collect_deps_symbols(rlib_paths)?;
+        debug!("Collected symbols for {} dependencies.", deps_symbols.len());
+
+        // Analyze LLVM IR (if any) from the target dir
+        hooks.enter_phase(BuildPhase::LlvmIrScan)?;
+        info!(
+            "Analyzing LLVM IR files (if present) in: {}",
+            llvm_ir_dir.blue()
+        );
+        let llvm_functions = analyze_llvm_ir_from_target_dir(llvm_ir_dir).unwrap_or_else(|err| {
+            warn!(
+                "Failed to analyze LLVM IR files: {}. Continuing without LLVM IR data.",
+                err.red()
+            );
+            HashMap::new()
+        });
+
+        info!(
+            "LLVM IR analysis: found {} LLVM functions.",
+            llvm_functions.len().bright_purple()
+        );
+
+        // Pick a timing per crate: the one compiling it for the target. Build scripts
+        // and proc macros only have host units. When a package has several targets
+        // with the same name (a lib and a bin), the longest one wins.
+        let mut crate_timings: HashMap<CrateName, TimingInfo> = HashMap::new();
+        for timing in timing_infos {
+            if timing.mode.as_deref() == Some("run-custom-build") {
+                continue;
+            }
+            let crate_name = timing
+                .target
+                .name
+                .clone()
+                .map(CrateName::from)
+                .unwrap_or_else(|| CrateName::from("unknown"));
+            let replace = match crate_timings.get(&crate_name) {
+                None => true,
+                Some(existing) => {
+                    (existing.is_host_unit() && !timing.is_host_unit())
+                        || (existing.is_host_unit() == timing.is_host_unit()
+                            && timing.duration > existing.duration)
+                }
+            };
+            if replace {
+                crate_timings.insert(crate_name, timing.clone());
+            }
+        }
+
+        Ok(Self {
+            target_triple,
+            profile,
+            std_crates,
+            dep_crates,
+            deps_symbols,
+            llvm_functions,
+            crate_timings,
+            wall_duration,
+            self_profiles,
+            type_layouts,
+            hooks,
+        })
+    }
+
+    /// Analyzes a single linked artifact (binary, cdylib or dylib).
+    pub(crate) fn analyze_binary(&self, binary: &Artifact) -> Result<BuildContext, SubstanceError> {
+        self.hooks.enter_phase(BuildPhase::TextParse)?;
+
+        // Get file size of the binary
+        let file_metadata = std::fs::metadata(&binary.path)
+            .map_err(|_| SubstanceError::OpenFailed(binary.path.clone()))?;
+        let file_size = ByteSize::new(file_metadata.len());
+        info!("Binary file size: {} bytes", file_size.value().yellow());
+
+        info!(
+            "Collecting self data (.text section) from binary artifact: {}",
+            binary.path.blue()
+        );
+        let raw_data = collect_self_data(&binary.path, ".text")?;
+        let text_size = ByteSize::new(raw_data.text_size);
+        debug!(
+            "Collected self data for binary artifact (.text section size: {} bytes).",
+            text_size.value().green()
+        );
+
+        let mut context = BuildContext {
+            artifact: binary.clone(),
+            target_triple: self.target_triple.clone(),
+            profile: self.profile.clone(),
+            std_crates: self.std_crates.clone(),
+            dep_crates: self.dep_crates.clone(),
+            deps_symbols: self.deps_symbols.clone(),
+            wall_duration: self.wall_duration,
+            file_size,
+            text_size,
+            crates: Default::default(),
+        };
+
+        // Build crate information from the collected data
+        let mut crates_map: HashMap<CrateName, Crate> = HashMap::new();
+
+        // Process binary symbols and group by crate
+        for symbol in raw_data.symbols {
+            let (crate_name, _exact) =
+                crate_name::from_sym(&context, StdHandling::Merged, &symbol.name);
+            let demangled_symbol = DemangledSymbol::from(symbol.name.complete);
+            let symbol_obj = Symbol {
+                name: demangled_symbol.clone(),
+                size: ByteSize::new(symbol.size),
+            };
+
+            crates_map
+                .entry(crate_name)
+                .or_insert_with(|| Crate::new(CrateName::from("")))
+                .symbols
+                .insert(demangled_symbol, symbol_obj);
+        }
+
+        // Process LLVM functions and group by crate
+        for (llvm_fn_name, llvm_fn) in &self.llvm_functions {
+            // Extract crate name from the function path using robust logic
+            let crate_name = {
+                let crate_string = crate_name::extract_crate_from_function(llvm_fn_name);
+                if crate_string == "unknown" {
+                    // Fallback to binary artifact name as main crate
+                    binary.name.clone()
+                } else {
+                    CrateName::from(crate_string)
+                }
+            };
+
+            // Update the LlvmFunction with its proper name
+            let mut llvm_fn_with_name = llvm_fn.clone();
+            llvm_fn_with_name.name = llvm_fn_name.clone();
+
+            crates_map
+                .entry(crate_name)
+                .or_insert_with(|| Crate::new(CrateName::from("")))
+                .llvm_functions
+                .insert(llvm_fn_name.clone(), llvm_fn_with_name);
+        }
+        // Crates we profiled or printed type sizes for may have no code of their own
+        // in the binary (e.g. it was all inlined), keep what we measured anyway
+        for crate_name in self.self_profiles.keys().chain(self.type_layouts.keys()) {
+            if !crates_map.contains_key(crate_name) {
+                debug!(
+                    "No code from {} in {}, adding it for its self-profile or type layouts",
+                    crate_name.blue(),
+                    binary.name.blue()
+                );
+                crates_map.insert(crate_name.clone(), Crate::new(crate_name.clone()));
+            }
+        }
+
+        // Set the proper crate names, populate timing information, and collect into a Vec
+        let mut crates: Vec<Crate> = crates_map
+            .into_iter()
+            .map(|(name, mut crate_obj)| {
+                // Assign the crate name
+                crate_obj.name = name.clone();
+
+                // If we have recorded build timing for this crate, attach it
+                crate_obj.timing_info = self.crate_timings.get(&name).cloned();
+                if let Some(timing) = &crate_obj.timing_info {
+                    crate_obj.frontend_duration = timing.frontend_duration();
+                    crate_obj.codegen_duration = timing.codegen_duration();
+                }
+                crate_obj.self_profile = self.self_profiles.get(&name).cloned();
+                if let Some(layouts) = self.type_layouts.get(&name) {
+                    crate_obj.type_layouts = layouts.clone();
+                }
+
+                crate_obj
+            })
+            .collect();
+
+        // Sort crates by name for consistent output
+        crates.sort_by(|a, b| a.name.cmp(&b.name));
+
+        context.crates = crates;
+
+        Ok(context)
+    }
+}
@@ -0,0 +1,692 @@
+//! Size and build-time budgets, to fail CI when a build grows past a limit.
+//!
+//! Budgets are read from a TOML file, usually `substance.toml` next to the
+//! manifest:
+//!
+//! ```toml
+//! [budget]
+//! file-size = "8 MiB"
+//! text-size = "4 MiB"
+//! crate-size = "1 MiB"        # symbols of any one crate
+//! crate-build-time = "60s"    # any one crate
+//! llvm-copies = 200           # copies of any one LLVM function
+//! crates = 150                # number of crates in the binary
+//!
+//! # Overrides the limits of any one crate, for that crate
+//! [budget.crate.serde_json]
+//! size = "300 KiB"
+//! build-time = "10s"
+//!
+//! # Growth over a baseline snapshot, in percent
+//! [budget.growth]
+//! file-size = 2
+//! text-size = 2
+//! crate-size = 10
+//! ```
+//!
+//! Sizes are a number of bytes, or a string with a unit (`B`, `KB`, `KiB`, `MB`,
+//! `MiB`, `GB`, `GiB`). Durations are a number of seconds, or a string in `ms`,
+//! `s` or `m`. Unknown tables and keys are errors, so that a typo doesn't
+//! silently disable a budget. For the same reason, a `[budget.crate.<name>]`
+//! for a crate that isn't in the binary is a violation.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use camino::Utf8Path;
+
+use crate::errors::SubstanceError;
+use crate::formatting::{format_bytes, format_duration, format_percentage};
+use crate::types::{BuildContext, ByteSize, Crate, CrateName, LlvmFunctionName};
+
+/// Limits a build must stay under, see the [module docs](self)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Budgets {
+    /// Total file size of the binary
+    pub file_size: Option<ByteSize>,
+
+    /// Size of the `.text` section
+    pub text_size: Option<ByteSize>,
+
+    /// Total size of the symbols of any one crate
+    pub crate_size: Option<ByteSize>,
+
+    /// Build time of any one crate
+    pub crate_build_time: Option<Duration>,
+
+    /// Limits for specific crates, replacing `crate_size` and `crate_build_time`
+    pub crates: BTreeMap<CrateName, CrateBudget>,
+
+    /// Copies of any one LLVM function, as counted by
+    /// [`BuildContext::all_llvm_functions`]
+    pub llvm_copies: Option<usize>,
+
+    /// Number of crates in the binary
+    pub crate_count: Option<usize>,
+
+    /// Growth over a baseline, checked when one is given to
+    /// [`Budgets::evaluate`]
+    pub growth: GrowthBudgets,
+}
+
+/// Limits for one crate
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CrateBudget {
+    pub size: Option<ByteSize>,
+    pub build_time: Option<Duration>,
+}
+
+/// Growth allowed over a baseline, in percent
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GrowthBudgets {
+    pub file_size: Option<f64>,
+    pub text_size: Option<f64>,
+
+    /// Growth of any one crate that's in both builds
+    pub crate_size: Option<f64>,
+}
+
+/// A budget that was exceeded
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+    FileSize {
+        size: ByteSize,
+        limit: ByteSize,
+    },
+    TextSize {
+        size: ByteSize,
+        limit: ByteSize,
+    },
+    CrateSize {
+        crate_name: CrateName,
+        size: ByteSize,
+        limit: ByteSize,
+    },
+    CrateBuildTime {
+        crate_name: CrateName,
+        build_time: Duration,
+        limit: Duration,
+    },
+    /// A crate has a budget but isn't in the binary, e.g. because of a typo or
+    /// because the dependency was removed
+    UnknownCrate {
+        crate_name: CrateName,
+    },
+    LlvmCopies {
+        function: LlvmFunctionName,
+        copies: usize,
+        limit: usize,
+    },
+    CrateCount {
+        count: usize,
+        limit: usize,
+    },
+    /// Growth over the baseline. `crate_name` is `None` for the whole binary.
+    Growth {
+        metric: GrowthMetric,
+        crate_name: Option<CrateName>,
+        before: ByteSize,
+        after: ByteSize,
+        limit: f64,
+    },
+}
+
+/// What a [`Violation::Growth`] is about
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GrowthMetric {
+    FileSize,
+    TextSize,
+    CrateSize,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let over = |size: &ByteSize, limit: &ByteSize| {
+            format!(
+                "{} (budget {}, over by {})",
+                format_bytes(size.value()),
+                format_bytes(limit.value()),
+                format_bytes(size.value().saturating_sub(limit.value()))
+            )
+        };
+        match self {
+            Violation::FileSize { size, limit } => write!(f, "file size is {}", over(size, limit)),
+            Violation::TextSize { size, limit } => {
+                write!(f, ".text size is {}", over(size, limit))
+            }
+            Violation::CrateSize {
+                crate_name,
+                size,
+                limit,
+            } => write!(f, "crate {crate_name} is {}", over(size, limit)),
+            Violation::CrateBuildTime {
+                crate_name,
+                build_time,
+                limit,
+            } => write!(
+                f,
+                "crate {crate_name} takes {} to build (budget {})",
+                format_duration(build_time),
+                format_duration(limit)
+            ),
+            Violation::UnknownCrate { crate_name } => {
+                write!(f, "crate {crate_name} has a budget but isn't in the binary")
+            }
+            Violation::LlvmCopies {
+                function,
+                copies,
+                limit,
+            } => write!(
+                f,
+                "LLVM function {function} has {copies} copies (budget {limit})"
+            ),
+            Violation::CrateCount { count, limit } => {
+                write!(f, "the binary has {count} crates (budget {limit})")
+            }
+            Violation::Growth {
+                metric,
+                crate_name,
+                before,
+                after,
+                limit,
+            } => {
+                let what = match (metric, crate_name) {
+                    (GrowthMetric::FileSize, _) => "file size".to_string(),
+                    (GrowthMetric::TextSize, _) => ".text size".to_string(),
+                    (GrowthMetric::CrateSize, Some(name)) => format!("crate {name}"),
+                    (GrowthMetric::CrateSize, None) => "crate".to_string(),
+                };
+                write!(
+                    f,
+                    "{what} grew by {} from {} to {} (budget {})",
+                    format_percentage(growth(before.value(), after.value())),
+                    format_bytes(before.value()),
+                    format_bytes(after.value()),
+                    format_percentage(*limit)
+                )
+            }
+        }
+    }
+}
+
+/// Result of [`Budgets::evaluate`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BudgetReport {
+    /// In the order of the [`Budgets`] fields, then largest first
+    pub violations: Vec<Violation>,
+}
+
+impl BudgetReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Process exit code for CI: `0` when every budget is met, `1` otherwise
+    pub fn exit_code(&self) -> i32 {
+        if self.is_ok() {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+impl Budgets {
+    /// Reads budgets from a file, see the [module docs](self) for the format
+    pub fn load(path: impl AsRef<Utf8Path>) -> Result<Self, SubstanceError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|_| SubstanceError::OpenFailed(path.to_owned()))?;
+        Self::parse(&contents)
+            .map_err(|reason| SubstanceError::InvalidBudgets(path.to_owned(), reason))
+    }
+
+    /// Parses budgets from the contents of a file. Errors name the offending
+    /// key.
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let document: toml::Table = contents
+            .parse()
+            .map_err(|e: toml::de::Error| e.to_string())?;
+        let mut budgets = Self::default();
+
+        for (name, item) in &document {
+            let budget = match item {
+                toml::Value::Table(budget) if name == "budget" => budget,
+                toml::Value::Table(_) => return Err(format!("unknown table [{name}]")),
+                _ => return Err(format!("{name} is outside of a [budget] table")),
+            };
+            for (key, value) in budget {
+                match (key.as_str(), value) {
+                    ("growth", toml::Value::Table(growth)) => {
+                        for (key, value) in growth {
+                            budgets.set("budget.growth", key, value)?;
+                        }
+                    }
+                    ("crate", toml::Value::Table(crates)) => {
+                        for (name, budget) in crates {
+                            let table = format!("budget.crate.{name}");
+                            let budget = budget
+                                .as_table()
+                                .ok_or_else(|| format!("[{table}] should be a table"))?;
+                            for (key, value) in budget {
+                                budgets.set(&table, key, value)?;
+                            }
+                        }
+                    }
+                    _ => budgets.set("budget", key, value)?,
+                }
+            }
+        }
+
+        Ok(budgets)
+    }
+
+    fn set(&mut self, table: &str, key: &str, value: &toml::Value) -> Result<(), String> {
+        let unknown = || format!("unknown key {key} in [{table}]");
+        let value = match value {
+            toml::Value::String(value) => value.clone(),
+            toml::Value::Integer(value) => value.to_string(),
+            toml::Value::Float(value) => value.to_string(),
+            _ => return Err(format!("{key} in [{table}] should be a number or a string")),
+        };
+        let value = value.as_str();
+        match table {
+            "budget" => match key {
+                "file-size" => self.file_size = Some(parse_size(value)?),
+                "text-size" => self.text_size = Some(parse_size(value)?),
+                "crate-size" => self.crate_size = Some(parse_size(value)?),
+                "crate-build-time" => self.crate_build_time = Some(parse_duration(value)?),
+                "llvm-copies" => self.llvm_copies = Some(parse_count(value)?),
+                "crates" => self.crate_count = Some(parse_count(value)?),
+                _ => return Err(unknown()),
+            },
+            "budget.growth" => {
+                let percent = parse_percent(value)?;
+                match key {
+                    "file-size" => self.growth.file_size = Some(percent),
+                    "text-size" => self.growth.text_size = Some(percent),
+                    "crate-size" => self.growth.crate_size = Some(percent),
+                    _ => return Err(unknown()),
+                }
+            }
+            _ => {
+                // Crates are named with underscores in the binary, whatever
+                // their package is called
+                let name = table.trim_start_matches("budget.crate.").replace('-', "_");
+                let budget = self.crates.entry(CrateName::from(name)).or_default();
+                match key {
+                    "size" => budget.size = Some(parse_size(value)?),
+                    "build-time" => budget.build_time = Some(parse_duration(value)?),
+                    _ => return Err(unknown()),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks `context` against every budget, and its growth over `baseline`
+    /// if there is one
+    pub fn evaluate(
+        &self,
+        context: &BuildContext,
+        baseline: Option<&BuildContext>,
+    ) -> BudgetReport {
+        let mut violations = Vec::new();
+
+        if let Some(limit) = self.file_size.filter(|l| context.file_size > *l) {
+            violations.push(Violation::FileSize {
+                size: context.file_size,
+                limit,
+            });
+        }
+        if let Some(limit) = self.text_size.filter(|l| context.text_size > *l) {
+            violations.push(Violation::TextSize {
+                size: context.text_size,
+                limit,
+            });
+        }
+
+        let mut crate_sizes = Vec::new();
+        let mut build_times = Vec::new();
+        for krate in &context.crates {
+            let budget = self.crates.get(&krate.name);
+            let size_limit = budget.and_then(|b| b.size).or(self.crate_size);
+            let size = crate_size(krate);
+            if let Some(limit) = size_limit.filter(|l| size > *l) {
+                crate_sizes.push((&krate.name, size, limit));
+            }
+
+            let time_limit = budget.and_then(|b| b.build_time).or(self.crate_build_time);
+            let build_time = krate
+                .timing_info
+                .as_ref()
+                .map(|t| Duration::from_secs_f64(t.duration));
+            if let (Some(limit), Some(build_time)) = (time_limit, build_time) {
+                if build_time > limit {
+                    build_times.push((&krate.name, build_time, limit));
+                }
+            }
+        }
+        crate_sizes.sort_by(|a, b| b.1.value().cmp(&a.1.value()).then_with(|| a.0.cmp(b.0)));
+        build_times.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        violations.extend(crate_sizes.into_iter().map(|(name, size, limit)| {
+            Violation::CrateSize {
+                crate_name: name.clone(),
+                size,
+                limit,
+            }
+        }));
+        violations.extend(build_times.into_iter().map(|(name, build_time, limit)| {
+            Violation::CrateBuildTime {
+                crate_name: name.clone(),
+                build_time,
+                limit,
+            }
+        }));
+
+        violations.extend(
+            self.crates
+                .keys()
+                .filter(|name| !context.crates.iter().any(|c| &c.name == *name))
+                .map(|name| Violation::UnknownCrate {
+                    crate_name: name.clone(),
+                }),
+        );
+
+        if let Some(limit) = self.llvm_copies {
+            let mut functions: Vec<_> = context
+                .all_llvm_functions()
+                .into_values()
+                .filter(|f| f.copies.value() > limit)
+                .collect();
+            functions.sort_by(|a, b| {
+                b.copies
+                    .value()
+                    .cmp(&a.copies.value())
+                    .then_with(|| a.name.cmp(&b.name))
+            });
+            violations.extend(functions.into_iter().map(|f| Violation::LlvmCopies {
+                function: f.name,
+                copies: f.copies.value(),
+                limit,
+            }));
+        }
+
+        if let Some(limit) = self.crate_count.filter(|l| context.crates.len() > *l) {
+            violations.push(Violation::CrateCount {
+                count: context.crates.len(),
+                limit,
+            });
+        }
+
+        if let Some(baseline) = baseline {
+            violations.extend(self.growth.evaluate(baseline, context));
+        }
+
+        BudgetReport { violations }
+    }
+}
+
+impl GrowthBudgets {
+    fn evaluate(&self, before: &BuildContext, after: &BuildContext) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let mut check = |metric,
+                         crate_name: Option<&CrateName>,
+                         limit: Option<f64>,
+                         before: ByteSize,
+                         after: ByteSize| {
+            if let Some(limit) = limit {
+                if growth(before.value(), after.value()) > limit {
+                    violations.push(Violation::Growth {
+                        metric,
+                        crate_name: crate_name.cloned(),
+                        before,
+                        after,
+                        limit,
+                    });
+                }
+            }
+        };
+
+        check(
+            GrowthMetric::FileSize,
+            None,
+            self.file_size,
+            before.file_size,
+            after.file_size,
+        );
+        check(
+            GrowthMetric::TextSize,
+            None,
+            self.text_size,
+            before.text_size,
+            after.text_size,
+        );
+        for krate in &after.crates {
+            if let Some(old) = before.crates.iter().find(|c| c.name == krate.name) {
+                check(
+                    GrowthMetric::CrateSize,
+                    Some(&krate.name),
+                    self.crate_size,
+                    crate_size(old),
+                    crate_size(krate),
+                );
+            }
+        }
+        violations
+    }
+}
+
+/// Growth from `before` to `after`, in percent. Anything is infinite growth
+/// over nothing.
+fn growth(before: u64, after: u64) -> f64 {
+    if before == 0 {
+        if after == 0 {
+            0.0
+        } else {
+            f64::INFINITY
+        }
+    } else {
+        (after as f64 - before as f64) / before as f64 * 100.0
+    }
+}
+
+fn crate_size(krate: &Crate) -> ByteSize {
+    ByteSize::new(krate.symbols.values().map(|s| s.size.value()).sum::<u64>())
+}
+
+/// `1048576`, `"1 MiB"`, `"1.5MB"`, ...
+fn parse_size(value: &str) -> Result<ByteSize, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '_'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .replace('_', "")
+        .parse()
+        .map_err(|_| format!("invalid size {value:?}"))?;
+    let multiplier: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "KB" | "kB" => 1_000,
+        "KiB" => 1 << 10,
+        "MB" => 1_000_000,
+        "MiB" => 1 << 20,
+        "GB" => 1_000_000_000,
+        "GiB" => 1 << 30,
+        other => return Err(format!("unknown size unit {other:?} in {value:?}")),
+    };
+    Ok(ByteSize::new((number * multiplier as f64).round() as u64))
+}
+
+/// `30` (seconds), `"30s"`, `"500ms"`, `"2m"`
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration {value:?}"))?;
+    let seconds = match unit.trim() {
+        "" | "s" => number,
+        "ms" => number / 1000.0,
+        "m" | "min" => number * 60.0,
+        other => return Err(format!("unknown duration unit {other:?} in {value:?}")),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("duration {value:?} is too long"))
+}
+
+fn parse_count(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    value
+        .replace('_', "")
+        .parse()
+        .map_err(|_| format!("invalid count {value:?}"))
+}
+
+fn parse_percent(value: &str) -> Result<f64, String> {
+    let value = value.trim();
+    value
+        .trim_end_matches('%')
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid percentage {value:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let budgets = Budgets::parse(
+            r#"
+[budget]
+file-size = "2 MiB" # whole binary
+text-size = 1_000_000
+crate-build-time = "1.5s"
+crates = 3
+
+[budget.crate."serde-json"]
+size = "10 KB"
+
+[budget.growth]
+file-size = "2.5%"
+"#,
+        )
+        .unwrap();
+        assert_eq!(budgets.file_size, Some(ByteSize::new(2u64 << 20)));
+        assert_eq!(budgets.text_size, Some(ByteSize::new(1_000_000u64)));
+        assert_eq!(budgets.crate_build_time, Some(Duration::from_millis(1500)));
+        assert_eq!(budgets.crate_count, Some(3));
+        assert_eq!(
+            budgets.crates[&CrateName::from("serde_json")].size,
+            Some(ByteSize::new(10_000u64))
+        );
+        assert_eq!(budgets.growth.file_size, Some(2.5));
+
+        assert_eq!(
+            Budgets::parse("[budget]\nfile_size = 3").unwrap_err(),
+            "unknown key file_size in [budget]"
+        );
+        assert!(Budgets::parse("[budget]\nfile-size = \"3 XB\"").is_err());
+        assert!(Budgets::parse("[budgets]").is_err());
+        assert!(Budgets::parse(include_str!("../analysis-target/substance.toml")).is_ok());
+        assert!(Budgets::parse("[budget\nfile-size = 3").is_err());
+
+        let inline =
+            Budgets::parse("budget = { crate = { serde = { size = \"1 KB\" } } }").unwrap();
+        assert_eq!(
+            inline.crates[&CrateName::from("serde")].size,
+            Some(ByteSize::new(1_000u64))
+        );
+        assert!(
+            Budgets::parse(&format!("[budget]\ncrate-build-time = {}", "9".repeat(400))).is_err()
+        );
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let budgets = Budgets {
+            file_size: Some(ByteSize::new(1000u64)),
+            crate_size: Some(ByteSize::new(100u64)),
+            crates: BTreeMap::from([(
+                CrateName::from("big"),
+                CrateBudget {
+                    size: Some(ByteSize::new(500u64)),
+                    build_time: None,
+                },
+            )]),
+            crate_count: Some(3),
+            growth: GrowthBudgets {
+                crate_size: Some(10.0),
+                ..GrowthBudgets::default()
+            },
+            ..Budgets::default()
+        };
+        let krate =
+            |name: &str, size| Crate::named(name).with_symbol(&format!("{}::f", name), size);
+        let baseline = BuildContext::for_test(900, vec![krate("app", 50), krate("big", 400)]);
+        let current = BuildContext::for_test(
+            1200,
+            vec![krate("app", 150), krate("big", 420), krate("small", 10)],
+        );
+
+        assert!(budgets.evaluate(&baseline, None).is_ok());
+
+        let report = budgets.evaluate(&current, Some(&baseline));
+        assert_eq!(report.exit_code(), 1);
+        assert_eq!(
+            report.violations,
+            [
+                Violation::FileSize {
+                    size: ByteSize::new(1200u64),
+                    limit: ByteSize::new(1000u64),
+                },
+                Violation::CrateSize {
+                    crate_name: CrateName::from("app"),
+                    size: ByteSize::new(150u64),
+                    limit: ByteSize::new(100u64),
+                },
+                Violation::Growth {
+                    metric: GrowthMetric::CrateSize,
+                    crate_name: Some(CrateName::from("app")),
+                    before: ByteSize::new(50u64),
+                    after: ByteSize::new(150u64),
+                    limit: 10.0,
+                },
+            ]
+        );
+        assert_eq!(
+            report.violations[1].to_string(),
+            "crate app is 150 B (budget 100 B, over by 50 B)"
+        );
+        let under = Violation::FileSize {
+            size: ByteSize::new(10u64),
+            limit: ByteSize::new(20u64),
+        };
+        assert_eq!(
+            under.to_string(),
+            "file size is 10 B (budget 20 B, over by 0 B)"
+        );
+
+        let typo = Budgets {
+            crates: BTreeMap::from([(CrateName::from("bgi"), CrateBudget::default())]),
+            ..Budgets::default()
+        };
+        let report = typo.evaluate(&baseline, None);
+        assert_eq!(
+            report.violations,
+            [Violation::UnknownCrate {
+                crate_name: CrateName::from("bgi"),
+            }]
+        );
+        assert_eq!(
+            report.violations[0].to_string(),
+            "crate bgi has a budget but isn't in the binary"
+        );
+    }
+}
@@ -1,7 +1,9 @@
+use std::time::Duration;
+
 use camino::Utf8PathBuf;
 use facet::Facet;
 
-use crate::types::CrateName;
+use crate::types::{Artifact, ArtifactKind, CrateName};
 
 // Cargo JSON metadata structures
 #[derive(Debug, Facet)]
@@ -13,10 +15,30 @@ struct RawCargoMessage {
     #[facet(default)]
     target: Option<CargoTarget>,
 
+    /// opaque identifier of the package the message is for
+    #[facet(default)]
+    package_id: Option<String>,
+
+    /// path to the `Cargo.toml` of the package the message is for
+    #[facet(default)]
+    manifest_path: Option<String>,
+
+    /// compiler-artifact only
+    #[facet(default)]
+    features: Option<Vec<String>>,
+
     /// compiler-artifact only
     #[facet(default)]
     filenames: Option<Vec<String>>,
 
+    /// compiler-artifact only, set for binaries
+    #[facet(default)]
+    executable: Option<String>,
+
+    /// timing-info only, e.g. "build" or "run-custom-build"
+    #[facet(default)]
+    mode: Option<String>,
+
     /// timing-info only
     #[facet(default)]
     duration: Option<f64>,
@@ -24,9 +46,35 @@ struct RawCargoMessage {
     /// timing-info only
     #[facet(default)]
     rmeta_time: Option<f64>,
+
+    /// compiler-message only
+    #[facet(default)]
+    message: Option<RawDiagnostic>,
 }
 
+/// A diagnostic as emitted by rustc with `--error-format=json`
 #[derive(Debug, Facet)]
+struct RawDiagnostic {
+    message: String,
+
+    /// "error", "warning", "note", etc.
+    level: String,
+
+    #[facet(default)]
+    code: Option<RawDiagnosticCode>,
+
+    /// the diagnostic as rustc would have printed it
+    #[facet(default)]
+    rendered: Option<String>,
+}
+
+#[derive(Debug, Facet)]
+struct RawDiagnosticCode {
+    /// e.g. "E0308" or "unused_variables"
+    code: String,
+}
+
+#[derive(Clone, Debug, Facet)]
 pub struct CargoTarget {
     /// The name of the build target, something like: "static_assertions", "proc_macro2", etc.
     pub name: Option<String>,
@@ -39,29 +87,206 @@ pub struct CargoTarget {
 }
 
 // Timing structures for build analysis
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct TimingInfo {
+    /// Cargo's package ID, e.g. `registry+https://github.com/rust-lang/crates.io-index#syn@2.0.101`
+    pub package_id: Option<String>,
+
     // cf. [`CargoMessage`]
     pub target: CargoTarget,
 
+    /// What cargo did with the target: `build` (compile it), `run-custom-build`
+    /// (run a build script), etc.
+    pub mode: Option<String>,
+
     // cf. [`CargoMessage`]
     pub duration: f64,
 
     // cf. [`CargoMessage`]
     pub rmeta_time: Option<f64>,
+
+    /// When we received the message, relative to the start of the build. Cargo
+    /// sends it as soon as the unit is done.
+    pub finished_at: Option<Duration>,
+}
+
+impl TimingInfo {
+    /// When the unit started, relative to the start of the build. Cargo doesn't
+    /// tell us when units start, so this is when the message arrived minus the
+    /// unit's duration.
+    pub fn started_at(&self) -> Option<Duration> {
+        self.finished_at
+            .map(|end| end.saturating_sub(Duration::from_secs_f64(self.duration)))
+    }
+
+    /// Time spent before metadata was ready (parsing, type checking, borrow
+    /// checking...), after which dependents can start with pipelining. `None` for
+    /// units that don't emit metadata, like binaries and build script runs.
+    pub fn frontend_duration(&self) -> Option<Duration> {
+        self.rmeta_time.map(Duration::from_secs_f64)
+    }
+
+    /// Time spent after metadata was ready, mostly codegen and LLVM. Nothing
+    /// downstream waits on it, except for linking.
+    pub fn codegen_duration(&self) -> Option<Duration> {
+        let rmeta_time = self.rmeta_time?;
+        Some(Duration::from_secs_f64(
+            (self.duration - rmeta_time).max(0.0),
+        ))
+    }
+
+    /// Whether the unit was compiled for, or run on, the host: build scripts and
+    /// proc macros. Their dependencies are host units too, but timing messages
+    /// don't tell them apart from target units. The unit graph does when
+    /// cross-compiling, see [`UnitGraph::partition_timings`].
+    ///
+    /// [`UnitGraph::partition_timings`]: crate::critical_path::UnitGraph::partition_timings
+    pub fn is_host_unit(&self) -> bool {
+        self.mode.as_deref() == Some("run-custom-build")
+            || self
+                .target
+                .kind
+                .iter()
+                .flatten()
+                .any(|k| k == "custom-build" || k == "proc-macro")
+    }
 }
 
 pub struct CompilerArtifact {
     // cf. [`CargoMessage`]
     pub crate_name: CrateName,
 
+    /// Target kinds, e.g. `["bin"]`, `["rlib", "cdylib"]`, `["proc-macro"]`
+    pub target_kind: Vec<String>,
+
+    /// Crate types, e.g. `["bin"]`, `["rlib", "cdylib"]`
+    pub crate_types: Vec<String>,
+
     // cf. [`CargoMessage`]
     pub filenames: Vec<Utf8PathBuf>,
+
+    /// Path to the executable, for binaries
+    pub executable: Option<Utf8PathBuf>,
+
+    /// Whether this is a std crate (or one of its dependencies) built from
+    /// source with `-Zbuild-std`
+    pub build_std: bool,
 }
 
-pub(crate) enum CargoMessage {
+/// Detects units built by `-Zbuild-std`: crates from the `rust-src` component,
+/// and crates.io dependencies of std, which are built with the `rustc-dep-of-std` feature.
+fn is_build_std_unit(manifest_path: Option<&str>, features: &[String]) -> bool {
+    let from_rust_src = manifest_path.is_some_and(|path| {
+        path.replace('\\', "/")
+            .contains("/lib/rustlib/src/rust/library/")
+    });
+    from_rust_src || features.iter().any(|f| f == "rustc-dep-of-std")
+}
+
+impl CompilerArtifact {
+    /// Classifies every file of this artifact. Files we have no use for
+    /// (`.rmeta`, `.d`, `.pdb`, import libraries etc.) are left out.
+    pub fn artifacts(&self) -> Vec<Artifact> {
+        let has = |kind: &str| {
+            self.target_kind.iter().any(|k| k == kind) || self.crate_types.iter().any(|k| k == kind)
+        };
+
+        let mut artifacts = Vec::new();
+        for path in &self.filenames {
+            let ext = path.extension().unwrap_or_default();
+            let is_dylib_ext = matches!(ext, "so" | "dylib" | "dll");
+
+            let kind = if has("custom-build") {
+                // build scripts are compiled to executables, but never linked into anything
+                (Some(path) == self.executable.as_ref()).then_some(ArtifactKind::BuildScript)
+            } else if has("proc-macro") {
+                is_dylib_ext.then_some(ArtifactKind::ProcMacro)
+            } else if ext == "rlib" {
+                Some(ArtifactKind::Library)
+            } else if has("cdylib") && is_dylib_ext {
+                Some(ArtifactKind::CDylib)
+            } else if has("dylib") && is_dylib_ext {
+                Some(ArtifactKind::DynLib)
+            } else if has("bin") {
+                // On Windows, binaries come with a `.pdb`: only keep the executable itself
+                match &self.executable {
+                    Some(executable) => (path == executable).then_some(ArtifactKind::Binary),
+                    None => (ext.is_empty() || ext == "exe").then_some(ArtifactKind::Binary),
+                }
+            } else {
+                None
+            };
+
+            if let Some(kind) = kind {
+                artifacts.push(Artifact {
+                    kind,
+                    name: self.crate_name.clone(),
+                    path: path.clone(),
+                });
+            }
+        }
+        artifacts
+    }
+}
+
+/// Severity of a compiler diagnostic
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiagnosticLevel {
+    Error,
+    Warning,
+    Note,
+    Help,
+    FailureNote,
+    /// An internal compiler error
+    Ice,
+    Other(String),
+}
+
+impl DiagnosticLevel {
+    fn parse(level: &str) -> Self {
+        match level {
+            "error" => Self::Error,
+            "warning" => Self::Warning,
+            "note" => Self::Note,
+            "help" => Self::Help,
+            "failure-note" => Self::FailureNote,
+            "error: internal compiler error" => Self::Ice,
+            other => Self::Other(other.to_string()),
+        }
+    }
+
+    /// Whether this diagnostic fails the build
+    pub fn is_error(&self) -> bool {
+        matches!(self, Self::Error | Self::Ice)
+    }
+}
+
+/// A warning or error emitted by rustc while building a crate
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    /// Crate being compiled, if cargo told us
+    pub crate_name: Option<CrateName>,
+
+    /// Cargo's package ID, e.g. `path+file:///app#0.1.0`
+    pub package_id: Option<String>,
+
+    pub level: DiagnosticLevel,
+
+    /// Error code or lint name, e.g. `E0308` or `unused_variables`
+    pub code: Option<String>,
+
+    /// The main message, without spans or children
+    pub message: String,
+
+    /// The full diagnostic as rustc would have printed it
+    pub rendered: Option<String>,
+}
+
+/// A JSON message from `cargo build --message-format=json` that we care about
+pub enum CargoMessage {
     TimingInfo(TimingInfo),
     CompilerArtifact(CompilerArtifact),
+    CompilerMessage(Diagnostic),
 }
 
 use std::fmt;
@@ -73,6 +298,7 @@ pub enum CargoMessageError {
     MissingDuration,
     MissingCrateName,
     MissingFilenames,
+    MissingMessage,
     InvalidJson(String),
 }
 
@@ -85,6 +311,7 @@ impl fmt::Display for CargoMessageError {
             MissingDuration => write!(f, "Missing duration for timing-info"),
             MissingCrateName => write!(f, "Missing crate name for compiler-artifact"),
             MissingFilenames => write!(f, "Missing filenames for compiler-artifact"),
+            MissingMessage => write!(f, "Missing message for compiler-message"),
             InvalidJson(err) => write!(f, "Failed to parse cargo message JSON: {err}"),
         }
     }
@@ -107,9 +334,12 @@ impl CargoMessage {
                 })?;
                 let duration = raw.duration.ok_or(CargoMessageError::MissingDuration)?;
                 Ok(Some(CargoMessage::TimingInfo(TimingInfo {
+                    package_id: raw.package_id,
                     target,
+                    mode: raw.mode,
                     duration,
                     rmeta_time: raw.rmeta_time,
+                    finished_at: None,
                 })))
             }
             "compiler-artifact" => {
@@ -127,9 +357,17 @@ impl CargoMessage {
                     .into_iter()
                     .map(Utf8PathBuf::from)
                     .collect();
+                let build_std = is_build_std_unit(
+                    raw.manifest_path.as_deref(),
+                    raw.features.as_deref().unwrap_or_default(),
+                );
                 Ok(Some(CargoMessage::CompilerArtifact(CompilerArtifact {
                     crate_name,
+                    target_kind: target.kind.unwrap_or_default(),
+                    crate_types: target.crate_types.unwrap_or_default(),
                     filenames,
+                    executable: raw.executable.map(Utf8PathBuf::from),
+                    build_std,
                 })))
             }
             "build-script-executed" => {
@@ -137,8 +375,15 @@ impl CargoMessage {
                 Ok(None)
             }
             "compiler-message" => {
-                // ignore
-                Ok(None)
+                let message = raw.message.ok_or(CargoMessageError::MissingMessage)?;
+                Ok(Some(CargoMessage::CompilerMessage(Diagnostic {
+                    crate_name: raw.target.and_then(|t| t.name).map(CrateName::from),
+                    package_id: raw.package_id,
+                    level: DiagnosticLevel::parse(&message.level),
+                    code: message.code.map(|c| c.code),
+                    message: message.message,
+                    rendered: message.rendered,
+                })))
             }
             "build-finished" => {
                 // ignore
@@ -148,3 +393,192 @@ impl CargoMessage {
         }
     }
 }
+
+/// Removes ANSI escape sequences (colors, hyperlinks) from cargo's output
+pub(crate) fn strip_ansi_escapes(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters, then a final byte in `@`..=`~`
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC: ends with BEL or ST (`ESC \`)
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\u{7}' {
+                        break;
+                    }
+                    if c == '\u{1b}' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn artifact(kind: &[&str], filenames: &[&str], executable: Option<&str>) -> CompilerArtifact {
+        CompilerArtifact {
+            crate_name: CrateName::from("p"),
+            target_kind: kind.iter().map(|s| s.to_string()).collect(),
+            crate_types: kind.iter().map(|s| s.to_string()).collect(),
+            filenames: filenames.iter().map(Utf8PathBuf::from).collect(),
+            executable: executable.map(Utf8PathBuf::from),
+            build_std: false,
+        }
+    }
+
+    fn kinds(artifact: &CompilerArtifact) -> Vec<(ArtifactKind, String)> {
+        artifact
+            .artifacts()
+            .into_iter()
+            .map(|a| (a.kind, a.path.file_name().unwrap().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rlib_and_cdylib_in_one_message() {
+        let a = artifact(
+            &["rlib", "cdylib"],
+            &["/t/debug/libp.rlib", "/t/debug/libp.so"],
+            None,
+        );
+        assert_eq!(
+            kinds(&a),
+            vec![
+                (ArtifactKind::Library, "libp.rlib".to_string()),
+                (ArtifactKind::CDylib, "libp.so".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_windows_binary_ignores_pdb() {
+        let a = artifact(
+            &["bin"],
+            &["C:/t/debug/p.exe", "C:/t/debug/p.pdb"],
+            Some("C:/t/debug/p.exe"),
+        );
+        assert_eq!(kinds(&a), vec![(ArtifactKind::Binary, "p.exe".to_string())]);
+    }
+
+    #[test]
+    fn test_proc_macro_and_rmeta() {
+        let a = artifact(&["proc-macro"], &["/t/debug/deps/libp-1234.so"], None);
+        assert_eq!(
+            kinds(&a),
+            vec![(ArtifactKind::ProcMacro, "libp-1234.so".to_string())]
+        );
+
+        let a = artifact(
+            &["lib"],
+            &[
+                "/t/debug/deps/libp-1234.rlib",
+                "/t/debug/deps/libp-1234.rmeta",
+            ],
+            None,
+        );
+        assert_eq!(
+            kinds(&a),
+            vec![(ArtifactKind::Library, "libp-1234.rlib".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_is_build_std_unit() {
+        assert!(is_build_std_unit(
+            Some("/home/me/.rustup/toolchains/nightly-x86_64-unknown-linux-gnu/lib/rustlib/src/rust/library/core/Cargo.toml"),
+            &[],
+        ));
+        assert!(is_build_std_unit(
+            Some(
+                r"C:\Users\me\.rustup\toolchains\nightly-x86_64-pc-windows-msvc\lib\rustlib\src\rust\library\alloc\Cargo.toml"
+            ),
+            &[],
+        ));
+        assert!(is_build_std_unit(
+            Some("/home/me/.cargo/registry/src/index.crates.io-6f17d22bba15001f/hashbrown-0.15.2/Cargo.toml"),
+            &["rustc-dep-of-std".to_string(), "alloc".to_string()],
+        ));
+        assert!(!is_build_std_unit(
+            Some("/home/me/.cargo/registry/src/index.crates.io-6f17d22bba15001f/hashbrown-0.15.2/Cargo.toml"),
+            &["default".to_string()],
+        ));
+        assert!(!is_build_std_unit(None, &[]));
+    }
+
+    #[test]
+    fn test_build_script() {
+        let a = artifact(
+            &["custom-build"],
+            &["/t/debug/build/p-1234/build-script-build"],
+            Some("/t/debug/build/p-1234/build-script-build"),
+        );
+        assert_eq!(
+            kinds(&a),
+            vec![(ArtifactKind::BuildScript, "build-script-build".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_level() {
+        assert_eq!(DiagnosticLevel::parse("warning"), DiagnosticLevel::Warning);
+        assert!(DiagnosticLevel::parse("error").is_error());
+        assert!(DiagnosticLevel::parse("error: internal compiler error").is_error());
+        assert!(!DiagnosticLevel::parse("failure-note").is_error());
+        assert_eq!(
+            DiagnosticLevel::parse("cursed"),
+            DiagnosticLevel::Other("cursed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_timing_host_units() {
+        let timing = |kind: &str, mode: &str| TimingInfo {
+            package_id: None,
+            target: CargoTarget {
+                name: Some("p".to_string()),
+                kind: Some(vec![kind.to_string()]),
+                crate_types: Some(vec![kind.to_string()]),
+            },
+            mode: Some(mode.to_string()),
+            duration: 1.0,
+            rmeta_time: Some(0.5),
+            finished_at: None,
+        };
+        assert!(!timing("lib", "build").is_host_unit());
+        assert!(!timing("bin", "build").is_host_unit());
+        assert!(timing("proc-macro", "build").is_host_unit());
+        assert!(timing("custom-build", "build").is_host_unit());
+        assert!(timing("custom-build", "run-custom-build").is_host_unit());
+    }
+
+    #[test]
+    fn test_strip_ansi_escapes() {
+        assert_eq!(
+            strip_ansi_escapes("\u{1b}[1m\u{1b}[32m   Compiling\u{1b}[0m app v0.1.0"),
+            "   Compiling app v0.1.0"
+        );
+        assert_eq!(
+            strip_ansi_escapes("\u{1b}]8;;file:///app\u{1b}\\app\u{1b}]8;;\u{7} [1;2]"),
+            "app [1;2]"
+        );
+    }
+}
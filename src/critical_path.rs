@@ -0,0 +1,543 @@
+//! Critical path through a build.
+//!
+//! Cargo's unit graph says which units (a target of a package, compiled or run
+//! once) wait on which, and timing messages say how long each unit took. The
+//! longest chain of waits bounds the wall time of the build, however many cores
+//! are available: speeding up anything off that chain doesn't help.
+//!
+//! Pipelining is taken into account: a library only has to reach the point where
+//! its metadata is ready before dependents that don't link it can start. Units
+//! that link wait for the libraries they depend on, directly or not, to be done.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use facet::Facet;
+
+use crate::cargo::{CargoTarget, TimingInfo};
+
+#[derive(Debug, Facet)]
+struct RawUnitGraph {
+    units: Vec<RawUnit>,
+}
+
+#[derive(Debug, Facet)]
+struct RawUnit {
+    pkg_id: String,
+    target: CargoTarget,
+    mode: String,
+    #[facet(default)]
+    platform: Option<String>,
+    dependencies: Vec<RawUnitDependency>,
+}
+
+#[derive(Debug, Facet)]
+struct RawUnitDependency {
+    index: usize,
+}
+
+/// A node of cargo's unit graph
+#[derive(Clone, Debug)]
+pub struct Unit {
+    /// Cargo's package ID, in the same format as [`TimingInfo::package_id`]
+    pub package_id: String,
+
+    pub target: CargoTarget,
+
+    /// `build`, `run-custom-build`, etc.
+    pub mode: String,
+
+    /// Triple the unit is built for, `None` for the host. Without `--target`,
+    /// cargo reports `None` for every unit.
+    pub platform: Option<String>,
+
+    /// Indices of the units this one waits on
+    pub dependencies: Vec<usize>,
+}
+
+impl Unit {
+    /// Whether the unit needs its dependencies fully built, rather than just their
+    /// metadata: anything that links, and build script runs.
+    fn needs_full_dependencies(&self) -> bool {
+        self.mode == "run-custom-build"
+            || self.target.kind.iter().flatten().any(|k| {
+                matches!(
+                    k.as_str(),
+                    "bin"
+                        | "test"
+                        | "bench"
+                        | "example"
+                        | "cdylib"
+                        | "dylib"
+                        | "staticlib"
+                        | "proc-macro"
+                        | "custom-build"
+                )
+            })
+    }
+
+    fn key(&self) -> UnitKey {
+        unit_key(&self.package_id, &self.target, &self.mode)
+    }
+
+    /// Whether the unit is built for, or run on, the host. When cross-compiling,
+    /// that's every unit without a platform, otherwise only build scripts and proc
+    /// macros can be told apart.
+    fn is_host(&self, cross_compiling: bool) -> bool {
+        if cross_compiling {
+            self.platform.is_none()
+        } else {
+            self.mode == "run-custom-build"
+                || self
+                    .target
+                    .kind
+                    .iter()
+                    .flatten()
+                    .any(|k| k == "custom-build" || k == "proc-macro")
+        }
+    }
+}
+
+/// Output of `cargo build --unit-graph`
+#[derive(Clone, Debug, Default)]
+pub struct UnitGraph {
+    pub units: Vec<Unit>,
+}
+
+impl UnitGraph {
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let raw: RawUnitGraph = facet_json::from_str(json).map_err(|e| e.to_string())?;
+        let units = raw
+            .units
+            .into_iter()
+            .map(|unit| Unit {
+                package_id: unit.pkg_id,
+                target: unit.target,
+                mode: unit.mode,
+                platform: unit.platform,
+                dependencies: unit.dependencies.into_iter().map(|d| d.index).collect(),
+            })
+            .collect();
+        Ok(Self { units })
+    }
+
+    fn cross_compiling(&self) -> bool {
+        self.units.iter().any(|u| u.platform.is_some())
+    }
+
+    /// Whether the units with each key are built for the host (`Some(true)`), the
+    /// target (`Some(false)`), or both (`None`)
+    fn hosts(&self) -> HashMap<UnitKey, Option<bool>> {
+        let cross_compiling = self.cross_compiling();
+        let mut hosts = HashMap::new();
+        for unit in &self.units {
+            let is_host = unit.is_host(cross_compiling);
+            hosts
+                .entry(unit.key())
+                .and_modify(|h: &mut Option<bool>| {
+                    if *h != Some(is_host) {
+                        *h = None
+                    }
+                })
+                .or_insert(Some(is_host));
+        }
+        hosts
+    }
+
+    /// Splits `timings` into those of host units and those of target units,
+    /// telling them apart by the platform of the matching unit. Timing messages
+    /// don't say which platform a unit was built for, so a timing that matches
+    /// both a host and a target unit, or no unit at all, is classified by
+    /// [`TimingInfo::is_host_unit`].
+    pub fn partition_timings(
+        &self,
+        timings: Vec<TimingInfo>,
+    ) -> (Vec<TimingInfo>, Vec<TimingInfo>) {
+        let hosts = self.hosts();
+        timings
+            .into_iter()
+            .partition(|timing| timing_is_host(&hosts, timing))
+    }
+}
+
+fn timing_key(timing: &TimingInfo) -> Option<UnitKey> {
+    let package_id = timing.package_id.as_ref()?;
+    Some(unit_key(
+        package_id,
+        &timing.target,
+        timing.mode.as_deref().unwrap_or("build"),
+    ))
+}
+
+fn timing_is_host(hosts: &HashMap<UnitKey, Option<bool>>, timing: &TimingInfo) -> bool {
+    timing_key(timing)
+        .and_then(|key| hosts.get(&key).copied().flatten())
+        .unwrap_or_else(|| timing.is_host_unit())
+}
+
+/// Timing messages don't say which platform a unit was built for, so units built
+/// for both the host and the target share a key, see [`UnitGraph::hosts`].
+type UnitKey = (String, Option<String>, Vec<String>, String);
+
+fn unit_key(package_id: &str, target: &CargoTarget, mode: &str) -> UnitKey {
+    (
+        package_id.to_string(),
+        target.name.clone(),
+        target.kind.clone().unwrap_or_default(),
+        mode.to_string(),
+    )
+}
+
+/// A unit, placed on a schedule with unlimited parallelism
+#[derive(Clone, Debug)]
+pub struct ScheduledUnit {
+    pub unit: Unit,
+
+    /// Build scripts, proc macros and, when cross-compiling, their dependencies
+    pub is_host: bool,
+
+    /// Zero if cargo didn't report a timing, e.g. for fresh units
+    pub duration: Duration,
+
+    /// When the unit's metadata was ready, relative to its start
+    pub rmeta_time: Option<Duration>,
+
+    /// As soon as all the unit's dependencies were ready
+    pub earliest_start: Duration,
+
+    /// How much the unit could be delayed (or slowed down) without delaying the build
+    pub slack: Duration,
+
+    /// How much of the critical path's length is spent in this unit. Zero for units
+    /// off the critical path.
+    pub contribution: Duration,
+
+    /// Number of units that depend on this one, directly or not
+    pub dependents: usize,
+}
+
+impl ScheduledUnit {
+    pub fn on_critical_path(&self) -> bool {
+        !self.contribution.is_zero()
+    }
+}
+
+/// Critical path through a build, see the [module docs](self).
+#[derive(Clone, Debug)]
+pub struct CriticalPath {
+    /// Every unit of the graph, in the same order
+    pub units: Vec<ScheduledUnit>,
+
+    /// Indices of the units on the critical path, in build order
+    pub path: Vec<usize>,
+
+    /// Length of the critical path: how long the build would take with unlimited cores
+    pub length: Duration,
+}
+
+impl CriticalPath {
+    /// Schedules the units of `graph` using the durations from `timings`. Returns
+    /// `None` if the graph is empty or has a cycle.
+    pub fn compute<'a>(
+        graph: &UnitGraph,
+        timings: impl IntoIterator<Item = &'a TimingInfo>,
+    ) -> Option<Self> {
+        let units = &graph.units;
+        let n = units.len();
+        if n == 0 || units.iter().flat_map(|u| &u.dependencies).any(|&d| d >= n) {
+            return None;
+        }
+
+        // Timings are keyed by whether they're for the host, as far as we can tell
+        let cross_compiling = graph.cross_compiling();
+        let hosts = graph.hosts();
+        let mut durations: HashMap<(UnitKey, bool), (f64, Option<f64>)> = HashMap::new();
+        for timing in timings {
+            let Some(key) = timing_key(timing) else {
+                continue;
+            };
+            let is_host = timing_is_host(&hosts, timing);
+            let entry = durations.entry((key, is_host)).or_insert((0.0, None));
+            if timing.duration >= entry.0 {
+                *entry = (timing.duration, timing.rmeta_time);
+            }
+        }
+        let (duration, rmeta): (Vec<f64>, Vec<Option<f64>>) = units
+            .iter()
+            .map(|u| {
+                let is_host = u.is_host(cross_compiling);
+                // Timings of units built for both went to whichever side
+                // `TimingInfo::is_host_unit` picked
+                durations
+                    .get(&(u.key(), is_host))
+                    .or_else(|| durations.get(&(u.key(), !is_host)))
+                    .copied()
+                    .unwrap_or((0.0, None))
+            })
+            .unzip();
+
+        let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); n];
+        for (i, unit) in units.iter().enumerate() {
+            for &d in &unit.dependencies {
+                dependents[d].push(i);
+            }
+        }
+        let order = topological_order(units, &dependents)?;
+
+        // What each unit actually waits on: units that link wait on every library
+        // below them, up to the next unit that links or runs
+        let waits_on: Vec<Vec<usize>> = units
+            .iter()
+            .map(|unit| {
+                if !unit.needs_full_dependencies() {
+                    return unit.dependencies.clone();
+                }
+                let mut seen = vec![false; n];
+                let mut stack = unit.dependencies.clone();
+                let mut deps = Vec::new();
+                while let Some(d) = stack.pop() {
+                    if std::mem::replace(&mut seen[d], true) {
+                        continue;
+                    }
+                    deps.push(d);
+                    if !units[d].needs_full_dependencies() {
+                        stack.extend(&units[d].dependencies);
+                    }
+                }
+                deps.sort_unstable();
+                deps
+            })
+            .collect();
+        let mut waited_on_by: Vec<Vec<usize>> = vec![Vec::new(); n];
+        for (i, deps) in waits_on.iter().enumerate() {
+            for &d in deps {
+                waited_on_by[d].push(i);
+            }
+        }
+
+        // How long after `dep` starts `unit` can start
+        let lag = |dep: usize, unit: usize| {
+            if units[unit].needs_full_dependencies() {
+                duration[dep]
+            } else {
+                rmeta[dep].unwrap_or(duration[dep]).min(duration[dep])
+            }
+        };
+
+        let mut start = vec![0.0_f64; n];
+        for &u in &order {
+            start[u] = waits_on[u]
+                .iter()
+                .map(|&d| start[d] + lag(d, u))
+                .fold(0.0, f64::max);
+        }
+        let (end, length) =
+            (0..n)
+                .map(|u| (u, start[u] + duration[u]))
+                .fold(
+                    (0, 0.0),
+                    |best, cur| if cur.1 > best.1 { cur } else { best },
+                );
+
+        let mut latest_start = vec![0.0_f64; n];
+        for &u in order.iter().rev() {
+            latest_start[u] = waited_on_by[u]
+                .iter()
+                .map(|&v| latest_start[v] - lag(u, v))
+                .fold(length - duration[u], f64::min);
+        }
+
+        // Walk back from the unit that finishes last, through the dependencies
+        // that held each unit up
+        let mut contribution = vec![0.0_f64; n];
+        let mut path = vec![end];
+        contribution[end] = duration[end];
+        let mut current = end;
+        while start[current] > 0.0 {
+            let Some(&pred) = waits_on[current].iter().max_by(|&&a, &&b| {
+                (start[a] + lag(a, current)).total_cmp(&(start[b] + lag(b, current)))
+            }) else {
+                break;
+            };
+            contribution[pred] = lag(pred, current);
+            path.push(pred);
+            current = pred;
+        }
+        path.reverse();
+
+        let secs = |s: f64| Duration::from_secs_f64(s.max(0.0));
+        let units = units
+            .iter()
+            .enumerate()
+            .map(|(i, unit)| ScheduledUnit {
+                is_host: unit.is_host(cross_compiling),
+                duration: secs(duration[i]),
+                rmeta_time: rmeta[i].map(secs),
+                earliest_start: secs(start[i]),
+                slack: secs(latest_start[i] - start[i]),
+                contribution: secs(contribution[i]),
+                dependents: count_reachable(i, &dependents),
+                unit: unit.clone(),
+            })
+            .collect();
+
+        Some(Self {
+            units,
+            path,
+            length: secs(length),
+        })
+    }
+
+    /// Units on the critical path that hold up the most other units, i.e. the
+    /// ones that keep the build from using more cores
+    pub fn blockers(&self) -> Vec<&ScheduledUnit> {
+        let mut blockers: Vec<&ScheduledUnit> = self.path.iter().map(|&i| &self.units[i]).collect();
+        blockers.sort_by(|a, b| {
+            b.dependents
+                .cmp(&a.dependents)
+                .then(b.contribution.cmp(&a.contribution))
+        });
+        blockers
+    }
+}
+
+/// Dependencies first. `None` if there's a cycle.
+fn topological_order(units: &[Unit], dependents: &[Vec<usize>]) -> Option<Vec<usize>> {
+    let mut remaining: Vec<usize> = units.iter().map(|u| u.dependencies.len()).collect();
+    let mut order: Vec<usize> = (0..units.len()).filter(|&i| remaining[i] == 0).collect();
+    let mut next = 0;
+    while next < order.len() {
+        for &v in &dependents[order[next]] {
+            remaining[v] -= 1;
+            if remaining[v] == 0 {
+                order.push(v);
+            }
+        }
+        next += 1;
+    }
+    (order.len() == units.len()).then_some(order)
+}
+
+fn count_reachable(from: usize, edges: &[Vec<usize>]) -> usize {
+    let mut seen = vec![false; edges.len()];
+    let mut stack = vec![from];
+    let mut count = 0;
+    while let Some(u) = stack.pop() {
+        for &v in &edges[u] {
+            if !seen[v] {
+                seen[v] = true;
+                count += 1;
+                stack.push(v);
+            }
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(name: &str, kind: &str, dependencies: &[usize]) -> Unit {
+        Unit {
+            package_id: format!("registry+https://example.com#{name}@1.0.0"),
+            target: CargoTarget {
+                name: Some(name.to_string()),
+                kind: Some(vec![kind.to_string()]),
+                crate_types: Some(vec![kind.to_string()]),
+            },
+            mode: "build".to_string(),
+            platform: None,
+            dependencies: dependencies.to_vec(),
+        }
+    }
+
+    fn timing(unit: &Unit, duration: f64, rmeta_time: Option<f64>) -> TimingInfo {
+        TimingInfo {
+            package_id: Some(unit.package_id.clone()),
+            target: unit.target.clone(),
+            mode: Some(unit.mode.clone()),
+            duration,
+            rmeta_time,
+            finished_at: None,
+        }
+    }
+
+    #[test]
+    fn test_critical_path_with_pipelining() {
+        // app (bin) <- big (lib) <- core (lib)
+        //           <- small (lib)
+        let graph = UnitGraph {
+            units: vec![
+                unit("core", "lib", &[]),
+                unit("big", "lib", &[0]),
+                unit("small", "lib", &[]),
+                unit("app", "bin", &[1, 2]),
+            ],
+        };
+        let timings = [
+            timing(&graph.units[0], 3.5, Some(1.0)),
+            timing(&graph.units[1], 3.0, Some(2.0)),
+            timing(&graph.units[2], 1.0, Some(0.5)),
+            timing(&graph.units[3], 2.0, None),
+        ];
+        let cp = CriticalPath::compute(&graph, &timings).unwrap();
+
+        // big can start once core's metadata is ready, app needs big's rlib
+        assert_eq!(cp.path, vec![0, 1, 3]);
+        assert_eq!(cp.length, Duration::from_secs(6));
+        assert_eq!(cp.units[1].earliest_start, Duration::from_secs(1));
+        assert_eq!(cp.units[0].contribution, Duration::from_secs(1));
+        assert_eq!(cp.units[1].contribution, Duration::from_secs(3));
+
+        // app also waits for core's rlib, which is ready at 3.5
+        assert_eq!(cp.units[0].slack, Duration::ZERO);
+        assert_eq!(cp.units[2].slack, Duration::from_secs(3));
+        assert!(!cp.units[2].on_critical_path());
+
+        assert_eq!(cp.units[0].dependents, 2);
+        assert_eq!(cp.blockers()[0].unit.target.name.as_deref(), Some("core"));
+    }
+
+    #[test]
+    fn test_cycle_is_rejected() {
+        let graph = UnitGraph {
+            units: vec![unit("a", "lib", &[1]), unit("b", "lib", &[0])],
+        };
+        assert!(CriticalPath::compute(&graph, &[]).is_none());
+    }
+
+    #[test]
+    fn test_partition_timings_when_cross_compiling() {
+        // app (bin, target) <- derive (proc-macro, host) <- syn (lib, host)
+        //                   <- log (lib, both)
+        let mut units = vec![
+            unit("syn", "lib", &[]),
+            unit("derive", "proc-macro", &[0]),
+            unit("log", "lib", &[]),
+            unit("log", "lib", &[]),
+            unit("app", "bin", &[1, 3]),
+        ];
+        for i in [3, 4] {
+            units[i].platform = Some("aarch64-unknown-linux-gnu".to_string());
+        }
+        let graph = UnitGraph { units };
+        let timings = graph.units.iter().map(|u| timing(u, 1.0, None)).collect();
+
+        let (host, target) = graph.partition_timings(timings);
+        let names = |timings: &[TimingInfo]| -> Vec<String> {
+            timings
+                .iter()
+                .map(|t| t.target.name.clone().unwrap())
+                .collect()
+        };
+        // `log` is built for both, which timings can't tell apart
+        assert_eq!(names(&host), ["syn", "derive"]);
+        assert_eq!(names(&target), ["log", "log", "app"]);
+
+        let cp = CriticalPath::compute(&graph, &host).unwrap();
+        assert!(cp.units[0].is_host);
+        assert_eq!(cp.units[0].duration, Duration::from_secs(1));
+        assert_eq!(cp.units[2].duration, Duration::ZERO);
+    }
+}
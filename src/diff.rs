@@ -0,0 +1,848 @@
+//! Comparison of two [`BuildContext`]s.
+//!
+//! Changes are reported per crate, per symbol (by normalized demangled name,
+//! summed within a crate) and per LLVM function. Symbols that disappeared are
+//! matched with similar ones that appeared, and reported as renames. Small
+//! relative changes are left out according to [`DiffOptions`]; things that
+//! appear or disappear are always reported. Every list is sorted by the size
+//! of the change, largest first, then by name, so that two comparisons of the
+//! same builds are identical.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::time::Duration;
+
+use crate::types::{BuildContext, ByteSize, CrateName, LlvmFunctionName};
+
+/// Thresholds for [`BuildContext::compare`]. Percentages are relative to the
+/// "before" value.
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// Crates whose size changed by more than this are reported, in percent
+    pub crate_size_threshold: f64,
+
+    /// Crates whose build time changed by more than this are reported, in percent
+    pub crate_time_threshold: f64,
+
+    /// Build time changes of crates that take less than this to build (before
+    /// and after) are noise, and ignored
+    pub min_crate_time: Duration,
+
+    /// Symbols whose size changed by more than this are reported, in percent
+    pub symbol_size_threshold: f64,
+
+    /// LLVM functions whose line count changed by more than this are reported,
+    /// in percent
+    pub llvm_lines_threshold: f64,
+
+    /// Only compare the largest symbols of each build, `None` compares them all
+    pub top_symbols: Option<usize>,
+
+    /// Symbols that disappeared are matched with symbols of the same crate and
+    /// module, or with the same name, that appeared, and reported as renamed,
+    /// when they're at least this alike (from 0 to 1, mostly by name, partly by
+    /// size). `None` disables rename matching.
+    pub rename_similarity: Option<f64>,
+
+    /// Only compare the largest LLVM functions of each build, `None` compares
+    /// them all
+    pub top_llvm_functions: Option<usize>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            crate_size_threshold: 5.0,
+            crate_time_threshold: 10.0,
+            min_crate_time: Duration::from_millis(330),
+            symbol_size_threshold: 5.0,
+            llvm_lines_threshold: 5.0,
+            top_symbols: Some(20),
+            rename_similarity: Some(0.75),
+            top_llvm_functions: Some(20),
+        }
+    }
+}
+
+/// Result of [`BuildContext::compare`]
+#[derive(Debug, Clone)]
+pub struct AnalysisComparison {
+    pub file_size_diff: FileSizeDiff,
+
+    /// Wall time of the builds, when both were built by us
+    pub wall_duration_before: Option<Duration>,
+    pub wall_duration_after: Option<Duration>,
+
+    pub symbol_changes: Vec<SymbolChange>,
+    pub crate_changes: Vec<CrateChange>,
+    pub llvm_function_changes: Vec<LlvmFunctionChange>,
+
+    /// Crates only in the "after" build, sorted
+    pub crates_added: Vec<CrateName>,
+
+    /// Crates only in the "before" build, sorted
+    pub crates_removed: Vec<CrateName>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileSizeDiff {
+    pub file_size_before: ByteSize,
+    pub file_size_after: ByteSize,
+    pub text_size_before: ByteSize,
+    pub text_size_after: ByteSize,
+}
+
+impl FileSizeDiff {
+    /// Change in file size, in bytes
+    pub fn file_size_delta(&self) -> i64 {
+        self.file_size_after.value() as i64 - self.file_size_before.value() as i64
+    }
+
+    /// Change in `.text` size, in bytes
+    pub fn text_size_delta(&self) -> i64 {
+        self.text_size_after.value() as i64 - self.text_size_before.value() as i64
+    }
+}
+
+/// Whether something appeared, disappeared or changed between two builds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+
+    /// Matched to a symbol with another name, see [`DiffOptions::rename_similarity`]
+    Renamed,
+}
+
+impl ChangeKind {
+    fn of<T>(before: &Option<T>, after: &Option<T>) -> Self {
+        match (before, after) {
+            (None, _) => ChangeKind::Added,
+            (_, None) => ChangeKind::Removed,
+            _ => ChangeKind::Changed,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SymbolChange {
+    pub crate_name: CrateName,
+
+    /// Demangled name without hash, normalized with [`normalize_symbol`], which
+    /// is what symbols are matched by
+    pub name: String,
+
+    /// The name in the "before" build, for renamed symbols
+    pub previous_name: Option<String>,
+
+    /// Demangled name of the largest instance, with its hash
+    pub demangled: String,
+
+    /// Total size of the symbols with this name in the crate, `None` if absent
+    pub size_before: Option<u64>,
+    pub size_after: Option<u64>,
+}
+
+impl SymbolChange {
+    pub fn kind(&self) -> ChangeKind {
+        if self.previous_name.is_some() {
+            return ChangeKind::Renamed;
+        }
+        ChangeKind::of(&self.size_before, &self.size_after)
+    }
+
+    /// Change in size, in bytes
+    pub fn delta(&self) -> i64 {
+        self.size_after.unwrap_or(0) as i64 - self.size_before.unwrap_or(0) as i64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CrateChange {
+    pub name: String,
+
+    /// Total size of the crate's symbols, `None` if the crate is absent
+    pub size_before: Option<u64>,
+    pub size_after: Option<u64>,
+
+    /// Time cargo took to build the crate, if known
+    pub build_time_before: Option<Duration>,
+    pub build_time_after: Option<Duration>,
+}
+
+impl CrateChange {
+    pub fn kind(&self) -> ChangeKind {
+        ChangeKind::of(&self.size_before, &self.size_after)
+    }
+
+    /// Change in size, in bytes
+    pub fn delta(&self) -> i64 {
+        self.size_after.unwrap_or(0) as i64 - self.size_before.unwrap_or(0) as i64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LlvmFunctionChange {
+    pub crate_name: CrateName,
+    pub name: LlvmFunctionName,
+
+    /// Lines of LLVM IR, `None` if the function is absent
+    pub lines_before: Option<usize>,
+    pub lines_after: Option<usize>,
+}
+
+impl LlvmFunctionChange {
+    pub fn kind(&self) -> ChangeKind {
+        ChangeKind::of(&self.lines_before, &self.lines_after)
+    }
+
+    /// Change in line count
+    pub fn delta(&self) -> i64 {
+        self.lines_after.unwrap_or(0) as i64 - self.lines_before.unwrap_or(0) as i64
+    }
+}
+
+impl BuildContext {
+    /// Compares this build (before) to `other` (after), see the
+    /// [`diff` module docs](crate::diff).
+    pub fn compare(&self, other: &BuildContext, options: &DiffOptions) -> AnalysisComparison {
+        let crates_before: BTreeSet<&CrateName> = self.crates.iter().map(|c| &c.name).collect();
+        let crates_after: BTreeSet<&CrateName> = other.crates.iter().map(|c| &c.name).collect();
+
+        AnalysisComparison {
+            file_size_diff: FileSizeDiff {
+                file_size_before: self.file_size,
+                file_size_after: other.file_size,
+                text_size_before: self.text_size,
+                text_size_after: other.text_size,
+            },
+            wall_duration_before: self.wall_duration,
+            wall_duration_after: other.wall_duration,
+            symbol_changes: symbol_changes(self, other, options),
+            crate_changes: crate_changes(self, other, options),
+            llvm_function_changes: llvm_function_changes(self, other, options),
+            crates_added: crates_after
+                .difference(&crates_before)
+                .map(|c| (*c).clone())
+                .collect(),
+            crates_removed: crates_before
+                .difference(&crates_after)
+                .map(|c| (*c).clone())
+                .collect(),
+        }
+    }
+}
+
+/// Whether going from `before` to `after` is worth reporting: anything that
+/// appears or disappears is, other changes must exceed `threshold` percent.
+fn exceeds(before: f64, after: f64, threshold: f64) -> bool {
+    if before == 0.0 {
+        after != 0.0
+    } else {
+        ((after - before) / before * 100.0).abs() > threshold
+    }
+}
+
+fn crate_changes(
+    before: &BuildContext,
+    after: &BuildContext,
+    options: &DiffOptions,
+) -> Vec<CrateChange> {
+    struct Stats {
+        size: u64,
+        build_time: Option<Duration>,
+    }
+    let stats = |context: &BuildContext| -> BTreeMap<CrateName, Stats> {
+        let mut map: BTreeMap<CrateName, Stats> = BTreeMap::new();
+        for krate in &context.crates {
+            let entry = map.entry(krate.name.clone()).or_insert(Stats {
+                size: 0,
+                build_time: None,
+            });
+            entry.size += krate.symbols.values().map(|s| s.size.value()).sum::<u64>();
+            if let Some(timing) = &krate.timing_info {
+                entry.build_time = Some(Duration::from_secs_f64(timing.duration));
+            }
+        }
+        map
+    };
+    let before = stats(before);
+    let after = stats(after);
+    let names: BTreeSet<&CrateName> = before.keys().chain(after.keys()).collect();
+
+    let mut changes: Vec<CrateChange> = names
+        .into_iter()
+        .filter_map(|name| {
+            let (b, a) = (before.get(name), after.get(name));
+            let change = CrateChange {
+                name: name.to_string(),
+                size_before: b.map(|s| s.size),
+                size_after: a.map(|s| s.size),
+                build_time_before: b.and_then(|s| s.build_time),
+                build_time_after: a.and_then(|s| s.build_time),
+            };
+            if change.kind() != ChangeKind::Changed {
+                return Some(change);
+            }
+
+            let size_changed = exceeds(
+                change.size_before.unwrap_or(0) as f64,
+                change.size_after.unwrap_or(0) as f64,
+                options.crate_size_threshold,
+            );
+            let time_changed = match (change.build_time_before, change.build_time_after) {
+                (Some(b), Some(a)) => {
+                    b.max(a) >= options.min_crate_time
+                        && exceeds(
+                            b.as_secs_f64(),
+                            a.as_secs_f64(),
+                            options.crate_time_threshold,
+                        )
+                }
+                _ => false,
+            };
+            (size_changed || time_changed).then_some(change)
+        })
+        .collect();
+    changes.sort_by(|a, b| {
+        b.delta()
+            .abs()
+            .cmp(&a.delta().abs())
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    changes
+}
+
+/// Names of the `top` largest entries of `map`, all of them if `None`
+fn largest<K: Ord + Clone, V: Ord>(map: &BTreeMap<K, V>, top: Option<usize>) -> Vec<K> {
+    let mut entries: Vec<(&K, &V)> = map.iter().collect();
+    entries.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    entries
+        .into_iter()
+        .take(top.unwrap_or(usize::MAX))
+        .map(|(k, _)| k.clone())
+        .collect()
+}
+
+fn symbol_changes(
+    before: &BuildContext,
+    after: &BuildContext,
+    options: &DiffOptions,
+) -> Vec<SymbolChange> {
+    type Key = (CrateName, String);
+    // Total size, and the largest instance's size and full name
+    let sizes = |context: &BuildContext| -> BTreeMap<Key, (u64, u64, String)> {
+        let mut map: BTreeMap<Key, (u64, u64, String)> = BTreeMap::new();
+        for krate in &context.crates {
+            for symbol in krate.symbols.values() {
+                let size = symbol.size.value();
+                let name = normalize_symbol(symbol.name.strip_hash().as_str());
+                let entry = map
+                    .entry((krate.name.clone(), name))
+                    .or_insert((0, 0, String::new()));
+                entry.0 += size;
+                if size > entry.1 || entry.2.is_empty() {
+                    entry.1 = size;
+                    entry.2 = symbol.name.to_string();
+                }
+            }
+        }
+        map
+    };
+    let before = sizes(before);
+    let after = sizes(after);
+    let totals = |map: &BTreeMap<Key, (u64, u64, String)>| -> BTreeMap<Key, u64> {
+        map.iter().map(|(k, v)| (k.clone(), v.0)).collect()
+    };
+    let candidates: BTreeSet<Key> = largest(&totals(&before), options.top_symbols)
+        .into_iter()
+        .chain(largest(&totals(&after), options.top_symbols))
+        .collect();
+
+    let change = |old: Option<&Key>, new: Option<&Key>| {
+        let b = old.and_then(|k| before.get(k));
+        let a = new.and_then(|k| after.get(k));
+        let demangled = match (b, a) {
+            (Some(b), Some(a)) if b.1 > a.1 => b.2.clone(),
+            (_, Some(a)) => a.2.clone(),
+            (Some(b), None) => b.2.clone(),
+            (None, None) => String::new(),
+        };
+        let (crate_name, name) = new
+            .or(old)
+            .cloned()
+            .expect("a change has a before or an after");
+        SymbolChange {
+            crate_name,
+            previous_name: old
+                .zip(new)
+                .filter(|(old, new)| old != new)
+                .map(|(old, _)| old.1.clone()),
+            name,
+            demangled,
+            size_before: b.map(|v| v.0),
+            size_after: a.map(|v| v.0),
+        }
+    };
+
+    let mut changes = Vec::new();
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for key in &candidates {
+        match (before.get(key), after.get(key)) {
+            (Some(b), Some(a)) => {
+                if exceeds(b.0 as f64, a.0 as f64, options.symbol_size_threshold) {
+                    changes.push(change(Some(key), Some(key)));
+                }
+            }
+            (Some(_), None) => removed.push(key),
+            (None, Some(_)) => added.push(key),
+            (None, None) => {}
+        }
+    }
+
+    if let Some(threshold) = options.rename_similarity {
+        // Removed candidates may have become any added symbol of the same
+        // crate, not just a large one, and the other way around. Only symbols
+        // that kept their name or their module are scored: comparing every
+        // pair is quadratic in the number of symbols.
+        let only_before = rename_index(before.keys().filter(|k| !after.contains_key(*k)));
+        let only_after = rename_index(after.keys().filter(|k| !before.contains_key(*k)));
+
+        let removed_keys: HashSet<&Key> = removed.iter().copied().collect();
+        let mut pairs: Vec<(&Key, &Key)> = Vec::new();
+        for &old in &removed {
+            pairs.extend(rename_candidates(&only_after, old).map(|new| (old, new)));
+        }
+        for &new in &added {
+            pairs.extend(
+                rename_candidates(&only_before, new)
+                    .filter(|old| !removed_keys.contains(old))
+                    .map(|old| (old, new)),
+            );
+        }
+        let mut pairs: Vec<(f64, &Key, &Key)> = pairs
+            .into_iter()
+            .map(|(old, new)| {
+                let score = rename_score(&old.1, before[old].0, &new.1, after[new].0);
+                (score, old, new)
+            })
+            .filter(|(score, _, _)| *score >= threshold)
+            .collect();
+        pairs.sort_by(|a, b| {
+            b.0.total_cmp(&a.0)
+                .then_with(|| a.1.cmp(b.1))
+                .then_with(|| a.2.cmp(b.2))
+        });
+
+        let mut renamed_from = BTreeSet::new();
+        let mut renamed_to = BTreeSet::new();
+        for (_, old, new) in pairs {
+            if renamed_from.contains(old) || renamed_to.contains(new) {
+                continue;
+            }
+            renamed_from.insert(old);
+            renamed_to.insert(new);
+            changes.push(change(Some(old), Some(new)));
+        }
+        removed.retain(|k| !renamed_from.contains(k));
+        added.retain(|k| !renamed_to.contains(k));
+    }
+
+    changes.extend(removed.into_iter().map(|k| change(Some(k), None)));
+    changes.extend(added.into_iter().map(|k| change(None, Some(k))));
+    changes.sort_by(|a, b| {
+        b.delta()
+            .abs()
+            .cmp(&a.delta().abs())
+            .then_with(|| a.crate_name.cmp(&b.crate_name))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    changes
+}
+
+/// Spells a demangled symbol name (without hash) so that what changes between
+/// otherwise identical builds doesn't matter: closure and impl indices
+/// (`{closure#3}`, `{{closure}}`) become `{closure}`, shims are spelled the same
+/// in both manglings (`{{vtable.shim}}`, `{shim:vtable#0}` become
+/// `{vtable-shim}`), and crate disambiguators (`serde[9f3c2a1b]`) are removed.
+pub fn normalize_symbol(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(index) = rest.find(['{', '[']) {
+        out.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        let double = rest.starts_with("{{");
+        let (open, close) = if double { ("{{", "}}") } else { ("{", "}") };
+        if rest.starts_with('{') {
+            if let Some(end) = rest.find(close) {
+                let inner = &rest[open.len()..end];
+                let is_tag = !inner.is_empty()
+                    && inner
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || "_.-:#".contains(c));
+                if is_tag {
+                    let tag = inner.split('#').next().unwrap_or_default();
+                    let tag = match tag.strip_prefix("shim:") {
+                        Some(kind) => format!("{kind}-shim"),
+                        None => tag.replace(".shim", "-shim"),
+                    };
+                    out.push('{');
+                    out.push_str(&tag);
+                    out.push('}');
+                    rest = &rest[end + close.len()..];
+                    continue;
+                }
+            }
+        } else if let Some(end) = rest.find(']') {
+            // Disambiguators follow the crate name, unlike slices of a type that
+            // happens to be spelled in hex (`[Face]`)
+            let inner = &rest[1..end];
+            let follows_name = out.ends_with(|c: char| c.is_alphanumeric() || c == '_');
+            if follows_name && inner.len() >= 8 && inner.chars().all(|c| c.is_ascii_hexdigit()) {
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+
+        out.push_str(&rest[..1]);
+        rest = &rest[1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// How likely `old` is to have become `new`, from 0 to 1. Names weigh the most:
+/// the bigram similarity of the whole names is averaged with that of the last
+/// path segment, so that moving a function to another module still scores high.
+fn rename_score(old: &str, old_size: u64, new: &str, new_size: u64) -> f64 {
+    let names = (dice(old, new) + dice(&last_segment(old), &last_segment(new))) / 2.0;
+    let sizes = if old_size.max(new_size) == 0 {
+        1.0
+    } else {
+        old_size.min(new_size) as f64 / old_size.max(new_size) as f64
+    };
+    0.75 * names + 0.25 * sizes
+}
+
+/// The last `::` segment of a name, ignoring generic arguments
+fn last_segment(name: &str) -> String {
+    let stripped = strip_generics(name);
+    stripped.rsplit("::").next().unwrap_or_default().to_string()
+}
+
+/// Symbols by crate and [`rename_buckets`]
+type RenameIndex<'a> = HashMap<(&'a CrateName, String), Vec<&'a (CrateName, String)>>;
+
+fn rename_index<'a>(keys: impl Iterator<Item = &'a (CrateName, String)>) -> RenameIndex<'a> {
+    let mut index = RenameIndex::new();
+    for key in keys {
+        for bucket in rename_buckets(&key.1) {
+            index.entry((&key.0, bucket)).or_default().push(key);
+        }
+    }
+    index
+}
+
+/// Symbols of `index` that `key` may have been renamed from or to
+fn rename_candidates<'a>(
+    index: &RenameIndex<'a>,
+    key: &(CrateName, String),
+) -> impl Iterator<Item = &'a (CrateName, String)> {
+    let candidates: BTreeSet<&'a (CrateName, String)> = rename_buckets(&key.1)
+        .into_iter()
+        .filter_map(|bucket| index.get(&(&key.0, bucket)))
+        .flatten()
+        .copied()
+        .collect();
+    candidates.into_iter()
+}
+
+/// Names that renamed symbols are looked for under: the last path segment, for
+/// symbols that moved, and the path before it, for symbols renamed in place
+fn rename_buckets(name: &str) -> Vec<String> {
+    let stripped = strip_generics(name);
+    let (module, last) = stripped.rsplit_once("::").unwrap_or(("", &stripped));
+    vec![last.to_string(), format!("{module}::")]
+}
+
+fn strip_generics(name: &str) -> String {
+    let mut depth = 0usize;
+    let mut stripped = String::new();
+    for c in name.chars() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth = depth.saturating_sub(1),
+            _ if depth == 0 => stripped.push(c),
+            _ => {}
+        }
+    }
+    stripped
+}
+
+/// Sørensen–Dice coefficient of the character bigrams of `a` and `b`
+fn dice(a: &str, b: &str) -> f64 {
+    let bigrams = |s: &str| {
+        let chars: Vec<char> = s.chars().collect();
+        let mut bigrams: Vec<(char, char)> = chars.windows(2).map(|w| (w[0], w[1])).collect();
+        bigrams.sort_unstable();
+        bigrams
+    };
+    let (a_bigrams, b_bigrams) = (bigrams(a), bigrams(b));
+    if a_bigrams.is_empty() || b_bigrams.is_empty() {
+        return if a == b { 1.0 } else { 0.0 };
+    }
+
+    let (mut i, mut j, mut common) = (0, 0, 0);
+    while i < a_bigrams.len() && j < b_bigrams.len() {
+        match a_bigrams[i].cmp(&b_bigrams[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    2.0 * common as f64 / (a_bigrams.len() + b_bigrams.len()) as f64
+}
+
+fn llvm_function_changes(
+    before: &BuildContext,
+    after: &BuildContext,
+    options: &DiffOptions,
+) -> Vec<LlvmFunctionChange> {
+    type Key = (CrateName, LlvmFunctionName);
+    let lines = |context: &BuildContext| -> BTreeMap<Key, usize> {
+        let mut map: BTreeMap<Key, usize> = BTreeMap::new();
+        for krate in &context.crates {
+            for function in krate.llvm_functions.values() {
+                *map.entry((krate.name.clone(), function.name.clone()))
+                    .or_default() += function.lines.value();
+            }
+        }
+        map
+    };
+    let before = lines(before);
+    let after = lines(after);
+    let candidates: BTreeSet<Key> = largest(&before, options.top_llvm_functions)
+        .into_iter()
+        .chain(largest(&after, options.top_llvm_functions))
+        .collect();
+
+    let mut changes: Vec<LlvmFunctionChange> = candidates
+        .into_iter()
+        .map(|key| {
+            let (crate_name, name) = key.clone();
+            LlvmFunctionChange {
+                crate_name,
+                name,
+                lines_before: before.get(&key).copied(),
+                lines_after: after.get(&key).copied(),
+            }
+        })
+        .filter(|change| {
+            change.kind() != ChangeKind::Changed
+                || exceeds(
+                    change.lines_before.unwrap_or(0) as f64,
+                    change.lines_after.unwrap_or(0) as f64,
+                    options.llvm_lines_threshold,
+                )
+        })
+        .collect();
+    changes.sort_by(|a, b| {
+        b.delta()
+            .abs()
+            .cmp(&a.delta().abs())
+            .then_with(|| a.crate_name.cmp(&b.crate_name))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Crate;
+
+    #[test]
+    fn test_compare() {
+        let before = BuildContext::for_test(
+            1000,
+            vec![
+                Crate::named("app")
+                    .with_symbol("app::main::h1111111111111111", 100)
+                    .with_symbol("app::run", 50)
+                    .with_llvm_function("app::main", 40, 1)
+                    .with_build_time(1.0),
+                Crate::named("serde")
+                    .with_symbol("serde::de::h2222222222222222", 400)
+                    .with_build_time(2.0),
+                Crate::named("gone")
+                    .with_symbol("gone::f", 10)
+                    .with_build_time(0.1),
+            ],
+        );
+        let after = BuildContext::for_test(
+            1300,
+            vec![
+                Crate::named("app")
+                    .with_symbol("app::main::h3333333333333333", 102)
+                    .with_symbol("app::run", 80)
+                    .with_llvm_function("app::main", 60, 1)
+                    .with_build_time(1.5),
+                Crate::named("serde")
+                    .with_symbol("serde::de::h4444444444444444", 401)
+                    .with_build_time(2.05),
+                Crate::named("new")
+                    .with_symbol("new::f", 20)
+                    .with_build_time(0.1),
+            ],
+        );
+
+        let diff = before.compare(&after, &DiffOptions::default());
+        assert_eq!(diff.file_size_diff.file_size_delta(), 300);
+        assert_eq!(diff.crates_added, [CrateName::from("new")]);
+        assert_eq!(diff.crates_removed, [CrateName::from("gone")]);
+
+        // serde changed by less than the thresholds, app's time changed by 50%
+        let crates: Vec<(&str, i64, ChangeKind)> = diff
+            .crate_changes
+            .iter()
+            .map(|c| (c.name.as_str(), c.delta(), c.kind()))
+            .collect();
+        assert_eq!(
+            crates,
+            [
+                ("app", 32, ChangeKind::Changed),
+                ("new", 20, ChangeKind::Added),
+                ("gone", -10, ChangeKind::Removed),
+            ]
+        );
+
+        // `app::main` only changed hash and by 2%
+        let symbols: Vec<(&str, i64)> = diff
+            .symbol_changes
+            .iter()
+            .map(|s| (s.name.as_str(), s.delta()))
+            .collect();
+        assert_eq!(
+            symbols,
+            [("app::run", 30), ("new::f", 20), ("gone::f", -10)]
+        );
+
+        assert_eq!(diff.llvm_function_changes.len(), 1);
+        assert_eq!(diff.llvm_function_changes[0].delta(), 20);
+
+        let strict = DiffOptions {
+            symbol_size_threshold: 1.0,
+            ..DiffOptions::default()
+        };
+        let diff = before.compare(&after, &strict);
+        assert_eq!(diff.symbol_changes.len(), 4);
+        assert_eq!(
+            diff.symbol_changes[3].demangled,
+            "app::main::h3333333333333333"
+        );
+    }
+
+    #[test]
+    fn test_normalize_symbol() {
+        let cases = [
+            (
+                "app::main::{{closure}}::{{closure}}",
+                "app::main::{closure}::{closure}",
+            ),
+            ("app[1a2b3c4d]::main::{closure#3}", "app::main::{closure}"),
+            (
+                "<app::Foo as core::ops::function::FnOnce<()>>::call_once{{vtable.shim}}",
+                "<app::Foo as core::ops::function::FnOnce<()>>::call_once{vtable-shim}",
+            ),
+            (
+                "<app::Foo as core::ops::function::FnOnce<()>>::call_once::{shim:vtable#0}",
+                "<app::Foo as core::ops::function::FnOnce<()>>::call_once::{vtable-shim}",
+            ),
+            (
+                "core::ptr::drop_in_place<[u8; 4]>",
+                "core::ptr::drop_in_place<[u8; 4]>",
+            ),
+            ("app::{impl#2}::fmt", "app::{impl}::fmt"),
+            ("<[Face] as app::Draw>::draw", "<[Face] as app::Draw>::draw"),
+            ("app::string<[Bead; 2]>", "app::string<[Bead; 2]>"),
+            ("app[abc]::main", "app[abc]::main"),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(normalize_symbol(name), expected, "normalizing {name}");
+        }
+    }
+
+    #[test]
+    fn test_renames() {
+        let before = BuildContext::for_test(
+            1000,
+            vec![Crate::named("app")
+                .with_symbol("app::config::load_settings", 300)
+                .with_symbol("app::render::{{closure}}", 100)
+                .with_symbol("app::unrelated", 50)
+                .with_build_time(1.0)],
+        );
+        let after = BuildContext::for_test(
+            1000,
+            vec![Crate::named("app")
+                .with_symbol("app::settings::load_settings", 310)
+                .with_symbol("app::render::{closure#0}", 100)
+                .with_symbol("app::something_else", 500)
+                .with_build_time(1.0)],
+        );
+
+        let diff = before.compare(&after, &DiffOptions::default());
+        let symbols: Vec<(&str, Option<&str>, ChangeKind)> = diff
+            .symbol_changes
+            .iter()
+            .map(|s| (s.name.as_str(), s.previous_name.as_deref(), s.kind()))
+            .collect();
+        assert_eq!(
+            symbols,
+            [
+                ("app::something_else", None, ChangeKind::Added),
+                ("app::unrelated", None, ChangeKind::Removed),
+                (
+                    "app::settings::load_settings",
+                    Some("app::config::load_settings"),
+                    ChangeKind::Renamed
+                ),
+            ]
+        );
+        assert_eq!(diff.symbol_changes[2].delta(), 10);
+
+        let no_renames = DiffOptions {
+            rename_similarity: None,
+            ..DiffOptions::default()
+        };
+        assert_eq!(before.compare(&after, &no_renames).symbol_changes.len(), 4);
+    }
+
+    #[test]
+    fn test_rename_buckets() {
+        assert_eq!(
+            rename_buckets("app::config::load<T>"),
+            ["load", "app::config::"]
+        );
+        assert_eq!(rename_buckets("main"), ["main", "::"]);
+
+        // Alike, but neither in the same module nor with the same name
+        let before = BuildContext::for_test(
+            1000,
+            vec![Crate::named("app").with_symbol("app::a::parse_config", 100)],
+        );
+        let after = BuildContext::for_test(
+            1000,
+            vec![Crate::named("app").with_symbol("app::b::parse_configs", 100)],
+        );
+        let diff = before.compare(&after, &DiffOptions::default());
+        let kinds: Vec<ChangeKind> = diff.symbol_changes.iter().map(|s| s.kind()).collect();
+        assert_eq!(kinds, [ChangeKind::Removed, ChangeKind::Added]);
+    }
+}
@@ -1,19 +1,102 @@
-use crate::{errors::SubstanceError, types::CrateName};
+use crate::{
+    errors::SubstanceError,
+    types::{CrateName, TargetTriple, TargetTripleRef},
+};
+
+/// Returns the manifest of the workspace `manifest_path` belongs to, which is
+/// where cargo reads profiles from. Falls back to `manifest_path` itself.
+pub(crate) fn workspace_root_manifest(
+    manifest_path: &camino::Utf8Path,
+    toolchain: Option<&str>,
+) -> camino::Utf8PathBuf {
+    let output = cargo(toolchain)
+        .args(["locate-project", "--workspace", "--message-format", "plain"])
+        .arg("--manifest-path")
+        .arg(manifest_path)
+        .output();
+
+    match output {
+        Ok(output) if output.status.success() => {
+            let root = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            camino::Utf8PathBuf::from(root)
+        }
+        _ => {
+            log::warn!("Could not locate the workspace root of {manifest_path}");
+            manifest_path.to_owned()
+        }
+    }
+}
+
+/// Returns a `rustc` command for `toolchain` (e.g. `nightly-2026-09-01`), or for
+/// the default toolchain if `None`. Toolchains are picked with rustup's
+/// `+toolchain` syntax.
+pub(crate) fn rustc(toolchain: Option<&str>) -> std::process::Command {
+    with_toolchain("rustc", toolchain)
+}
+
+/// Returns a `cargo` command for `toolchain`, see [`rustc`].
+pub(crate) fn cargo(toolchain: Option<&str>) -> std::process::Command {
+    with_toolchain("cargo", toolchain)
+}
+
+fn with_toolchain(program: &str, toolchain: Option<&str>) -> std::process::Command {
+    let mut cmd = std::process::Command::new(program);
+    if let Some(toolchain) = toolchain {
+        cmd.arg(format!("+{toolchain}"));
+    }
+    cmd
+}
 
-pub(crate) fn stdlibs_dir() -> Result<camino::Utf8PathBuf, SubstanceError> {
+/// Returns the triple of the host, as reported by `rustc -vV`.
+pub(crate) fn host_triple(toolchain: Option<&str>) -> Result<TargetTriple, SubstanceError> {
+    let output = rustc(toolchain).arg("-vV").output().map_err(|e| {
+        log::error!("Failed to execute rustc: {e}");
+        SubstanceError::RustcFailed
+    })?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    parse_host_triple(&stdout).ok_or(SubstanceError::TargetDetectionFailed)
+}
+
+/// Returns the version of rustc, e.g. `rustc 1.87.0 (17067e9ac 2025-05-09)`.
+pub(crate) fn rustc_version(toolchain: Option<&str>) -> Result<String, SubstanceError> {
+    let output = rustc(toolchain).arg("--version").output().map_err(|e| {
+        log::error!("Failed to execute rustc: {e}");
+        SubstanceError::RustcFailed
+    })?;
+    if !output.status.success() {
+        return Err(SubstanceError::RustcFailed);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn parse_host_triple(rustc_version_verbose: &str) -> Option<TargetTriple> {
+    rustc_version_verbose
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(|host| TargetTriple::from(host.trim()))
+}
+
+/// Returns the directory containing the std rlibs for the given target, or for
+/// the host if `target` is `None`, in the sysroot of `toolchain`.
+pub(crate) fn stdlibs_dir(
+    target: Option<&TargetTripleRef>,
+    toolchain: Option<&str>,
+) -> Result<camino::Utf8PathBuf, SubstanceError> {
     use camino::Utf8PathBuf;
-    use std::process::Command;
 
-    log::debug!("Finding stdlib directory");
+    log::debug!("Finding stdlib directory for target {target:?} (toolchain {toolchain:?})");
 
-    let output = Command::new("rustc")
-        .arg("--print")
-        .arg("target-libdir")
-        .output()
-        .map_err(|e| {
-            log::error!("Failed to execute rustc: {e}");
-            SubstanceError::RustcFailed
-        })?;
+    let mut cmd = rustc(toolchain);
+    cmd.arg("--print").arg("target-libdir");
+    if let Some(target) = target {
+        cmd.arg("--target").arg(target.as_str());
+    }
+    let output = cmd.output().map_err(|e| {
+        log::error!("Failed to execute rustc: {e}");
+        SubstanceError::RustcFailed
+    })?;
 
     // Handle potential non-UTF8 output gracefully, rather than panicking.
     // If it's not valid UTF-8, it's likely an issue with rustc's output itself.
@@ -112,6 +195,23 @@ mod tests {
         assert_eq!(cratename, crate_name("foo"));
     }
 
+    #[test]
+    fn test_parse_host_triple() {
+        let output = "rustc 1.87.0 (17067e9ac 2025-05-09)
+binary: rustc
+commit-hash: 17067e9ac6d7e98f18d4a2ab5f4ac3fb1a5c9c4e
+commit-date: 2025-05-09
+host: aarch64-apple-darwin
+release: 1.87.0
+LLVM version: 20.1.1
+";
+        assert_eq!(
+            parse_host_triple(output),
+            Some(TargetTriple::from("aarch64-apple-darwin"))
+        );
+        assert_eq!(parse_host_triple("garbage"), None);
+    }
+
     #[test]
     fn test_rlib_path_to_cratename_underscore() {
         let path = Utf8PathBuf::from("libbar_baz-abcd1234abcdabcd.rlib");
@@ -7,6 +7,8 @@
 use camino::Utf8PathBuf;
 use thiserror::Error;
 
+use crate::cargo::Diagnostic;
+
 /// All errors that can originate from `substance`.
 ///
 /// Wherever possible, we derive `From` automatically via `thiserror`'s
@@ -28,7 +30,13 @@ pub enum SubstanceError {
     CargoMetadataFailed,
 
     #[error("failed to execute 'cargo build'. Probably a build error")]
-    CargoBuildFailed,
+    CargoBuildFailed {
+        /// Warnings and errors reported by rustc during the build
+        diagnostics: Vec<Diagnostic>,
+
+        /// Everything cargo printed to stderr, e.g. manifest or resolution errors
+        stderr: String,
+    },
 
     #[error("only 'bin', 'dylib' and 'cdylib' crate types are supported")]
     UnsupportedCrateType,
@@ -36,6 +44,22 @@ pub enum SubstanceError {
     #[error("failed to open a file '{0}'")]
     OpenFailed(Utf8PathBuf),
 
+    #[error("failed to write a file '{0}'")]
+    WriteFailed(Utf8PathBuf),
+
+    #[error("'{0}' is not a valid snapshot: {1}")]
+    InvalidSnapshot(Utf8PathBuf, String),
+
+    #[error("'{path}' is a version {found} snapshot, but only version {expected} can be loaded")]
+    SnapshotVersionMismatch {
+        path: Utf8PathBuf,
+        found: u32,
+        expected: u32,
+    },
+
+    #[error("invalid budgets in '{0}': {1}")]
+    InvalidBudgets(Utf8PathBuf, String),
+
     #[error("failed to parse 'cargo' output")]
     InvalidCargoOutput,
 
@@ -53,6 +77,12 @@ pub enum SubstanceError {
 
     #[error("failed to detect target triple")]
     TargetDetectionFailed,
+
+    #[error("the build was cancelled")]
+    Cancelled,
+
+    #[error("the build timed out after {0:?}")]
+    TimedOut(std::time::Duration),
 }
 
 /// `binfarce::UnexpectedEof` does not implement `std::error::Error`, so
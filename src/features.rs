@@ -0,0 +1,226 @@
+//! Cost of each Cargo feature of a package.
+//!
+//! The package is built once without default features, then once per feature
+//! with only that feature enabled (`default` aside), or once per chosen set of
+//! features, each in its own target directory (see [`crate::matrix`]). Every
+//! set is compared to the baseline, not to the others.
+
+use std::collections::HashMap;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use facet::Facet;
+use log::{info, warn};
+
+use crate::env;
+use crate::errors::SubstanceError;
+use crate::matrix::{BuildDelta, BuildOptions};
+use crate::BuildResult;
+
+#[derive(Debug, Facet)]
+struct RawMetadata {
+    packages: Vec<RawPackage>,
+}
+
+#[derive(Debug, Facet)]
+struct RawPackage {
+    name: String,
+    manifest_path: String,
+
+    /// Feature name to the features and dependencies it enables
+    features: HashMap<String, Vec<String>>,
+}
+
+/// Returns the features of the package of `manifest_path`, sorted, as reported
+/// by `cargo metadata` of `toolchain` (the default one if `None`). This includes
+/// `default` if the package declares it, and the implicit features of optional
+/// dependencies.
+pub fn package_features(
+    manifest_path: &Utf8Path,
+    toolchain: Option<&str>,
+) -> Result<Vec<String>, SubstanceError> {
+    let output = env::cargo(toolchain)
+        .args(["metadata", "--no-deps", "--format-version", "1"])
+        .arg("--manifest-path")
+        .arg(manifest_path)
+        .output()
+        .map_err(|e| {
+            log::error!("Failed to execute cargo metadata: {e}");
+            SubstanceError::CargoMetadataFailed
+        })?;
+    if !output.status.success() {
+        return Err(SubstanceError::CargoError(format!(
+            "cargo metadata failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let metadata: RawMetadata = facet_json::from_str(&String::from_utf8_lossy(&output.stdout))
+        .map_err(|e| SubstanceError::CargoError(format!("Invalid cargo metadata: {e}")))?;
+    let canonical = |path: &Utf8Path| path.canonicalize_utf8().unwrap_or_else(|_| path.to_owned());
+    let wanted = canonical(manifest_path);
+    let package = metadata
+        .packages
+        .into_iter()
+        .find(|p| canonical(Utf8Path::new(&p.manifest_path)) == wanted)
+        .ok_or_else(|| {
+            SubstanceError::CargoError(format!(
+                "{manifest_path} is not the manifest of a package (is it a virtual manifest?)"
+            ))
+        })?;
+
+    let mut features: Vec<String> = package.features.into_keys().collect();
+    features.sort();
+    info!("Package {} has features {features:?}", package.name);
+    Ok(features)
+}
+
+/// Builds a package once per feature, see the [module docs](self).
+pub struct FeatureMatrix {
+    options: BuildOptions,
+
+    /// Sets of features built together, `None` means every feature of the
+    /// package on its own
+    feature_sets: Option<Vec<Vec<String>>>,
+}
+
+impl FeatureMatrix {
+    pub fn for_manifest(manifest_path: impl Into<Utf8PathBuf>) -> Self {
+        Self::new(BuildOptions::for_manifest(manifest_path))
+    }
+
+    pub fn new(options: BuildOptions) -> Self {
+        Self {
+            options,
+            feature_sets: None,
+        }
+    }
+
+    /// Measure each of these features on its own, instead of every feature of
+    /// the package
+    pub fn features<S: Into<String>>(mut self, features: impl IntoIterator<Item = S>) -> Self {
+        let sets = features.into_iter().map(|feature| vec![feature.into()]);
+        self.feature_sets.get_or_insert_with(Vec::new).extend(sets);
+        self
+    }
+
+    /// Also measure these features enabled together, e.g. `["std", "derive"]`.
+    /// Unless [`FeatureMatrix::features`] is called too, only the combinations
+    /// are measured.
+    pub fn combination<S: Into<String>>(mut self, features: impl IntoIterator<Item = S>) -> Self {
+        let set = features.into_iter().map(Into::into).collect();
+        self.feature_sets.get_or_insert_with(Vec::new).push(set);
+        self
+    }
+
+    /// Runs the baseline build, then one build per set of features. A set that
+    /// doesn't build is reported in [`FeatureReport::failed`]; the baseline
+    /// failing is an error.
+    pub fn run(&self) -> Result<FeatureReport, SubstanceError> {
+        let feature_sets = match &self.feature_sets {
+            Some(sets) => sets.clone(),
+            None => single_features(package_features(
+                self.options.manifest_path(),
+                self.options.selected_toolchain(),
+            )?),
+        };
+
+        info!("Building baseline, without default features");
+        let baseline = self
+            .options
+            .baseline_runner()
+            .arg("--no-default-features")
+            .run()?;
+
+        let mut costs = Vec::new();
+        let mut failed = Vec::new();
+        for (index, features) in feature_sets.into_iter().enumerate() {
+            info!("Building with features {features:?}");
+            let runner = self
+                .options
+                .variant_runner(index, &features.join("+"))
+                .arg("--no-default-features")
+                .arg("--features")
+                .arg(features.join(","));
+            match runner.run() {
+                Ok(result) => costs.push(FeatureCost {
+                    delta: BuildDelta::between(baseline.context(), result.context()),
+                    features,
+                    result,
+                }),
+                Err(e) => {
+                    warn!("Features {features:?} don't build on their own: {e}");
+                    failed.push((features, e));
+                }
+            }
+        }
+        costs.sort_by(|a, b| {
+            b.delta
+                .text_size
+                .cmp(&a.delta.text_size)
+                .then_with(|| a.features.cmp(&b.features))
+        });
+
+        Ok(FeatureReport {
+            baseline,
+            features: costs,
+            failed,
+        })
+    }
+}
+
+/// Every feature on its own, but `default`: it only enables other features,
+/// which are measured anyway
+fn single_features(features: Vec<String>) -> Vec<Vec<String>> {
+    features
+        .into_iter()
+        .filter(|feature| feature != "default")
+        .map(|feature| vec![feature])
+        .collect()
+}
+
+/// Result of [`FeatureMatrix::run`]
+pub struct FeatureReport {
+    /// Build without default features
+    pub baseline: BuildResult,
+
+    /// One entry per set of features that built, the largest `.text` increase
+    /// first
+    pub features: Vec<FeatureCost>,
+
+    /// Sets of features that failed to build on their own, e.g. because
+    /// they're only meant to be used along with another feature
+    pub failed: Vec<(Vec<String>, SubstanceError)>,
+}
+
+/// What enabling a set of features adds to the baseline
+pub struct FeatureCost {
+    pub features: Vec<String>,
+
+    /// The build with only these features enabled
+    pub result: BuildResult,
+
+    /// Change from the baseline
+    pub delta: BuildDelta,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_sets() {
+        let package = ["default", "derive", "std"].map(String::from).to_vec();
+        assert_eq!(single_features(package), [["derive"], ["std"]]);
+
+        let matrix = FeatureMatrix::for_manifest("/app/Cargo.toml")
+            .features(["std"])
+            .combination(["std", "derive"]);
+        assert_eq!(
+            matrix.feature_sets,
+            Some(vec![
+                vec!["std".to_string()],
+                vec!["std".to_string(), "derive".to_string()]
+            ])
+        );
+    }
+}
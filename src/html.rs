@@ -0,0 +1,271 @@
+//! Self-contained HTML report with a treemap of the binary.
+//!
+//! The treemap goes crate → module path → symbol, from the demangled names of
+//! [`Crate::symbols`] and [`Crate::llvm_functions`]. It can be sized by
+//! machine code bytes, LLVM IR lines or copies, searched, and zoomed into by
+//! clicking. With two contexts, both are shown side by side at the same path.
+//!
+//! The page has no external dependencies: styles, script and data are inline,
+//! so the file can be opened offline or attached to a CI run.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::types::{BuildContext, Crate};
+
+const TEMPLATE: &str = include_str!("treemap.html");
+
+/// Writes a treemap of `context` as an HTML page
+pub fn write_treemap(context: &BuildContext, mut writer: impl Write) -> io::Result<()> {
+    let title = format!("substance: {}", context.artifact.name);
+    writer.write_all(render(&title, &[(context.artifact.name.as_str(), context)]).as_bytes())
+}
+
+/// Writes treemaps of two builds side by side, as an HTML page. Sizes in the
+/// "after" treemap are labeled with their change from the "before" one.
+pub fn write_treemap_comparison(
+    before: &BuildContext,
+    after: &BuildContext,
+    mut writer: impl Write,
+) -> io::Result<()> {
+    let title = format!(
+        "substance: {} vs {}",
+        before.artifact.name, after.artifact.name
+    );
+    writer.write_all(render(&title, &[("Before", before), ("After", after)]).as_bytes())
+}
+
+fn render(title: &str, panes: &[(&str, &BuildContext)]) -> String {
+    let mut data = String::from("[");
+    for (index, (label, context)) in panes.iter().enumerate() {
+        if index > 0 {
+            data.push(',');
+        }
+        data.push_str("{\"label\":");
+        push_json_string(&mut data, label);
+        data.push_str(",\"tree\":");
+        tree(context).push_json(&mut data, "all crates");
+        data.push('}');
+    }
+    data.push(']');
+
+    TEMPLATE
+        .replace("__TITLE__", &escape_html(title))
+        .replace("__DATA__", &data)
+}
+
+/// A crate, module or symbol. Values of inner nodes are the sums of their
+/// children's.
+#[derive(Debug, Default, PartialEq)]
+struct Node {
+    bytes: u64,
+    lines: u64,
+
+    /// Symbols with this path in the binary, e.g. one per instantiation
+    instances: u64,
+
+    /// Copies of the LLVM function with this path
+    llvm_copies: u64,
+
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn entry(&mut self, path: &[String]) -> &mut Node {
+        path.iter().fold(self, |node, segment| {
+            node.children.entry(segment.clone()).or_default()
+        })
+    }
+
+    /// Copies of a symbol: its LLVM copies if there is LLVM IR for it,
+    /// otherwise how many times it's in the binary
+    fn copies(&self) -> u64 {
+        if self.children.is_empty() {
+            if self.llvm_copies > 0 {
+                self.llvm_copies
+            } else {
+                self.instances
+            }
+        } else {
+            self.children.values().map(Node::copies).sum()
+        }
+    }
+
+    fn sum(&mut self) {
+        for child in self.children.values_mut() {
+            child.sum();
+        }
+        // A function can have items nested in it, e.g. `main` and `main::{{closure}}`
+        let has_own = self.bytes > 0 || self.lines > 0 || self.instances > 0;
+        if !self.children.is_empty() && has_own {
+            let own = Node {
+                bytes: self.bytes,
+                lines: self.lines,
+                instances: self.instances,
+                llvm_copies: self.llvm_copies,
+                children: BTreeMap::new(),
+            };
+            self.children.insert("(self)".to_string(), own);
+        }
+        if !self.children.is_empty() {
+            self.bytes = self.children.values().map(|c| c.bytes).sum();
+            self.lines = self.children.values().map(|c| c.lines).sum();
+        }
+    }
+
+    /// `[name, bytes, lines, copies, children]`, with `children` left out for
+    /// symbols
+    fn push_json(&self, out: &mut String, name: &str) {
+        out.push('[');
+        push_json_string(out, name);
+        out.push_str(&format!(",{},{},{}", self.bytes, self.lines, self.copies()));
+        if !self.children.is_empty() {
+            out.push_str(",[");
+            for (index, (name, child)) in self.children.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                child.push_json(out, name);
+            }
+            out.push(']');
+        }
+        out.push(']');
+    }
+}
+
+fn tree(context: &BuildContext) -> Node {
+    let mut root = Node::default();
+    for krate in &context.crates {
+        add_crate(&mut root, krate);
+    }
+    root.sum();
+    root
+}
+
+fn add_crate(root: &mut Node, krate: &Crate) {
+    let crate_node = root.children.entry(krate.name.to_string()).or_default();
+    let segments = |name: &str| {
+        let mut segments = split_path(name);
+        if segments.len() > 1 && segments[0] == krate.name.as_str() {
+            segments.remove(0);
+        }
+        segments
+    };
+
+    for symbol in krate.symbols.values() {
+        let leaf = crate_node.entry(&segments(symbol.name.strip_hash().as_str()));
+        leaf.bytes += symbol.size.value();
+        leaf.instances += 1;
+    }
+    for (name, function) in &krate.llvm_functions {
+        let leaf = crate_node.entry(&segments(name.as_str()));
+        leaf.lines += function.lines.value() as u64;
+        leaf.llvm_copies += function.copies.value() as u64;
+    }
+}
+
+/// Splits a demangled path on the `::` that aren't within `<...>`, so that
+/// `<alloc::vec::Vec<T> as core::ops::Drop>::drop` has two segments
+fn split_path(path: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let bytes = path.as_bytes();
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'<' => depth += 1,
+            // The arrow of `fn() -> T` doesn't close anything
+            b'>' if index == 0 || bytes[index - 1] != b'-' => depth = depth.saturating_sub(1),
+            b':' if depth == 0 && bytes.get(index + 1) == Some(&b':') => {
+                segments.push(path[start..index].to_string());
+                index += 2;
+                start = index;
+                continue;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    segments.push(path[start..].to_string());
+    segments.retain(|s| !s.is_empty());
+    segments
+}
+
+/// Escapes a JSON string so that it can also go in a `<script>` element
+fn push_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '<' | '>' | '&' | '\u{2028}' | '\u{2029}' => {
+                out.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_path() {
+        assert_eq!(split_path("app::config::load"), ["app", "config", "load"]);
+        assert_eq!(
+            split_path("<alloc::vec::Vec<T> as core::ops::drop::Drop>::drop"),
+            ["<alloc::vec::Vec<T> as core::ops::drop::Drop>", "drop"]
+        );
+        assert_eq!(
+            split_path("core::ptr::drop_in_place<fn() -> u8>::{{closure}}"),
+            ["core", "ptr", "drop_in_place<fn() -> u8>", "{{closure}}"]
+        );
+    }
+
+    #[test]
+    fn test_tree() {
+        let krate = Crate::named("app")
+            .with_symbol("app::config::load::h0123456789abcdef", 100)
+            .with_symbol("app::config::load::hfedcba9876543210", 60)
+            .with_symbol("app::main", 40)
+            .with_llvm_function("app::main", 25, 3);
+        let context = BuildContext::for_test(1000, vec![krate]);
+
+        let root = tree(&context);
+        let app = &root.children["app"];
+        assert_eq!((root.bytes, root.lines, root.copies()), (200, 25, 5));
+        assert_eq!(app.children["config"].children["load"].copies(), 2);
+
+        let mut json = String::new();
+        app.push_json(&mut json, "app");
+        assert_eq!(
+            json,
+            r#"["app",200,25,5,[["config",160,0,2,[["load",160,0,2]]],["main",40,25,3]]]"#
+        );
+
+        let html = render("<app>", &[("app", &context)]);
+        assert!(html.contains("<title>&lt;app&gt;</title>"));
+        assert!(!html.contains("__DATA__"));
+        assert!(!html.contains("://"), "the page should be self-contained");
+    }
+
+    #[test]
+    fn test_json_string() {
+        let mut out = String::new();
+        push_json_string(&mut out, "<T as \"x\"\\y>");
+        assert_eq!(out, r#""\u003cT as \"x\"\\y\u003e""#);
+    }
+}
@@ -1,4 +1,11 @@
-use owo_colors::OwoColorize;
+pub use analyzer::Analyzer;
+pub use diff::{
+    AnalysisComparison, ChangeKind, CrateChange, DiffOptions, FileSizeDiff, LlvmFunctionChange,
+    SymbolChange,
+};
+pub use observer::{BuildObserver, BuildPhase, CancellationToken, PhaseSpan};
+pub use profile::ProfileSettings;
+pub use snapshot::SnapshotOptions;
 pub use types::*;
 
 use camino::{Utf8Path, Utf8PathBuf};
@@ -6,26 +13,48 @@ use ignore::WalkBuilder;
 
 use std::collections::HashMap;
 use std::process::{Command, Stdio};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use binfarce::ar;
 use log::{debug, error, info, trace, warn};
 
-use crate::cargo::{CargoMessage, TimingInfo};
-use crate::crate_name::StdHandling;
-use crate::env::{collect_rlib_paths, stdlibs_dir};
+use crate::analyzer::{Analysis, AnalysisInput};
+use crate::cargo::{CargoMessage, Diagnostic, TimingInfo};
+use crate::critical_path::{CriticalPath, UnitGraph};
 use crate::errors::SubstanceError;
-use crate::llvm_ir::analyze_llvm_ir_from_target_dir;
-use crate::object::{collect_deps_symbols, collect_self_data};
+use crate::observer::Hooks;
+use crate::proc_macro::{ProcMacroCrate, ProcMacroExpansion};
+use crate::self_profile::SelfProfile;
 
+pub mod analyzer;
+pub mod budgets;
 pub mod cargo;
 pub mod crate_name;
+pub mod critical_path;
+pub mod diff;
 pub mod env;
 pub mod errors;
+pub mod features;
 pub mod formatting;
+pub mod html;
 pub mod llvm_ir;
+#[cfg(feature = "markdown")]
+pub mod markdown;
+pub mod matrix;
 pub mod object;
+pub mod observer;
+pub mod proc_macro;
+pub mod profile;
+pub mod profile_matrix;
+pub mod pruning;
 pub mod reporting;
+pub mod rustflags;
+pub mod self_profile;
+pub mod snapshot;
+pub mod toolchains;
+pub mod trace;
+pub mod type_layout;
 pub mod types;
 
 pub struct BuildRunner {
@@ -37,43 +66,186 @@ pub struct BuildRunner {
 
     /// Flags like `--bin blah`, or `--example bleh` etc.
     additional_args: Vec<String>,
+
+    /// Passed as `--target`, `None` builds for the host
+    target: Option<TargetTriple>,
+
+    /// Rustup toolchain, passed as `+toolchain`. `None` uses the default one.
+    toolchain: Option<String>,
+
+    /// Rustflags added on top of the user's and ours
+    extra_rustflags: Vec<String>,
+
+    /// Rustflags removed from the final set
+    removed_rustflags: Vec<String>,
+
+    /// Passed as `--profile`, `None` lets cargo pick (`dev`, unless `--release` is passed)
+    profile: Option<String>,
+
+    /// `(key, value)` settings of the profile, passed as `CARGO_PROFILE_*` variables
+    profile_overrides: Vec<(String, String)>,
+
+    /// `(package, key, value)` settings of the profile for one package
+    package_profile_overrides: Vec<(String, String, String)>,
+
+    hooks: Hooks,
+
+    /// Cargo is killed if the build takes longer than this
+    timeout: Option<Duration>,
+
+    /// Don't forward cargo's output and compiler diagnostics to our stderr
+    quiet: bool,
+
+    /// Crates compiled with `-Zself-profile`
+    self_profiled: Vec<CrateName>,
+
+    /// Compile every crate with `-Zself-profile`
+    self_profile_all: bool,
+
+    /// Crates compiled with `-Zprint-type-sizes`
+    type_sized: Vec<CrateName>,
 }
 
-// Result of a build run with all parsed data
-pub struct BuildResult {
-    pub context: BuildContext,
-    pub timing_data: Vec<TimingInfo>,
+/// Artifacts and timings collected from a successful `cargo build`
+struct BuildOutput {
+    artifacts: Vec<Artifact>,
+
+    /// rlibs of std crates built with `-Zbuild-std`, empty otherwise
+    std_rlibs: Vec<(CrateName, Utf8PathBuf)>,
+
+    timing_infos: Vec<TimingInfo>,
+
+    /// Compiler warnings, since the build succeeded
+    diagnostics: Vec<Diagnostic>,
+    cargo_stderr: String,
+
+    unit_graph: Option<UnitGraph>,
+    metadata: BuildMetadata,
+
+    /// Profiles of the crates compiled with `-Zself-profile`
+    self_profiles: HashMap<CrateName, SelfProfile>,
+
+    /// Layouts printed by the crates compiled with `-Zprint-type-sizes`
+    type_layouts: HashMap<CrateName, HashMap<TypeName, TypeLayout>>,
+
+    /// When `cargo build` was started
+    started_at: Instant,
 }
 
-// Analysis comparison types
-#[derive(Debug, Clone)]
-pub struct AnalysisComparison {
-    pub file_size_diff: FileSizeDiff,
-    pub symbol_changes: Vec<SymbolChange>,
-    pub crate_changes: Vec<CrateChange>,
+impl BuildOutput {
+    /// Binaries, cdylibs and dylibs, i.e. everything we can analyze symbols of
+    fn linkable_artifacts(&self) -> impl Iterator<Item = &Artifact> {
+        self.artifacts.iter().filter(|a| a.kind.is_linkable())
+    }
+
+    /// The first binary, or the first cdylib/dylib if there are no binaries
+    fn main_artifact(&self) -> Option<&Artifact> {
+        self.linkable_artifacts()
+            .find(|a| a.kind == ArtifactKind::Binary)
+            .or_else(|| self.linkable_artifacts().next())
+    }
+
+    fn into_result(self, contexts: Vec<BuildContext>, hooks: &Hooks) -> BuildResult {
+        let (host_timings, target_timings) = match &self.unit_graph {
+            Some(graph) => graph.partition_timings(self.timing_infos),
+            None => self
+                .timing_infos
+                .into_iter()
+                .partition(TimingInfo::is_host_unit),
+        };
+        let (proc_macros, unattributed_expansions) = proc_macro::attribute(
+            &self.artifacts,
+            &host_timings,
+            &self.self_profiles,
+            self.unit_graph.as_ref(),
+        );
+        BuildResult {
+            contexts,
+            target_timings,
+            host_timings,
+            diagnostics: self.diagnostics,
+            cargo_stderr: self.cargo_stderr,
+            unit_graph: self.unit_graph,
+            proc_macros,
+            unattributed_expansions,
+            phases: hooks.phase_spans(self.started_at),
+            metadata: self.metadata,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct FileSizeDiff {
-    pub file_size_before: ByteSize,
-    pub file_size_after: ByteSize,
-    pub text_size_before: ByteSize,
-    pub text_size_after: ByteSize,
+// Result of a build run with all parsed data
+pub struct BuildResult {
+    /// One context per analyzed artifact, the main one first. Never empty.
+    pub contexts: Vec<BuildContext>,
+
+    /// Timings of every unit compiled for the target, in the order they finished
+    pub target_timings: Vec<TimingInfo>,
+
+    /// Timings of build scripts (compiling and running them) and proc macros,
+    /// which are built for the host. When cross-compiling, their dependencies too,
+    /// see [`UnitGraph::partition_timings`].
+    pub host_timings: Vec<TimingInfo>,
+
+    /// Diagnostics reported by rustc, i.e. warnings since the build succeeded
+    pub diagnostics: Vec<Diagnostic>,
+
+    /// Everything cargo printed to stderr, without colors
+    pub cargo_stderr: String,
+
+    /// Dependencies between units, from `cargo build --unit-graph`. `None` if
+    /// cargo couldn't provide it.
+    pub unit_graph: Option<UnitGraph>,
+
+    /// Proc-macro crates, with what they cost to build and to run, the most
+    /// expensive first. Expansion times are only known for self-profiled crates,
+    /// see [`BuildRunner::self_profile`].
+    pub proc_macros: Vec<ProcMacroCrate>,
+
+    /// Proc macros that ran in self-profiled crates, but that we couldn't tie to
+    /// a proc-macro crate
+    pub unattributed_expansions: Vec<ProcMacroExpansion>,
+
+    /// When the build and each analysis phase ran
+    pub phases: Vec<PhaseSpan>,
+
+    pub metadata: BuildMetadata,
 }
 
-#[derive(Debug, Clone)]
-pub struct SymbolChange {
-    pub name: String,
-    pub demangled: String,
-    pub size_before: Option<u64>,
-    pub size_after: Option<u64>,
+impl BuildResult {
+    /// Context of the main artifact, see [`BuildRunner::run`]
+    pub fn context(&self) -> &BuildContext {
+        &self.contexts[0]
+    }
+
+    /// Critical path through the build, from the unit graph and the timings of
+    /// both host and target units. `None` without a unit graph.
+    pub fn critical_path(&self) -> Option<CriticalPath> {
+        let graph = self.unit_graph.as_ref()?;
+        CriticalPath::compute(graph, self.target_timings.iter().chain(&self.host_timings))
+    }
 }
 
+/// How a build was run
 #[derive(Debug, Clone)]
-pub struct CrateChange {
-    pub name: String,
-    pub size_before: Option<u64>,
-    pub size_after: Option<u64>,
+pub struct BuildMetadata {
+    /// Program and arguments, e.g. `["cargo", "build", "--release", ...]`
+    pub command_line: Vec<String>,
+
+    /// Environment variables set for cargo on top of ours, e.g. `CARGO_ENCODED_RUSTFLAGS`
+    pub command_env: Vec<(String, String)>,
+
+    /// Output of `rustc --version`
+    pub toolchain_version: String,
+
+    /// The triple we built for
+    pub target_triple: TargetTriple,
+
+    /// Settings of the profile used for the build, after analysis overrides
+    pub profile: ProfileSettings,
+
+    /// Wall time of `cargo build`
+    pub wall_duration: Duration,
 }
 
 impl BuildRunner {
@@ -104,6 +276,19 @@ impl BuildRunner {
                 target_dir,
                 _temp_dir: None,
                 additional_args: Vec::new(),
+                target: None,
+                toolchain: None,
+                extra_rustflags: Vec::new(),
+                removed_rustflags: Vec::new(),
+                profile: None,
+                profile_overrides: Vec::new(),
+                package_profile_overrides: Vec::new(),
+                hooks: Hooks::default(),
+                timeout: None,
+                quiet: false,
+                self_profiled: Vec::new(),
+                self_profile_all: false,
+                type_sized: Vec::new(),
             }
         } else {
             // Generate a temporary directory for the target directory.
@@ -120,17 +305,314 @@ impl BuildRunner {
                 target_dir,
                 _temp_dir: Some(tmp_dir),
                 additional_args: Vec::new(),
+                target: None,
+                toolchain: None,
+                extra_rustflags: Vec::new(),
+                removed_rustflags: Vec::new(),
+                profile: None,
+                profile_overrides: Vec::new(),
+                package_profile_overrides: Vec::new(),
+                hooks: Hooks::default(),
+                timeout: None,
+                quiet: false,
+                self_profiled: Vec::new(),
+                self_profile_all: false,
+                type_sized: Vec::new(),
             }
         }
     }
 
+    /// Build in the subdirectory `name` of the target directory. With
+    /// `SUBSTANCE_TMP_DIR` set, every runner for a manifest gets the same target
+    /// directory, so builds with different settings that shouldn't see each
+    /// other's artifacts (like the `.ll` files we scan) need their own
+    /// subdirectory. Path separators in `name` are replaced with `_`.
+    pub fn target_subdir(mut self, name: &str) -> Self {
+        let name = name.replace(['/', '\\', ':'], "_");
+        self.target_dir = self.target_dir.join(name);
+        self
+    }
+
     /// Add an additional argument to the cargo build command.
     pub fn arg<T: Into<String>>(mut self, arg: T) -> Self {
         self.additional_args.push(arg.into());
         self
     }
 
-    pub fn run(&self) -> Result<BuildContext, SubstanceError> {
+    /// Cross-compile for the given target triple, e.g. `aarch64-unknown-linux-gnu`.
+    ///
+    /// This passes `--target` to cargo, and symbols are matched against the std
+    /// libraries of that target.
+    pub fn target(mut self, triple: impl Into<TargetTriple>) -> Self {
+        self.target = Some(triple.into());
+        self
+    }
+
+    /// Build with the given rustup toolchain, e.g. `nightly-2026-09-01` or
+    /// `stable`, which must be installed. Cargo and rustc are invoked with
+    /// `+toolchain`, and std symbols come from that toolchain's sysroot.
+    pub fn toolchain(mut self, toolchain: impl Into<String>) -> Self {
+        self.toolchain = Some(toolchain.into());
+        self
+    }
+
+    /// Build with the given profile, e.g. `release` or a custom profile. This
+    /// passes `--profile` to cargo.
+    ///
+    /// Settings that break symbol attribution (stripping symbols, disabling debug
+    /// info) are overridden, see [`ProfileSettings`].
+    pub fn profile(mut self, name: impl Into<String>) -> Self {
+        self.profile = Some(name.into());
+        self
+    }
+
+    /// Override a setting of the profile the build uses, e.g. `("opt-level", "z")`
+    /// or `("lto", "fat")`. Keys are spelled like in `Cargo.toml`, and passed to
+    /// cargo as `CARGO_PROFILE_<PROFILE>_<KEY>` environment variables.
+    ///
+    /// Analysis overrides still apply on top, see [`BuildRunner::profile`].
+    pub fn profile_override(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.profile_overrides.push((key.into(), value.into()));
+        self
+    }
+
+    /// Override a setting of the profile for one package, like
+    /// `[profile.<name>.package.<package>]` does. Cargo has no environment
+    /// variables for these, so they're passed with `--config`.
+    pub fn package_profile_override(
+        mut self,
+        package: impl Into<String>,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.package_profile_overrides
+            .push((package.into(), key.into(), value.into()));
+        self
+    }
+
+    /// Name of the profile the build will use, taking `--release` and `--profile`
+    /// passed with [`BuildRunner::arg`] into account.
+    pub fn profile_name(&self) -> String {
+        if let Some(profile) = &self.profile {
+            return profile.clone();
+        }
+
+        let mut args = self.additional_args.iter();
+        let mut name = "dev".to_string();
+        while let Some(arg) = args.next() {
+            if arg == "--release" || arg == "-r" {
+                name = "release".to_string();
+            } else if arg == "--profile" {
+                if let Some(profile) = args.next() {
+                    name = profile.clone();
+                }
+            } else if let Some(profile) = arg.strip_prefix("--profile=") {
+                name = profile.to_string();
+            }
+        }
+        name
+    }
+
+    /// Settings of the profile the build will use, with the analysis overrides applied,
+    /// along with the environment variables that apply those overrides.
+    fn profile_settings(&self) -> (ProfileSettings, Vec<(String, String)>) {
+        let root_manifest =
+            env::workspace_root_manifest(&self.manifest_path, self.toolchain.as_deref());
+        let name = self.profile_name();
+        let mut settings = ProfileSettings::resolve(&root_manifest, &name);
+        let mut overrides = Vec::new();
+        for (key, value) in &self.profile_overrides {
+            settings.set(key, value.clone());
+            overrides.push((profile::profile_env_var(&name, key), value.clone()));
+        }
+        // Later variables win, so analysis overrides take precedence
+        overrides.extend(settings.apply_analysis_overrides());
+        (settings, overrides)
+    }
+
+    /// Add a flag to pass to rustc, on top of the ones from the environment and cargo
+    /// config (`RUSTFLAGS`, `build.rustflags`, `target.<triple>.rustflags`) and the ones
+    /// substance needs, see [`rustflags::ANALYSIS_RUSTFLAGS`].
+    pub fn rustflag(mut self, flag: impl Into<String>) -> Self {
+        self.extra_rustflags.push(flag.into());
+        self
+    }
+
+    /// Remove a flag from the rustflags, whether it comes from the user's configuration
+    /// or from substance itself. Flags are matched exactly, one argument at a time.
+    pub fn remove_rustflag(mut self, flag: impl Into<String>) -> Self {
+        self.removed_rustflags.push(flag.into());
+        self
+    }
+
+    /// Report progress to `observer`, see [`BuildObserver`].
+    pub fn observer(mut self, observer: Arc<dyn BuildObserver>) -> Self {
+        self.hooks.observer = Some(observer);
+        self
+    }
+
+    /// Stop the build when `token` is cancelled: cargo is stopped if it's still
+    /// running, otherwise the analysis stops between phases, and
+    /// [`SubstanceError::Cancelled`] is returned.
+    ///
+    /// On unix, cargo then runs in its own process group, so that rustc and build
+    /// scripts are stopped with it. They no longer get the terminal's Ctrl-C, so
+    /// cancel `token` on Ctrl-C instead.
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.hooks.cancellation = Some(token);
+        self
+    }
+
+    /// Stop cargo if the build takes longer than `timeout`, and return
+    /// [`SubstanceError::TimedOut`]. The analysis afterwards is not bounded. Like
+    /// with [`BuildRunner::cancellation_token`], cargo runs in its own process
+    /// group on unix.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Don't print cargo's output and compiler diagnostics to stderr. They're
+    /// still collected, see [`BuildResult::diagnostics`] and
+    /// [`SubstanceError::CargoBuildFailed`].
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Compile `crate_name` with `-Zself-profile`, and attach a breakdown of its
+    /// compile time by query to it, see [`Crate::self_profile`]. Can be called
+    /// several times.
+    ///
+    /// Profiles are only written when the crate is actually rebuilt, so a crate
+    /// that was fresh in a reused target directory gets none.
+    pub fn self_profile(mut self, crate_name: impl Into<CrateName>) -> Self {
+        self.self_profiled.push(crate_name.into());
+        self
+    }
+
+    /// Compile every crate with `-Zself-profile`, see [`BuildRunner::self_profile`].
+    /// Profiling slows the build down, and the profiles of large crates take a
+    /// while to read.
+    pub fn self_profile_all(mut self) -> Self {
+        self.self_profile_all = true;
+        self
+    }
+
+    /// Compile `crate_name` with `-Zprint-type-sizes`, and attach the layouts of
+    /// the types it uses to it, see [`Crate::type_layouts`]. Can be called several
+    /// times.
+    ///
+    /// Rustc prints layouts to stdout without saying which crate they're from, so
+    /// they're attributed to the next of these crates to finish. With more than
+    /// one crate, the build runs one job at a time (`-j1`) so that their output
+    /// doesn't get mixed up, unless `-j` was passed with [`BuildRunner::arg`].
+    pub fn print_type_sizes(mut self, crate_name: impl Into<CrateName>) -> Self {
+        self.type_sized.push(crate_name.into());
+        self
+    }
+
+    /// Whether several crates print type sizes, which must then not be compiled
+    /// at the same time
+    fn prints_several_type_sizes(&self) -> bool {
+        let mut crate_names: Vec<&CrateName> = self.type_sized.iter().collect();
+        crate_names.sort();
+        crate_names.dedup();
+        crate_names.len() > 1
+    }
+
+    /// Whether `-j`/`--jobs` was passed with [`BuildRunner::arg`]
+    fn sets_jobs(&self) -> bool {
+        self.additional_args
+            .iter()
+            .any(|arg| arg.starts_with("-j") || arg.starts_with("--jobs"))
+    }
+
+    fn self_profiles_anything(&self) -> bool {
+        self.self_profile_all || !self.self_profiled.is_empty()
+    }
+
+    /// Returns the rustflags the build will use: the user's, resolved like cargo
+    /// would, then substance's, then the ones added with [`BuildRunner::rustflag`].
+    pub fn effective_rustflags(&self) -> Vec<String> {
+        let cwd = self.manifest_path.parent().unwrap_or(Utf8Path::new("."));
+        let target = match &self.target {
+            Some(target) => Some(target.clone()),
+            None => env::host_triple(self.toolchain.as_deref())
+                .map_err(|e| warn!("Could not detect host triple: {e}"))
+                .ok(),
+        };
+        let user = rustflags::user_rustflags(cwd, target.as_deref(), self.toolchain.as_deref());
+
+        let extra: Vec<String> = rustflags::ANALYSIS_RUSTFLAGS
+            .iter()
+            .map(|s| s.to_string())
+            .chain(self.extra_rustflags.iter().cloned())
+            .collect();
+        rustflags::merge(user, &extra, &self.removed_rustflags)
+    }
+
+    /// Builds the project and analyzes its main artifact: the first binary, or
+    /// the first cdylib/dylib if there are no binaries.
+    ///
+    /// See [`BuildRunner::run_all`] to analyze every linked artifact.
+    pub fn run(&self) -> Result<BuildResult, SubstanceError> {
+        self.hooks.finished(self.run_main())
+    }
+
+    fn run_main(&self) -> Result<BuildResult, SubstanceError> {
+        let hooks = self.hooks.for_run();
+        let output = self.build(&hooks)?;
+        let analysis = self.analysis(&output, &hooks)?;
+
+        info!("Locating main artifact for analysis...");
+        let main_artifact = output
+            .main_artifact()
+            .ok_or(SubstanceError::UnsupportedCrateType)?;
+        info!(
+            "Main artifact found: {} ({:?}, path: {})",
+            main_artifact.name, main_artifact.kind, main_artifact.path
+        );
+
+        let context = analysis.analyze_binary(main_artifact)?;
+        Ok(output.into_result(vec![context], &hooks))
+    }
+
+    /// Builds the project and analyzes every linked artifact it produces: all
+    /// binaries, cdylibs and dylibs. The main artifact comes first, then the
+    /// others in the order cargo reported them.
+    pub fn run_all(&self) -> Result<BuildResult, SubstanceError> {
+        self.hooks.finished(self.run_every())
+    }
+
+    fn run_every(&self) -> Result<BuildResult, SubstanceError> {
+        let hooks = self.hooks.for_run();
+        let output = self.build(&hooks)?;
+        let analysis = self.analysis(&output, &hooks)?;
+
+        let main_artifact = output
+            .main_artifact()
+            .ok_or(SubstanceError::UnsupportedCrateType)?;
+        let artifacts = std::iter::once(main_artifact).chain(
+            output
+                .linkable_artifacts()
+                .filter(|a| !std::ptr::eq(*a, main_artifact)),
+        );
+
+        let contexts = artifacts
+            .map(|artifact| {
+                info!(
+                    "Analyzing artifact: {} ({:?}, path: {})",
+                    artifact.name, artifact.kind, artifact.path
+                );
+                analysis.analyze_binary(artifact)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(output.into_result(contexts, &hooks))
+    }
+
+    /// Runs `cargo build` and collects its artifacts and timings.
+    fn build(&self, hooks: &Hooks) -> Result<BuildOutput, SubstanceError> {
         // Ensure manifest exists
         if !self.manifest_path.exists() {
             error!("Manifest file not found: {:?}", self.manifest_path);
@@ -139,14 +621,67 @@ impl BuildRunner {
 
         info!("Building project from manifest: {:?}", self.manifest_path);
         info!("Target directory: {:?}", self.target_dir);
+        hooks.check_cancelled()?;
+
+        let (profile, profile_overrides) = self.profile_settings();
+        info!("Profile: {profile:?}");
+
+        let target_triple = match &self.target {
+            Some(target) => target.clone(),
+            None => env::host_triple(self.toolchain.as_deref())?,
+        };
+        let toolchain_version = env::rustc_version(self.toolchain.as_deref())?;
+        info!("Toolchain: {toolchain_version} ({target_triple})");
 
-        let mut cmd = self.build_command();
+        if self.prints_several_type_sizes() && self.sets_jobs() {
+            warn!(
+                "Several crates print type sizes, and the build runs several jobs at once: \
+                 layouts of crates that finish at the same time will be mixed up"
+            );
+        }
+
+        hooks.check_cancelled()?;
+        let rustflags = self.effective_rustflags();
+        debug!("Effective rustflags: {rustflags:?}");
+
+        hooks.check_cancelled()?;
+        let unit_graph = self.unit_graph(&profile_overrides, &rustflags);
+        let mut cmd = self.build_command(&profile_overrides, &rustflags);
+        let self_profile_dir = self.target_dir.join("self-profile");
+        // Don't pick up profiles of a previous build
+        if self.self_profiles_anything() && self_profile_dir.exists() {
+            std::fs::remove_dir_all(&self_profile_dir).map_err(|e| {
+                SubstanceError::CargoError(format!("Failed to clear {self_profile_dir}: {e}"))
+            })?;
+        }
+        self.add_profile_rustflags_args(&mut cmd, &profile.name, &self_profile_dir);
+        let command_line = std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let command_env = cmd
+            .get_envs()
+            .filter_map(|(k, v)| {
+                Some((
+                    k.to_string_lossy().into_owned(),
+                    v?.to_string_lossy().into_owned(),
+                ))
+            })
+            .collect();
 
-        // Execute the build and forward stdout/stderr to the parent's stdout/stderr as it happens,
-        // using two threads, but only collect JSON lines from stdout.
+        // Execute the build, reading stdout/stderr from two threads as it happens. JSON
+        // messages are parsed from stdout, stderr is collected, and both are forwarded to
+        // our stderr unless we're quiet.
 
+        hooks.enter_phase(BuildPhase::Build)?;
         let before_build = Instant::now();
 
+        // In its own process group, so that it can be stopped with what it spawned
+        #[cfg(unix)]
+        if hooks.cancellation.is_some() || self.timeout.is_some() {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+        }
         let mut cmd = cmd
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
@@ -164,110 +699,211 @@ impl BuildRunner {
 
         struct StdoutResult {
             artifacts: Vec<Artifact>,
+            std_rlibs: Vec<(CrateName, Utf8PathBuf)>,
             timing_infos: Vec<TimingInfo>,
+            diagnostics: Vec<Diagnostic>,
+            type_layouts: HashMap<CrateName, HashMap<TypeName, TypeLayout>>,
         }
 
-        // Thread for stdout: collect JSON lines, print them, and return Vec<CargoMessage>.
+        let quiet = self.quiet;
+        let type_sized = self.type_sized.clone();
+
+        // Thread for stdout: parse JSON lines into artifacts, timings and diagnostics.
+        let stdout_hooks = hooks.clone();
         let stdout_handle = thread::spawn(move || {
             // Parse cargo messages to extract artifacts
             let mut artifacts = Vec::new();
+            let mut std_rlibs = Vec::new();
             let mut timing_infos = Vec::new();
+            let mut diagnostics = Vec::new();
+            let mut type_layouts: HashMap<CrateName, HashMap<TypeName, TypeLayout>> =
+                HashMap::new();
+            // `-Zprint-type-sizes` output of the next crate to finish
+            let mut type_size_lines: Vec<String> = Vec::new();
 
             let reader = BufReader::new(stdout);
 
             for line_result in reader.lines() {
                 let Ok(line) = line_result else { continue };
+                if line.starts_with(type_layout::LINE_PREFIX) {
+                    type_size_lines.push(line);
+                    continue;
+                }
                 let msg = match CargoMessage::parse(&line) {
                     Ok(msg) => msg,
                     Err(err) => {
-                        eprintln!("Failed to parse cargo message: {err}.\nLine: {line}");
+                        warn!("Failed to parse cargo message: {err}.\nLine: {line}");
                         continue;
                     }
                 };
                 let Some(msg) = msg else {
-                    eprintln!("Received cargo JSON message: {line}");
+                    trace!("Received cargo JSON message: {line}");
                     continue;
                 };
+                stdout_hooks.cargo_message(&msg);
 
                 match msg {
-                    CargoMessage::TimingInfo(timing_info) => {
+                    CargoMessage::TimingInfo(mut timing_info) => {
+                        timing_info.finished_at = Some(before_build.elapsed());
                         timing_infos.push(timing_info);
                     }
-                    CargoMessage::CompilerArtifact(artifact) => {
-                        let kind = {
-                            // Try to guess artifact kind from its file extension (best effort).
-                            let path = &artifact
-                                .filenames
-                                .first()
-                                .expect("No filename in CompilerArtifact");
-                            if let Some(ext) = path.extension() {
-                                match ext {
-                                    "rlib" | "lib" => ArtifactKind::Library,
-                                    "dylib" | "so" | "dll" => ArtifactKind::DynLib,
-                                    "exe" | "bin" => ArtifactKind::Binary,
-                                    _ => ArtifactKind::Binary, // fallback
-                                }
-                            } else {
-                                ArtifactKind::Binary
+                    CargoMessage::CompilerArtifact(artifact) if artifact.build_std => {
+                        for std_artifact in artifact.artifacts() {
+                            trace!(
+                                "Found build-std artifact: {:?} - {} at {}",
+                                std_artifact.kind,
+                                std_artifact.name,
+                                std_artifact.path
+                            );
+                            stdout_hooks.artifact(&std_artifact);
+                            if std_artifact.kind == ArtifactKind::Library {
+                                std_rlibs.push((std_artifact.name, std_artifact.path));
                             }
-                        };
-
-                        for filename in &artifact.filenames {
-                            let artifact_struct = Artifact {
-                                kind,
-                                name: artifact.crate_name.clone(),
-                                path: filename.clone(),
-                            };
+                        }
+                    }
+                    CargoMessage::CompilerArtifact(artifact) => {
+                        if type_sized.contains(&artifact.crate_name) && !type_size_lines.is_empty()
+                        {
+                            let layouts = type_layout::parse_type_sizes(
+                                type_size_lines.iter().map(String::as_str),
+                            );
+                            type_size_lines.clear();
+                            type_layouts
+                                .entry(artifact.crate_name.clone())
+                                .or_default()
+                                .extend(layouts);
+                        }
+                        for artifact_struct in artifact.artifacts() {
                             trace!(
                                 "Found artifact: {:?} - {} at {}",
                                 artifact_struct.kind,
                                 artifact_struct.name,
-                                filename
+                                artifact_struct.path
                             );
+                            stdout_hooks.artifact(&artifact_struct);
                             artifacts.push(artifact_struct);
                         }
                     }
+                    CargoMessage::CompilerMessage(diagnostic) => {
+                        if !quiet {
+                            if let Some(rendered) = &diagnostic.rendered {
+                                eprint!("{rendered}");
+                            }
+                        }
+                        diagnostics.push(diagnostic);
+                    }
                 }
             }
 
             StdoutResult {
                 artifacts,
+                std_rlibs,
                 timing_infos,
+                diagnostics,
+                type_layouts,
             }
         });
 
-        // Thread for stderr: print to parent's stderr, but DO NOT collect lines.
+        // Thread for stderr: collect lines, and print them to parent's stderr.
         let stderr_handle = thread::spawn(move || {
             let reader = BufReader::new(stderr);
+            let mut collected = String::new();
             for line in reader.lines().map_while(Result::ok) {
-                eprintln!("{line}");
+                if !quiet {
+                    eprintln!("{line}");
+                }
+                collected.push_str(&cargo::strip_ansi_escapes(&line));
+                collected.push('\n');
             }
+            collected
         });
 
-        // Wait for the command to finish
-        let status = cmd.wait().map_err(|e| {
+        // Wait for the command to finish, or kill it if we're cancelled or out of time
+        let wait_error = |e: std::io::Error| {
             error!("Failed to wait for cargo: {e}");
             SubstanceError::CargoError(format!("Failed to wait for cargo: {e}"))
-        })?;
+        };
+        let mut interrupted = None;
+        let status = loop {
+            if let Some(status) = cmd.try_wait().map_err(wait_error)? {
+                break status;
+            }
+            if hooks.is_cancelled() {
+                interrupted = Some(SubstanceError::Cancelled);
+            } else if let Some(timeout) = self.timeout.filter(|t| before_build.elapsed() > *t) {
+                interrupted = Some(SubstanceError::TimedOut(timeout));
+            }
+            if let Some(err) = &interrupted {
+                warn!("Stopping cargo: {err}");
+                break stop_cargo(&mut cmd).map_err(wait_error)?;
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
 
         let wall_duration = before_build.elapsed();
 
         // Wait for both threads to finish reading
         let stdout_result = stdout_handle.join().unwrap();
-        let _ = stderr_handle.join();
+        let cargo_stderr = stderr_handle.join().unwrap_or_default();
+
+        if let Some(err) = interrupted {
+            return Err(err);
+        }
 
         if !status.success() {
             error!("Cargo build failed with status: {status:?}");
-            // Stderr was already streamed, so we don't print it again here
-            return Err(SubstanceError::CargoBuildFailed);
+            // Stderr was already streamed (unless quiet), so we don't print it again here
+            return Err(SubstanceError::CargoBuildFailed {
+                diagnostics: stdout_result.diagnostics,
+                stderr: cargo_stderr,
+            });
         }
 
         info!("Cargo build completed successfully");
 
+        let self_profiles = if self.self_profiles_anything() {
+            self_profile::read_profiles(&self_profile_dir)
+        } else {
+            HashMap::new()
+        };
+        for crate_name in &self.self_profiled {
+            if !self_profiles.contains_key(crate_name) {
+                warn!("No self-profile was written for {crate_name}, was it rebuilt?");
+            }
+        }
+        for crate_name in &self.type_sized {
+            if !stdout_result.type_layouts.contains_key(crate_name) {
+                warn!("No type sizes were printed for {crate_name}, was it rebuilt?");
+            }
+        }
+
+        Ok(BuildOutput {
+            artifacts: stdout_result.artifacts,
+            std_rlibs: stdout_result.std_rlibs,
+            timing_infos: stdout_result.timing_infos,
+            diagnostics: stdout_result.diagnostics,
+            cargo_stderr,
+            unit_graph,
+            self_profiles,
+            type_layouts: stdout_result.type_layouts,
+            started_at: before_build,
+            metadata: BuildMetadata {
+                command_line,
+                command_env,
+                toolchain_version,
+                target_triple,
+                profile,
+                wall_duration,
+            },
+        })
+    }
+
+    /// Prepares the data shared by all artifacts of a build.
+    fn analysis(&self, output: &BuildOutput, hooks: &Hooks) -> Result<Analysis, SubstanceError> {
         // Collect rlib paths from artifacts
         let mut rlib_paths: Vec<(CrateName, Utf8PathBuf)> = Vec::new();
         let mut dep_crates = Vec::new();
-        for artifact in &stdout_result.artifacts {
+        for artifact in &output.artifacts {
             dep_crates.push(artifact.name.clone());
 
             if matches!(artifact.kind, ArtifactKind::Library) {
@@ -275,224 +911,252 @@ impl BuildRunner {
             }
         }
 
-        dep_crates.dedup();
         dep_crates.sort();
+        dep_crates.dedup();
 
-        // Get std crates - always collect them since we can't tell if build-std was used from JSON
-        let target_dylib_path = stdlibs_dir()?;
-        let std_paths = collect_rlib_paths(&target_dylib_path);
-
-        let mut std_crates: Vec<CrateName> = std_paths.iter().map(|v| v.0.clone()).collect();
-        rlib_paths.extend_from_slice(&std_paths);
-        std_crates.sort();
-
-        // Remove std crates that were explicitly added as dependencies.
-        for c in &dep_crates {
-            if let Some(idx) = std_crates.iter().position(|v| v == c) {
-                std_crates.remove(idx);
-            }
+        // With `--target`, cargo puts target artifacts under `target/<triple>/`,
+        // and everything under `target/debug` etc. was built for the host. Other
+        // profiles may have left IR around too.
+        let llvm_ir_dir = match &self.target {
+            Some(triple) => self.target_dir.join(triple.as_str()),
+            None => self.target_dir.clone(),
         }
+        .join(output.metadata.profile.dir_name());
 
-        // Build symbol mapping
-        info!("Building dependency symbol mapping...");
-        let deps_symbols = collect_deps_symbols(rlib_paths)?;
-        debug!("Collected symbols for {} dependencies.", deps_symbols.len());
-
-        // Find the binary artifact first, filtering out build scripts
-        info!("Locating binary artifact for analysis (excluding build-script-build)...");
-        let binary_artifact = stdout_result
-            .artifacts
-            .into_iter()
-            .find(|a| {
-                matches!(a.kind, ArtifactKind::Binary) && a.name.as_str() != "build-script-build"
-            })
-            .ok_or(SubstanceError::CargoError(
-                "No binary artifact found (all were build-script-build or missing).".to_string(),
-            ))?;
-        info!(
-            "Binary artifact found: {} (path: {})",
-            binary_artifact.name, binary_artifact.path
-        );
-
-        // Get file size of the binary
-        let file_metadata = std::fs::metadata(&binary_artifact.path)
-            .map_err(|_| SubstanceError::OpenFailed(binary_artifact.path.clone()))?;
-        let file_size = ByteSize::new(file_metadata.len());
-        info!("Binary file size: {} bytes", file_size.value().yellow());
-
-        info!(
-            "Collecting self data (.text section) from binary artifact: {}",
-            binary_artifact.path.blue()
-        );
-        let raw_data = collect_self_data(&binary_artifact.path, ".text")?;
-        let text_size = ByteSize::new(raw_data.text_size);
-        debug!(
-            "Collected self data for binary artifact (.text section size: {} bytes).",
-            text_size.value().green()
-        );
+        if !output.std_rlibs.is_empty() {
+            info!(
+                "Build used -Zbuild-std: taking {} std crates from the build instead of the sysroot",
+                output.std_rlibs.len()
+            );
+        }
 
-        let mut context = BuildContext {
-            std_crates,
+        Analysis::new(AnalysisInput {
+            rlib_paths,
             dep_crates,
-            deps_symbols,
-            wall_duration,
-            file_size,
-            text_size,
-            crates: Default::default(),
-        };
+            std_rlibs: (!output.std_rlibs.is_empty()).then(|| output.std_rlibs.clone()),
+            llvm_ir_dir: &llvm_ir_dir,
+            target: Some(&output.metadata.target_triple),
+            toolchain: self.toolchain.as_deref(),
+            profile: Some(output.metadata.profile.clone()),
+            timing_infos: &output.timing_infos,
+            wall_duration: Some(output.metadata.wall_duration),
+            self_profiles: output.self_profiles.clone(),
+            type_layouts: output.type_layouts.clone(),
+            hooks: hooks.clone(),
+        })
+    }
 
-        // Analyze LLVM IR (if any) for this crate from the target dir
-        info!(
-            "Analyzing LLVM IR files (if present) in target dir: {}",
-            self.target_dir.blue()
-        );
-        let llvm_functions =
-            analyze_llvm_ir_from_target_dir(&self.target_dir).unwrap_or_else(|err| {
-                warn!(
-                    "Failed to analyze LLVM IR files: {}. Continuing without LLVM IR data.",
-                    err.red()
-                );
-                HashMap::new()
-            });
+    fn build_command(
+        &self,
+        profile_overrides: &[(String, String)],
+        rustflags: &[String],
+    ) -> Command {
+        let mut cmd = self.cargo_build(profile_overrides, rustflags);
 
-        info!(
-            "LLVM IR analysis: found {} LLVM functions.",
-            llvm_functions.len().bright_purple()
-        );
+        // Add required flags for analysis
+        cmd.args([
+            "--message-format=json",
+            "-Z",
+            "binary-dep-depinfo",
+            "-Z",
+            "checksum-freshness",
+            "--timings=json",
+        ]);
 
-        // Compute build times per crate.
-        let mut crate_build_times: HashMap<CrateName, Duration> = HashMap::new();
-        for timing in &stdout_result.timing_infos {
-            let crate_name = timing
-                .target
-                .name
-                .clone()
-                .map(CrateName::from)
-                .unwrap_or_else(|| CrateName::from("unknown"));
-            crate_build_times
-                .entry(crate_name)
-                .or_insert_with(|| Duration::from_secs_f64(timing.duration));
-        }
+        cmd
+    }
 
-        // Build crate information from the collected data
-        let mut crates_map: HashMap<CrateName, Crate> = HashMap::new();
-
-        // Process binary symbols and group by crate
-        for symbol in raw_data.symbols {
-            let (crate_name, _exact) =
-                crate_name::from_sym(&context, StdHandling::Merged, &symbol.name);
-            let demangled_symbol = DemangledSymbol::from(symbol.name.complete);
-            let symbol_obj = Symbol {
-                name: demangled_symbol.clone(),
-                size: ByteSize::new(symbol.size),
-            };
-
-            crates_map
-                .entry(crate_name)
-                .or_insert_with(|| Crate {
-                    name: CrateName::from(""),
-                    symbols: HashMap::new(),
-                    llvm_functions: HashMap::new(),
-                    timing_info: None,
-                })
-                .symbols
-                .insert(demangled_symbol, symbol_obj);
+    /// Passes `-Zself-profile` and `-Zprint-type-sizes` to the crates that need
+    /// them, through profile rustflags. Self-profile events are recorded with their
+    /// arguments, to know which proc macro ran.
+    ///
+    /// Packages are named after crates, with `-` or `_`, so both spellings are
+    /// configured and cargo warns about the one that doesn't match.
+    fn add_profile_rustflags_args(
+        &self,
+        cmd: &mut Command,
+        profile: &str,
+        self_profile_dir: &Utf8Path,
+    ) {
+        if !self.self_profiles_anything() && self.type_sized.is_empty() {
+            return;
         }
+        let self_profile_flags = [
+            format!("-Zself-profile={self_profile_dir}"),
+            "-Zself-profile-events=default,args".to_string(),
+        ];
+        let toml_array = |flags: &[String]| {
+            let flags: Vec<String> = flags
+                .iter()
+                .map(|flag| format!("\"{}\"", flag.replace('\\', "\\\\").replace('"', "\\\"")))
+                .collect();
+            format!("[{}]", flags.join(", "))
+        };
 
-        // Process LLVM functions and group by crate
-        for (llvm_fn_name, llvm_fn) in llvm_functions {
-            // Extract crate name from the function path using robust logic
-            let crate_name = {
-                let crate_string = crate_name::extract_crate_from_function(&llvm_fn_name);
-                if crate_string == "unknown" {
-                    // Fallback to binary artifact name as main crate
-                    binary_artifact.name.clone()
-                } else {
-                    CrateName::from(crate_string)
-                }
-            };
-
-            // Update the LlvmFunction with its proper name
-            let mut llvm_fn_with_name = llvm_fn;
-            llvm_fn_with_name.name = llvm_fn_name.clone();
-
-            crates_map
-                .entry(crate_name)
-                .or_insert_with(|| Crate {
-                    name: CrateName::from(""),
-                    symbols: HashMap::new(),
-                    llvm_functions: HashMap::new(),
-                    timing_info: None,
-                })
-                .llvm_functions
-                .insert(llvm_fn_name, llvm_fn_with_name);
+        cmd.args(["-Z", "profile-rustflags"]);
+        if self.self_profile_all {
+            cmd.arg("--config");
+            cmd.arg(format!(
+                "profile.{profile}.rustflags={}",
+                toml_array(&self_profile_flags)
+            ));
         }
-        // Set the proper crate names, populate timing information, and collect into a Vec
-        let mut crates: Vec<Crate> = crates_map
-            .into_iter()
-            .map(|(name, mut crate_obj)| {
-                // Assign the crate name
-                crate_obj.name = name.clone();
-
-                // If we have recorded build timing for this crate, attach it
-                if let Some(dur) = crate_build_times.get(&name) {
-                    crate_obj.timing_info = Some(TimingInfo {
-                        target: crate::cargo::CargoTarget {
-                            name: Some(name.as_str().to_string()),
-                            kind: None,
-                            crate_types: None,
-                        },
-                        duration: dur.as_secs_f64(),
-                        rmeta_time: None,
-                    });
-                }
-
-                crate_obj
-            })
-            .collect();
 
-        // Sort crates by name for consistent output
-        crates.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut crate_names: Vec<&CrateName> =
+            self.self_profiled.iter().chain(&self.type_sized).collect();
+        crate_names.sort();
+        crate_names.dedup();
+        for crate_name in crate_names {
+            // Package rustflags replace the profile's, rather than adding to them
+            let mut flags = Vec::new();
+            if self.self_profile_all || self.self_profiled.contains(crate_name) {
+                flags.extend_from_slice(&self_profile_flags);
+            }
+            if self.type_sized.contains(crate_name) {
+                flags.push("-Zprint-type-sizes".to_string());
+            }
 
-        context.crates = crates;
+            let mut packages = vec![crate_name.to_string()];
+            let dashed = crate_name.as_str().replace('_', "-");
+            if dashed != packages[0] {
+                packages.push(dashed);
+            }
+            for package in packages {
+                cmd.arg("--config");
+                cmd.arg(format!(
+                    "profile.{profile}.package.\"{package}\".rustflags={}",
+                    toml_array(&flags)
+                ));
+            }
+        }
+    }
 
-        Ok(context)
+    /// Asks cargo for the unit graph of the build, without building anything.
+    fn unit_graph(
+        &self,
+        profile_overrides: &[(String, String)],
+        rustflags: &[String],
+    ) -> Option<UnitGraph> {
+        let mut cmd = self.cargo_build(profile_overrides, rustflags);
+        cmd.arg("--unit-graph");
+
+        let output = cmd
+            .output()
+            .map_err(|e| warn!("Failed to execute cargo to get the unit graph: {e}"))
+            .ok()?;
+        if !output.status.success() {
+            warn!(
+                "Failed to get the unit graph: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return None;
+        }
+        UnitGraph::parse(&String::from_utf8_lossy(&output.stdout))
+            .map_err(|e| warn!("Failed to parse the unit graph: {e}"))
+            .ok()
     }
 
-    fn build_command(&self) -> Command {
-        let mut cmd = Command::new("cargo");
+    /// `cargo build` with the user's arguments, and the profile, target, target
+    /// directory and rustflags we picked
+    fn cargo_build(&self, profile_overrides: &[(String, String)], rustflags: &[String]) -> Command {
+        let mut cmd = env::cargo(self.toolchain.as_deref());
         cmd.arg("build");
 
         // Just pass additional args
         cmd.args(&self.additional_args);
+        // Type sizes are printed without the crate's name, so we can only tell
+        // which crate they're from if crates are compiled one at a time
+        if self.prints_several_type_sizes() && !self.sets_jobs() {
+            cmd.arg("-j1");
+        }
 
-        // Add required flags for analysis
-        cmd.args([
-            "--message-format=json",
-            "-Z",
-            "unstable-options",
-            "-Z",
-            "binary-dep-depinfo",
-            "-Z",
-            "checksum-freshness",
-            "--timings=json",
-            "--manifest-path",
-        ]);
+        if let Some(profile) = &self.profile {
+            cmd.arg("--profile");
+            cmd.arg(profile);
+        }
+        cmd.envs(profile_overrides.iter().map(|(k, v)| (k, v)));
+        let profile = self.profile_name();
+        for (package, key, value) in &self.package_profile_overrides {
+            cmd.arg("--config");
+            cmd.arg(format!(
+                "profile.{profile}.package.\"{package}\".{key}={}",
+                profile::toml_value(value)
+            ));
+        }
+
+        cmd.args(["-Z", "unstable-options", "--manifest-path"]);
         cmd.arg(&self.manifest_path);
         cmd.arg("--target-dir");
         cmd.arg(&self.target_dir);
-        let rustflags = "--emit=llvm-ir -Cdebuginfo=line-tables-only -Cstrip=none";
-
-        // Set environment variables for LLVM IR, timing, and Cstrip
-        cmd.env("RUSTFLAGS", rustflags);
+        if let Some(target) = &self.target {
+            cmd.arg("--target");
+            cmd.arg(target.as_str());
+        }
+        // Our flags are merged with the user's, and passed in the variable that takes
+        // precedence over all other sources of rustflags.
+        cmd.env("CARGO_ENCODED_RUSTFLAGS", rustflags::encode(rustflags));
+        cmd.env_remove("RUSTFLAGS");
         cmd.env("RUSTC_BOOTSTRAP", "1");
-        // Force colored output in cargo/rustc even if not a tty
-        cmd.env("CLICOLOR_FORCE", "1");
+        // Force colored output in cargo/rustc even if not a tty, unless nobody's
+        // going to see it
+        if !self.quiet {
+            cmd.env("CLICOLOR_FORCE", "1");
+        }
 
         cmd
     }
 }
 
+/// How long cargo gets to exit after being asked to stop, before it's killed
+const STOP_GRACE_PERIOD: Duration = Duration::from_secs(3);
+
+/// Stops cargo and reaps it, so that it doesn't linger as a zombie and the
+/// reader threads see the pipes close.
+///
+/// On unix, cargo's process group (see [`BuildRunner::cancellation_token`]) gets
+/// `SIGTERM`, then `SIGKILL` after [`STOP_GRACE_PERIOD`], so that rustc and build
+/// scripts don't outlive it.
+#[cfg(unix)]
+fn stop_cargo(child: &mut std::process::Child) -> std::io::Result<std::process::ExitStatus> {
+    let group = -(child.id() as libc::pid_t);
+    // SAFETY: `kill` has no memory safety requirements
+    if unsafe { libc::kill(group, libc::SIGTERM) } != 0 {
+        warn!(
+            "Failed to terminate cargo: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+
+    let deadline = Instant::now() + STOP_GRACE_PERIOD;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if Instant::now() > deadline {
+            break None;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+
+    // Whatever is left of the group, e.g. rustc if cargo exited first
+    // SAFETY: as above
+    unsafe { libc::kill(group, libc::SIGKILL) };
+    match status {
+        Some(status) => Ok(status),
+        None => child.wait(),
+    }
+}
+
+/// Kills cargo and reaps it, so that it doesn't linger as a zombie and the
+/// reader threads see the pipes close.
+#[cfg(not(unix))]
+fn stop_cargo(child: &mut std::process::Child) -> std::io::Result<std::process::ExitStatus> {
+    if let Err(e) = child.kill() {
+        warn!("Failed to kill cargo: {e}");
+    }
+    child.wait()
+}
+
 /// Finds all `.ll` files within a given directory, ignoring `build` directories.
 pub fn find_llvm_ir_files(root_dir: &Utf8Path) -> Result<Vec<Utf8PathBuf>, SubstanceError> {
     let mut ll_files = Vec::new();
@@ -0,0 +1,603 @@
+//! GitHub-flavored markdown reports, to paste in pull request comments.
+//!
+//! A report starts with a summary table, followed by collapsible `<details>`
+//! sections for crates, symbols, LLVM functions and build times. Reports are
+//! kept under [`MarkdownOptions::max_len`]: sections are shortened first, then
+//! the last ones are left out, with a note saying so.
+
+use std::time::Duration;
+
+use crate::diff::{AnalysisComparison, ChangeKind};
+use crate::formatting::{
+    format_bytes, format_duration, format_duration_diff, format_percentage, format_size_diff,
+};
+use crate::types::BuildContext;
+
+/// How [`render_context`] and [`render_comparison`] lay out a report
+#[derive(Debug, Clone)]
+pub struct MarkdownOptions {
+    /// Heading of the report, a default one if `None`
+    pub title: Option<String>,
+
+    /// Rows of each section, before shortening to fit in `max_len`
+    pub rows: usize,
+
+    /// Longest report, in bytes. GitHub rejects comments over 65536
+    /// characters.
+    pub max_len: usize,
+
+    /// Longer names are cut in the middle
+    pub max_name_len: usize,
+}
+
+impl Default for MarkdownOptions {
+    fn default() -> Self {
+        Self {
+            title: None,
+            rows: 20,
+            max_len: 60_000,
+            max_name_len: 120,
+        }
+    }
+}
+
+/// Renders one build: its size, and where the size and build time go
+pub fn render_context(context: &BuildContext, options: &MarkdownOptions) -> String {
+    let title = options
+        .title
+        .clone()
+        .unwrap_or_else(|| format!("Size report for `{}`", context.artifact.name));
+    fit(options, |rows| {
+        let mut summary = heading(&title);
+        summary.push_str(&table(
+            &["", ""],
+            &[Align::Left, Align::Right],
+            vec![
+                vec![
+                    "File size".to_string(),
+                    format_bytes(context.file_size.value()),
+                ],
+                vec![
+                    "`.text` size".to_string(),
+                    format_bytes(context.text_size.value()),
+                ],
+                vec!["Crates".to_string(), context.crates.len().to_string()],
+                vec![
+                    "LLVM IR lines".to_string(),
+                    context.num_llvm_lines().to_string(),
+                ],
+                vec![
+                    "Build time".to_string(),
+                    context
+                        .wall_duration
+                        .as_ref()
+                        .map_or("?".to_string(), format_duration),
+                ],
+            ],
+            0,
+        ));
+
+        let text_size = context.text_size.value();
+        let mut crates: Vec<(String, u64, usize)> = context
+            .crates
+            .iter()
+            .map(|c| {
+                (
+                    c.name.to_string(),
+                    c.symbols.values().map(|s| s.size.value()).sum(),
+                    c.num_llvm_lines(),
+                )
+            })
+            .collect();
+        crates.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let crate_rows = crates
+            .iter()
+            .map(|(name, size, lines)| {
+                vec![
+                    code(name, options),
+                    format_bytes(*size),
+                    share(*size, text_size),
+                    lines.to_string(),
+                ]
+            })
+            .collect();
+
+        let mut symbols: Vec<_> = context.all_symbols().into_values().collect();
+        symbols.sort_by(|a, b| {
+            b.total_size
+                .cmp(&a.total_size)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        let symbol_rows = symbols
+            .iter()
+            .map(|s| {
+                let mut crates: Vec<&str> = s.crates.iter().map(|c| c.as_str()).collect();
+                crates.sort_unstable();
+                vec![
+                    code(s.name.as_str(), options),
+                    crates.join(", "),
+                    s.copies.value().to_string(),
+                    format_bytes(s.total_size.value()),
+                ]
+            })
+            .collect();
+
+        let mut functions: Vec<_> = context.all_llvm_functions().into_values().collect();
+        functions.sort_by(|a, b| {
+            b.total_llvm_lines
+                .cmp(&a.total_llvm_lines)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        let function_rows = functions
+            .iter()
+            .map(|f| {
+                vec![
+                    code(f.name.as_str(), options),
+                    f.copies.value().to_string(),
+                    f.total_llvm_lines.value().to_string(),
+                ]
+            })
+            .collect();
+
+        let mut timed: Vec<_> = context
+            .crates
+            .iter()
+            .filter_map(|c| Some((c, c.timing_info.as_ref()?.duration)))
+            .collect();
+        timed.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.name.cmp(&b.0.name)));
+        let optional = |d: Option<Duration>| d.as_ref().map_or(String::new(), format_duration);
+        let time_rows = timed
+            .iter()
+            .map(|(c, secs)| {
+                vec![
+                    code(c.name.as_str(), options),
+                    format_duration(&Duration::from_secs_f64(*secs)),
+                    optional(c.frontend_duration),
+                    optional(c.codegen_duration),
+                ]
+            })
+            .collect();
+
+        vec![
+            summary,
+            details(
+                "Crates",
+                &["Crate", "Size", "% of `.text`", "LLVM IR lines"],
+                &[Align::Left, Align::Right, Align::Right, Align::Right],
+                crate_rows,
+                rows,
+            ),
+            details(
+                "Symbols",
+                &["Symbol", "Crates", "Copies", "Size"],
+                &[Align::Left, Align::Left, Align::Right, Align::Right],
+                symbol_rows,
+                rows,
+            ),
+            details(
+                "LLVM functions",
+                &["Function", "Copies", "Lines"],
+                &[Align::Left, Align::Right, Align::Right],
+                function_rows,
+                rows,
+            ),
+            details(
+                "Build times",
+                &["Crate", "Build time", "Frontend", "Codegen"],
+                &[Align::Left, Align::Right, Align::Right, Align::Right],
+                time_rows,
+                rows,
+            ),
+        ]
+    })
+}
+
+/// Renders what changed between two builds, see [`BuildContext::compare`]
+pub fn render_comparison(comparison: &AnalysisComparison, options: &MarkdownOptions) -> String {
+    let title = options
+        .title
+        .clone()
+        .unwrap_or_else(|| "Size changes".to_string());
+    let sizes = &comparison.file_size_diff;
+    fit(options, |rows| {
+        let mut summary = heading(&title);
+        let mut summary_rows = vec![
+            vec![
+                "File size".to_string(),
+                format_bytes(sizes.file_size_before.value()),
+                format_bytes(sizes.file_size_after.value()),
+                size_delta(sizes.file_size_delta()),
+            ],
+            vec![
+                "`.text` size".to_string(),
+                format_bytes(sizes.text_size_before.value()),
+                format_bytes(sizes.text_size_after.value()),
+                size_delta(sizes.text_size_delta()),
+            ],
+        ];
+        if let (Some(before), Some(after)) = (
+            comparison.wall_duration_before,
+            comparison.wall_duration_after,
+        ) {
+            summary_rows.push(vec![
+                "Build time".to_string(),
+                format_duration(&before),
+                format_duration(&after),
+                time_delta(before, after),
+            ]);
+        }
+        summary.push_str(&table(
+            &["", "Before", "After", "Δ"],
+            &[Align::Left, Align::Right, Align::Right, Align::Right],
+            summary_rows,
+            0,
+        ));
+        for (label, crates) in [
+            ("Crates added", &comparison.crates_added),
+            ("Crates removed", &comparison.crates_removed),
+        ] {
+            if crates.is_empty() {
+                continue;
+            }
+            let mut names: Vec<String> = crates
+                .iter()
+                .take(rows)
+                .map(|c| code(c.as_str(), options))
+                .collect();
+            if crates.len() > rows {
+                names.push(format!("and {} more", crates.len() - rows));
+            }
+            summary.push_str(&format!("\n**{label}:** {}\n", names.join(", ")));
+        }
+
+        let optional_bytes = |size: Option<u64>| size.map_or("—".to_string(), format_bytes);
+        let crate_rows = comparison
+            .crate_changes
+            .iter()
+            .filter(|c| c.delta() != 0 || c.kind() != ChangeKind::Changed)
+            .map(|c| {
+                vec![
+                    code(&c.name, options),
+                    optional_bytes(c.size_before),
+                    optional_bytes(c.size_after),
+                    size_delta(c.delta()),
+                ]
+            })
+            .collect();
+
+        let symbol_rows = comparison
+            .symbol_changes
+            .iter()
+            .map(|s| {
+                let name = match &s.previous_name {
+                    Some(previous) => {
+                        format!(
+                            "{} (was {})",
+                            code(&s.name, options),
+                            code(previous, options)
+                        )
+                    }
+                    None => code(&s.name, options),
+                };
+                vec![
+                    name,
+                    s.crate_name.to_string(),
+                    optional_bytes(s.size_before),
+                    optional_bytes(s.size_after),
+                    size_delta(s.delta()),
+                ]
+            })
+            .collect();
+
+        let optional_lines =
+            |lines: Option<usize>| lines.map_or("—".to_string(), |l| l.to_string());
+        let function_rows = comparison
+            .llvm_function_changes
+            .iter()
+            .map(|f| {
+                vec![
+                    code(f.name.as_str(), options),
+                    f.crate_name.to_string(),
+                    optional_lines(f.lines_before),
+                    optional_lines(f.lines_after),
+                    signed(&format!("{:+}", f.delta())),
+                ]
+            })
+            .collect();
+
+        let mut timed: Vec<_> = comparison
+            .crate_changes
+            .iter()
+            .filter_map(|c| Some((c, c.build_time_before?, c.build_time_after?)))
+            .filter(|(_, before, after)| before != after)
+            .collect();
+        timed.sort_by(|a, b| {
+            let delta = |(_, before, after): &(_, Duration, Duration)| {
+                (after.as_secs_f64() - before.as_secs_f64()).abs()
+            };
+            delta(b)
+                .total_cmp(&delta(a))
+                .then_with(|| a.0.name.cmp(&b.0.name))
+        });
+        let time_rows = timed
+            .iter()
+            .map(|(c, before, after)| {
+                vec![
+                    code(&c.name, options),
+                    format_duration(before),
+                    format_duration(after),
+                    time_delta(*before, *after),
+                ]
+            })
+            .collect();
+
+        let changes = [Align::Left, Align::Right, Align::Right, Align::Right];
+        let named_changes = [
+            Align::Left,
+            Align::Left,
+            Align::Right,
+            Align::Right,
+            Align::Right,
+        ];
+        vec![
+            summary,
+            details(
+                "Crates",
+                &["Crate", "Before", "After", "Δ"],
+                &changes,
+                crate_rows,
+                rows,
+            ),
+            details(
+                "Symbols",
+                &["Symbol", "Crate", "Before", "After", "Δ"],
+                &named_changes,
+                symbol_rows,
+                rows,
+            ),
+            details(
+                "LLVM functions",
+                &["Function", "Crate", "Before", "After", "Δ"],
+                &named_changes,
+                function_rows,
+                rows,
+            ),
+            details(
+                "Build times",
+                &["Crate", "Before", "After", "Δ"],
+                &changes,
+                time_rows,
+                rows,
+            ),
+        ]
+    })
+}
+
+/// Renders the report's parts (the summary, then sections) with fewer and
+/// fewer rows until they fit in `max_len`. When they don't with a single row,
+/// the sections that don't fit are left out.
+fn fit(options: &MarkdownOptions, render: impl Fn(usize) -> Vec<String>) -> String {
+    let mut rows = options.rows.max(1);
+    let parts = loop {
+        let parts = render(rows);
+        let len: usize = parts.iter().map(String::len).sum();
+        if len <= options.max_len || rows == 1 {
+            break parts;
+        }
+        rows /= 2;
+    };
+
+    let mut out = String::new();
+    let mut omitted = 0;
+    // Leave room for the note
+    let budget = options.max_len.saturating_sub(100);
+    for (index, part) in parts.iter().enumerate() {
+        if index == 0 || out.len() + part.len() <= budget {
+            out.push_str(part);
+        } else {
+            omitted += 1;
+        }
+    }
+    if omitted > 0 {
+        out.push_str(&format!(
+            "\n_{omitted} more section(s) left out to keep this under {} characters._\n",
+            options.max_len
+        ));
+    }
+    if out.len() > options.max_len {
+        let mut end = budget;
+        while !out.is_char_boundary(end) {
+            end -= 1;
+        }
+        out.truncate(end);
+        out.push_str("\n\n_Report truncated._\n");
+    }
+    out
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Right,
+}
+
+fn heading(title: &str) -> String {
+    format!("### {title}\n\n")
+}
+
+/// A table, followed by a row saying how many rows were left out
+fn table(headers: &[&str], align: &[Align], rows: Vec<Vec<String>>, more: usize) -> String {
+    let mut out = format!("| {} |\n|", headers.join(" | "));
+    for align in align {
+        out.push_str(match align {
+            Align::Left => " :--- |",
+            Align::Right => " ---: |",
+        });
+    }
+    out.push('\n');
+    for row in rows {
+        out.push_str(&format!("| {} |\n", row.join(" | ")));
+    }
+    if more > 0 {
+        let mut row = vec![String::new(); headers.len()];
+        row[0] = format!("_…and {more} more_");
+        out.push_str(&format!("| {} |\n", row.join(" | ")));
+    }
+    out
+}
+
+/// A collapsed section with the first `rows` rows of a table, or nothing if
+/// there are no rows
+fn details(
+    summary: &str,
+    headers: &[&str],
+    align: &[Align],
+    mut rows: Vec<Vec<String>>,
+    max_rows: usize,
+) -> String {
+    if rows.is_empty() {
+        return String::new();
+    }
+    let total = rows.len();
+    rows.truncate(max_rows);
+    format!(
+        "\n<details>\n<summary>{summary} ({total})</summary>\n\n{}\n</details>\n",
+        table(headers, align, rows, total - total.min(max_rows))
+    )
+}
+
+/// A name in a code span, safe to put in a table cell, cut in the middle when
+/// it's too long
+fn code(name: &str, options: &MarkdownOptions) -> String {
+    let count = name.chars().count();
+    let name = if count > options.max_name_len {
+        let keep = options.max_name_len.saturating_sub(1) / 2;
+        let head: String = name.chars().take(keep).collect();
+        let tail: String = name.chars().skip(count - keep).collect();
+        format!("{head}…{tail}")
+    } else {
+        name.to_string()
+    };
+    let name = name.replace('|', "\\|");
+    if name.contains('`') {
+        format!("`` {name} ``")
+    } else {
+        format!("`{name}`")
+    }
+}
+
+/// Uses a minus sign rather than a hyphen, so that columns of deltas line up
+fn signed(value: &str) -> String {
+    match value.strip_prefix('-') {
+        Some(abs) => format!("−{abs}"),
+        None => value.to_string(),
+    }
+}
+
+fn size_delta(delta: i64) -> String {
+    if delta == 0 {
+        "0 B".to_string()
+    } else {
+        signed(&format_size_diff(delta))
+    }
+}
+
+fn time_delta(before: Duration, after: Duration) -> String {
+    signed(&format_duration_diff(
+        after.as_secs_f64() - before.as_secs_f64(),
+    ))
+}
+
+fn share(size: u64, total: u64) -> String {
+    if total == 0 {
+        String::new()
+    } else {
+        format_percentage(size as f64 / total as f64 * 100.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::DiffOptions;
+    use crate::types::Crate;
+
+    #[test]
+    fn test_render_context() {
+        let context = BuildContext::for_test(
+            4096,
+            vec![
+                Crate::named("app").with_symbol("app::main", 1024),
+                Crate::named("serde").with_symbol("<serde::de::Visitor | x>::visit", 512),
+            ],
+        );
+        let report = render_context(&context, &MarkdownOptions::default());
+        assert!(report.starts_with("### Size report for `app`\n"));
+        assert!(report.contains("| File size | 4.00 KiB |"));
+        assert!(report.contains("<summary>Crates (2)</summary>"));
+        assert!(report.contains("| `app` | 1.00 KiB | 50.0% | 0 |"));
+        assert!(report.contains("`<serde::de::Visitor \\| x>::visit`"));
+        assert!(!report.contains("Build times"));
+    }
+
+    #[test]
+    fn test_render_comparison() {
+        let before = BuildContext::for_test(
+            4096,
+            vec![Crate::named("app").with_symbol("app::main", 1024)],
+        );
+        let after = BuildContext::for_test(
+            3072,
+            vec![
+                Crate::named("app").with_symbol("app::main", 512),
+                Crate::named("log").with_symbol("log::log", 64),
+            ],
+        );
+        let comparison = before.compare(&after, &DiffOptions::default());
+        let report = render_comparison(&comparison, &MarkdownOptions::default());
+        assert!(report.contains("| File size | 4.00 KiB | 3.00 KiB | −1.00 KiB |"));
+        assert!(report.contains("**Crates added:** `log`"));
+        assert!(report.contains("| `app` | 1.00 KiB | 512 B | −512 B |"));
+        assert!(report.contains("| `log` | — | 64 B | +64 B |"));
+    }
+
+    #[test]
+    fn test_fit() {
+        let krate = (0..200).fold(Crate::named("app"), |krate, i| {
+            krate.with_symbol(&format!("app::function_number_{i}"), 1000 + i)
+        });
+        let context = BuildContext::for_test(1 << 20, vec![krate]);
+
+        let options = MarkdownOptions {
+            rows: 100,
+            max_len: 2000,
+            ..MarkdownOptions::default()
+        };
+        let report = render_context(&context, &options);
+        assert!(report.len() <= options.max_len);
+        assert!(report.contains("more_ |"));
+        assert_eq!(
+            report.matches("<details>").count(),
+            report.matches("</details>").count()
+        );
+
+        let options = MarkdownOptions {
+            max_len: 400,
+            ..options
+        };
+        let report = render_context(&context, &options);
+        assert!(report.len() <= options.max_len);
+        assert!(report.contains("left out to keep this under 400 characters"));
+    }
+
+    #[test]
+    fn test_code() {
+        let options = MarkdownOptions {
+            max_name_len: 9,
+            ..MarkdownOptions::default()
+        };
+        assert_eq!(code("a::b", &options), "`a::b`");
+        assert_eq!(code("abcdefghijklmn", &options), "`abcd…klmn`");
+        assert_eq!(code("a`b", &options), "`` a`b ``");
+    }
+}
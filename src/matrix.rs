@@ -0,0 +1,270 @@
+//! What builds of several variants of one package have in common.
+//!
+//! [`FeatureMatrix`](crate::features::FeatureMatrix),
+//! [`ProfileMatrix`](crate::profile_matrix::ProfileMatrix),
+//! [`ToolchainMatrix`](crate::toolchains::ToolchainMatrix) and
+//! [`PruningAdvisor`](crate::pruning::PruningAdvisor) build every variant with
+//! the same [`BuildOptions`], each in its own subdirectory of the target
+//! directory, so that no build picks up another's artifacts (like the `.ll`
+//! files we scan) and build times are from scratch. Variants are compared to
+//! their baseline with [`BuildDelta`].
+
+use std::collections::HashMap;
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::types::{BuildContext, BuildTimeSeconds, Crate, CrateName, TargetTriple};
+use crate::BuildRunner;
+
+/// How every build of a matrix is run
+#[derive(Clone, Debug)]
+pub struct BuildOptions {
+    manifest_path: Utf8PathBuf,
+    additional_args: Vec<String>,
+    target: Option<TargetTriple>,
+    toolchain: Option<String>,
+    profile: Option<String>,
+    quiet: bool,
+}
+
+impl BuildOptions {
+    pub fn for_manifest(manifest_path: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            manifest_path: manifest_path.into(),
+            additional_args: Vec::new(),
+            target: None,
+            toolchain: None,
+            profile: None,
+            quiet: false,
+        }
+    }
+
+    /// Passed to every build, see [`BuildRunner::arg`]
+    pub fn arg<T: Into<String>>(mut self, arg: T) -> Self {
+        self.additional_args.push(arg.into());
+        self
+    }
+
+    /// See [`BuildRunner::target`]
+    pub fn target(mut self, triple: impl Into<TargetTriple>) -> Self {
+        self.target = Some(triple.into());
+        self
+    }
+
+    /// See [`BuildRunner::toolchain`]
+    pub fn toolchain(mut self, toolchain: impl Into<String>) -> Self {
+        self.toolchain = Some(toolchain.into());
+        self
+    }
+
+    /// See [`BuildRunner::profile`]
+    pub fn profile(mut self, name: impl Into<String>) -> Self {
+        self.profile = Some(name.into());
+        self
+    }
+
+    /// See [`BuildRunner::quiet`]
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    pub(crate) fn manifest_path(&self) -> &Utf8Path {
+        &self.manifest_path
+    }
+
+    /// Also the toolchain `cargo metadata` runs with
+    pub(crate) fn selected_toolchain(&self) -> Option<&str> {
+        self.toolchain.as_deref()
+    }
+
+    /// The same options for another manifest, e.g. a copy of the package
+    pub(crate) fn with_manifest(&self, manifest_path: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            manifest_path: manifest_path.into(),
+            ..self.clone()
+        }
+    }
+
+    /// A runner for the baseline build
+    pub(crate) fn baseline_runner(&self) -> BuildRunner {
+        self.runner("baseline")
+    }
+
+    /// A runner for the `index`th variant of the matrix, named `name` (e.g. a
+    /// feature or a toolchain). Names may repeat or look alike once made into
+    /// a directory name, so the index keeps the target directories apart.
+    pub(crate) fn variant_runner(&self, index: usize, name: &str) -> BuildRunner {
+        self.runner(&format!("variant-{index}-{name}"))
+    }
+
+    fn runner(&self, subdir: &str) -> BuildRunner {
+        let mut runner = BuildRunner::for_manifest(&self.manifest_path)
+            .target_subdir(subdir)
+            .quiet(self.quiet);
+        for arg in &self.additional_args {
+            runner = runner.arg(arg);
+        }
+        if let Some(target) = &self.target {
+            runner = runner.target(target.clone());
+        }
+        if let Some(toolchain) = &self.toolchain {
+            runner = runner.toolchain(toolchain);
+        }
+        if let Some(profile) = &self.profile {
+            runner = runner.profile(profile);
+        }
+        runner
+    }
+}
+
+/// How a build differs from a baseline. Values are negative when it's smaller
+/// or faster.
+#[derive(Clone, Debug)]
+pub struct BuildDelta {
+    /// Change in file size of the main artifact, in bytes
+    pub file_size: i64,
+
+    /// Change in `.text` size of the main artifact, in bytes
+    pub text_size: i64,
+
+    /// Change in LLVM IR lines
+    pub llvm_lines: i64,
+
+    /// Change in wall time of the build
+    pub build_time: BuildTimeSeconds,
+
+    /// Crates that changed, the largest size increase first
+    pub crates: Vec<CrateCost>,
+}
+
+impl BuildDelta {
+    /// From `before` to `after`
+    pub(crate) fn between(before: &BuildContext, after: &BuildContext) -> Self {
+        let seconds =
+            |context: &BuildContext| context.wall_duration.map_or(0.0, |d| d.as_secs_f64());
+
+        Self {
+            file_size: after.file_size.value() as i64 - before.file_size.value() as i64,
+            text_size: after.text_size.value() as i64 - before.text_size.value() as i64,
+            llvm_lines: after.num_llvm_lines() as i64 - before.num_llvm_lines() as i64,
+            build_time: BuildTimeSeconds::new(seconds(after) - seconds(before)),
+            crates: crate_costs(&before.crates, &after.crates),
+        }
+    }
+}
+
+/// How one crate differs from the baseline
+#[derive(Clone, Debug)]
+pub struct CrateCost {
+    pub crate_name: CrateName,
+
+    /// Change in total size of the crate's symbols, in bytes
+    pub size: i64,
+
+    /// Change in LLVM IR lines
+    pub llvm_lines: i64,
+
+    /// Change in the crate's own build time
+    pub build_time: BuildTimeSeconds,
+}
+
+fn crate_costs(before: &[Crate], after: &[Crate]) -> Vec<CrateCost> {
+    let size = |krate: &Crate| krate.symbols.values().map(|s| s.size.value()).sum::<u64>() as i64;
+    let build_time = |krate: &Crate| krate.timing_info.as_ref().map_or(0.0, |t| t.duration);
+
+    let before: HashMap<&CrateName, &Crate> = before.iter().map(|c| (&c.name, c)).collect();
+    let after: HashMap<&CrateName, &Crate> = after.iter().map(|c| (&c.name, c)).collect();
+    let mut names: Vec<&CrateName> = before.keys().chain(after.keys()).copied().collect();
+    names.sort();
+    names.dedup();
+
+    let mut costs: Vec<CrateCost> = names
+        .into_iter()
+        .map(|name| {
+            let (b, a) = (before.get(name), after.get(name));
+            let delta = |f: &dyn Fn(&Crate) -> i64| a.map_or(0, |c| f(c)) - b.map_or(0, |c| f(c));
+            CrateCost {
+                crate_name: name.clone(),
+                size: delta(&size),
+                llvm_lines: delta(&|c| c.num_llvm_lines() as i64),
+                build_time: BuildTimeSeconds::new(
+                    a.map_or(0.0, |c| build_time(c)) - b.map_or(0.0, |c| build_time(c)),
+                ),
+            }
+        })
+        .filter(|c| c.size != 0 || c.llvm_lines != 0 || c.build_time.value() != 0.0)
+        .collect();
+    costs.sort_by(|a, b| {
+        b.size
+            .cmp(&a.size)
+            .then_with(|| a.crate_name.cmp(&b.crate_name))
+    });
+    costs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn krate(name: &str, symbol_sizes: &[u64], build_time: f64) -> Crate {
+        symbol_sizes
+            .iter()
+            .enumerate()
+            .fold(Crate::named(name), |krate, (i, size)| {
+                krate.with_symbol(&format!("{name}::f{i}"), *size)
+            })
+            .with_build_time(build_time)
+    }
+
+    #[test]
+    fn test_crate_costs() {
+        let before = [krate("app", &[100], 1.0), krate("unchanged", &[50], 2.0)];
+        let after = [
+            krate("app", &[100, 20], 1.5),
+            krate("unchanged", &[50], 2.0),
+            krate("serde_json", &[300, 200], 3.0),
+        ];
+        let costs = crate_costs(&before, &after);
+        let summary: Vec<(&str, i64, f64)> = costs
+            .iter()
+            .map(|c| (c.crate_name.as_str(), c.size, c.build_time.value()))
+            .collect();
+        assert_eq!(summary, [("serde_json", 500, 3.0), ("app", 20, 0.5)]);
+    }
+
+    #[test]
+    fn test_target_dirs_differ() {
+        let options = BuildOptions::for_manifest("/app/Cargo.toml");
+        let copy = options.with_manifest("/tmp/copy/Cargo.toml");
+        // Names that are the same, look like the baseline's, or only differ by
+        // path separators
+        let names = ["baseline", "baseline", "std", "/opt/rust", "_opt_rust"];
+        let runners = std::iter::once(options.baseline_runner())
+            .chain(std::iter::once(copy.baseline_runner()))
+            .chain(
+                names
+                    .iter()
+                    .enumerate()
+                    .map(|(index, name)| options.variant_runner(index, name)),
+            );
+        let dirs: Vec<String> = runners
+            .map(|runner| runner.target_dir.file_name().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            dirs,
+            [
+                "baseline",
+                "baseline",
+                "variant-0-baseline",
+                "variant-1-baseline",
+                "variant-2-std",
+                "variant-3-_opt_rust",
+                "variant-4-_opt_rust",
+            ]
+        );
+        let mut unique = dirs[1..].to_vec();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), dirs.len() - 1, "{:?}", dirs);
+    }
+}
@@ -0,0 +1,258 @@
+//! Progress reporting and cancellation.
+//!
+//! Builds of large projects take minutes, and the analysis that follows can take
+//! a while too. A [`BuildObserver`] is told what's going on as it happens, and a
+//! [`CancellationToken`] lets another thread stop everything.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::cargo::CargoMessage;
+use crate::errors::SubstanceError;
+use crate::types::Artifact;
+
+/// The steps a [`BuildRunner`](crate::BuildRunner) goes through, in order.
+/// [`Analyzer`](crate::Analyzer) skips [`BuildPhase::Build`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BuildPhase {
+    /// `cargo build` is running
+    Build,
+
+    /// Reading symbols from the `.rlib` files of dependencies and std
+    RlibSymbolScan,
+
+    /// Parsing the LLVM IR emitted during the build
+    LlvmIrScan,
+
+    /// Reading the symbols of the `.text` section of a linked artifact. Happens
+    /// once per analyzed artifact.
+    TextParse,
+}
+
+impl fmt::Display for BuildPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            BuildPhase::Build => "building",
+            BuildPhase::RlibSymbolScan => "scanning rlib symbols",
+            BuildPhase::LlvmIrScan => "scanning LLVM IR",
+            BuildPhase::TextParse => "parsing .text section",
+        };
+        f.write_str(s)
+    }
+}
+
+/// When a phase ran, relative to the start of the build
+#[derive(Clone, Debug)]
+pub struct PhaseSpan {
+    pub phase: BuildPhase,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// Receives progress events. All methods do nothing by default.
+///
+/// Cargo messages and artifacts are reported from the thread that reads cargo's
+/// output, hence the `Send + Sync` bound.
+pub trait BuildObserver: Send + Sync {
+    /// A message was received from cargo
+    fn on_cargo_message(&self, _message: &CargoMessage) {}
+
+    /// Cargo produced an artifact (including std artifacts with `-Zbuild-std`)
+    fn on_artifact(&self, _artifact: &Artifact) {}
+
+    /// A new phase started
+    fn on_phase(&self, _phase: BuildPhase) {}
+
+    /// The build and analysis are over, successfully or not
+    fn on_finished(&self, _result: Result<(), &SubstanceError>) {}
+}
+
+/// Stops a build from another thread. Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests cancellation. The cargo child is stopped at the next check, and
+    /// the analysis stops between phases.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// The observer and cancellation token of a build or analysis, either of which
+/// may be missing.
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    pub(crate) observer: Option<Arc<dyn BuildObserver>>,
+    pub(crate) cancellation: Option<CancellationToken>,
+
+    /// When each phase of the current run started
+    timeline: Arc<Mutex<Vec<(BuildPhase, Instant)>>>,
+}
+
+impl Hooks {
+    /// The same observer and cancellation token, with an empty timeline
+    pub(crate) fn for_run(&self) -> Self {
+        Self {
+            observer: self.observer.clone(),
+            cancellation: self.cancellation.clone(),
+            timeline: Default::default(),
+        }
+    }
+
+    /// Phases entered so far, relative to `epoch`. Each one ends when the next
+    /// one starts, and the last one ends now.
+    pub(crate) fn phase_spans(&self, epoch: Instant) -> Vec<PhaseSpan> {
+        let timeline = self.timeline.lock().unwrap();
+        let now = Instant::now();
+        timeline
+            .iter()
+            .enumerate()
+            .map(|(i, (phase, start))| PhaseSpan {
+                phase: *phase,
+                start: start.saturating_duration_since(epoch),
+                end: timeline
+                    .get(i + 1)
+                    .map_or(now, |(_, next)| *next)
+                    .saturating_duration_since(epoch),
+            })
+            .collect()
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Bails out if cancellation was requested
+    pub(crate) fn check_cancelled(&self) -> Result<(), SubstanceError> {
+        if self.is_cancelled() {
+            return Err(SubstanceError::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// Bails out if cancellation was requested, and notifies the observer otherwise
+    pub(crate) fn enter_phase(&self, phase: BuildPhase) -> Result<(), SubstanceError> {
+        self.check_cancelled()?;
+        self.timeline.lock().unwrap().push((phase, Instant::now()));
+        if let Some(observer) = &self.observer {
+            observer.on_phase(phase);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn cargo_message(&self, message: &CargoMessage) {
+        if let Some(observer) = &self.observer {
+            observer.on_cargo_message(message);
+        }
+    }
+
+    pub(crate) fn artifact(&self, artifact: &Artifact) {
+        if let Some(observer) = &self.observer {
+            observer.on_artifact(artifact);
+        }
+    }
+
+    /// Notifies the observer that we're done, and passes the result through
+    pub(crate) fn finished<T>(
+        &self,
+        result: Result<T, SubstanceError>,
+    ) -> Result<T, SubstanceError> {
+        if let Some(observer) = &self.observer {
+            observer.on_finished(result.as_ref().map(|_| ()));
+        }
+        result
+    }
+}
+
+/// Shows a spinner with the number of compiled units and the current phase.
+#[cfg(feature = "cli")]
+pub struct ProgressBarObserver {
+    bar: indicatif::ProgressBar,
+}
+
+#[cfg(feature = "cli")]
+impl ProgressBarObserver {
+    pub fn new() -> Self {
+        let bar = indicatif::ProgressBar::new_spinner();
+        bar.set_style(
+            indicatif::ProgressStyle::with_template(
+                "{spinner:.green} [{elapsed}] {pos} units {msg}",
+            )
+            .expect("progress bar template is valid"),
+        );
+        bar.enable_steady_tick(std::time::Duration::from_millis(100));
+        Self { bar }
+    }
+}
+
+#[cfg(feature = "cli")]
+impl Default for ProgressBarObserver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "cli")]
+impl BuildObserver for ProgressBarObserver {
+    fn on_cargo_message(&self, message: &CargoMessage) {
+        if let CargoMessage::CompilerArtifact(artifact) = message {
+            self.bar.inc(1);
+            self.bar
+                .set_message(format!("compiled {}", artifact.crate_name));
+        }
+    }
+
+    fn on_phase(&self, phase: BuildPhase) {
+        self.bar.set_message(phase.to_string());
+    }
+
+    fn on_finished(&self, result: Result<(), &SubstanceError>) {
+        match result {
+            Ok(()) => self.bar.finish_and_clear(),
+            Err(e) => self.bar.abandon_with_message(format!("failed: {e}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancellation_token_is_shared() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        token.cancel();
+        assert!(clone.is_cancelled());
+    }
+
+    #[test]
+    fn test_phase_spans_are_contiguous() {
+        let epoch = Instant::now();
+        let hooks = Hooks::default().for_run();
+        hooks.enter_phase(BuildPhase::Build).unwrap();
+        hooks.enter_phase(BuildPhase::RlibSymbolScan).unwrap();
+        let spans = hooks.phase_spans(epoch);
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].phase, BuildPhase::Build);
+        assert_eq!(spans[0].end, spans[1].start);
+        assert!(spans[1].start <= spans[1].end);
+
+        // A new run starts from scratch
+        assert!(hooks.for_run().phase_spans(epoch).is_empty());
+    }
+}
@@ -0,0 +1,354 @@
+//! Cost of proc macros: building them, and running them in the crates that use
+//! them.
+//!
+//! Self-profiles say which macro was expanded (`#[derive(Facet)]`), not which
+//! crate it comes from. With a unit graph, the candidates are the proc-macro
+//! crates the consuming crate depends on directly, or through a direct
+//! dependency like `facet` or `serde` that re-exports them. A macro invoked with
+//! a path (`#[derive(facet::Facet)]`) only has the candidates behind that
+//! dependency.
+//!
+//! When there are still several, names settle it: the crate whose name contains
+//! the macro's (or the other way around) wins, and failing that, the only crate
+//! whose name doesn't look like any other macro expanded in the same crate.
+//! Otherwise, the expansion is left unattributed.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use camino::Utf8PathBuf;
+
+use crate::cargo::TimingInfo;
+use crate::critical_path::UnitGraph;
+use crate::self_profile::{MacroExpansion, SelfProfile};
+use crate::types::{Artifact, ArtifactKind, CrateName};
+
+/// A proc-macro crate of the build
+#[derive(Clone, Debug)]
+pub struct ProcMacroCrate {
+    pub name: CrateName,
+
+    /// The dylib loaded by the compiler
+    pub path: Utf8PathBuf,
+
+    /// Timing of building the proc macro itself
+    pub timing_info: Option<TimingInfo>,
+
+    /// Time spent running its macros, per consuming crate. Only covers crates
+    /// that were self-profiled.
+    pub expansions: Vec<ProcMacroExpansion>,
+}
+
+impl ProcMacroCrate {
+    /// Time spent running this crate's macros, in all the profiled crates
+    pub fn expansion_time(&self) -> Duration {
+        self.expansions.iter().map(|e| e.expansion.time).sum()
+    }
+
+    /// Build time of the proc macro itself
+    pub fn build_duration(&self) -> Option<Duration> {
+        self.timing_info
+            .as_ref()
+            .map(|t| Duration::from_secs_f64(t.duration))
+    }
+}
+
+/// Expansions of one macro in one crate
+#[derive(Clone, Debug)]
+pub struct ProcMacroExpansion {
+    /// The crate the macro was expanded in
+    pub consumer: CrateName,
+
+    pub expansion: MacroExpansion,
+}
+
+/// Proc-macro crates with their build times and expansions, most expensive
+/// first, along with the expansions we couldn't attribute to any of them.
+pub(crate) fn attribute(
+    artifacts: &[Artifact],
+    host_timings: &[TimingInfo],
+    self_profiles: &HashMap<CrateName, SelfProfile>,
+    unit_graph: Option<&UnitGraph>,
+) -> (Vec<ProcMacroCrate>, Vec<ProcMacroExpansion>) {
+    let mut crates: Vec<ProcMacroCrate> = artifacts
+        .iter()
+        .filter(|a| a.kind == ArtifactKind::ProcMacro)
+        .map(|artifact| ProcMacroCrate {
+            name: artifact.name.clone(),
+            path: artifact.path.clone(),
+            timing_info: host_timings
+                .iter()
+                .find(|t| {
+                    t.target.name.as_deref().map(normalize)
+                        == Some(normalize(artifact.name.as_str()))
+                        && t.target.kind.iter().flatten().any(|k| k == "proc-macro")
+                })
+                .cloned(),
+            expansions: Vec::new(),
+        })
+        .collect();
+
+    let names: Vec<String> = crates.iter().map(|c| normalize(c.name.as_str())).collect();
+    let mut unattributed = Vec::new();
+    let mut consumers: Vec<&CrateName> = self_profiles.keys().collect();
+    consumers.sort();
+    for consumer in consumers {
+        let expansions = &self_profiles[consumer].macro_expansions;
+        let dependencies = unit_graph.map(|graph| proc_macro_dependencies(graph, consumer));
+        let expanded: Vec<String> = expansions
+            .iter()
+            .map(|e| normalize(e.macro_name()))
+            .collect();
+
+        for expansion in expansions {
+            let candidates: Vec<usize> = match &dependencies {
+                Some(dependencies) => {
+                    let prefix = expansion.crate_prefix().map(normalize);
+                    let through_prefix: Vec<&String> = dependencies
+                        .iter()
+                        .filter(|(via, _)| Some(via) == prefix.as_ref())
+                        .map(|(_, name)| name)
+                        .collect();
+                    let wanted: Vec<&String> = if through_prefix.is_empty() {
+                        dependencies.iter().map(|(_, name)| name).collect()
+                    } else {
+                        through_prefix
+                    };
+                    (0..crates.len())
+                        .filter(|&i| wanted.contains(&&names[i]))
+                        .collect()
+                }
+                None => (0..crates.len()).collect(),
+            };
+
+            let macro_name = normalize(expansion.macro_name());
+            let expansion = ProcMacroExpansion {
+                consumer: consumer.clone(),
+                expansion: expansion.clone(),
+            };
+            match pick(&names, &candidates, &macro_name, &expanded) {
+                Some(index) => crates[index].expansions.push(expansion),
+                None => unattributed.push(expansion),
+            }
+        }
+    }
+
+    crates.sort_by(|a, b| {
+        b.expansion_time()
+            .cmp(&a.expansion_time())
+            .then_with(|| b.build_duration().cmp(&a.build_duration()))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    (crates, unattributed)
+}
+
+/// Picks the crate among `candidates` that defines `macro_name`: the only
+/// candidate, else the only one whose name looks like the macro's, else the only
+/// one whose name looks like none of the other macros in `expanded`
+fn pick(
+    names: &[String],
+    candidates: &[usize],
+    macro_name: &str,
+    expanded: &[String],
+) -> Option<usize> {
+    let looks_like = |name: &str, macro_name: &str| {
+        !macro_name.is_empty() && (name.contains(macro_name) || macro_name.contains(name))
+    };
+    let only = |matching: Vec<usize>| match matching.as_slice() {
+        [index] => Some(*index),
+        _ => None,
+    };
+
+    only(candidates.to_vec())
+        .or_else(|| {
+            only(
+                candidates
+                    .iter()
+                    .copied()
+                    .filter(|&i| looks_like(&names[i], macro_name))
+                    .collect(),
+            )
+        })
+        .or_else(|| {
+            only(
+                candidates
+                    .iter()
+                    .copied()
+                    .filter(|&i| {
+                        !expanded
+                            .iter()
+                            .any(|other| other != macro_name && looks_like(&names[i], other))
+                    })
+                    .collect(),
+            )
+        })
+}
+
+/// Proc-macro crates `consumer` can invoke macros of, as `(via, proc_macro)`
+/// pairs of normalized names: its direct proc-macro dependencies, through
+/// themselves, and those of its direct library dependencies, through the library
+fn proc_macro_dependencies(graph: &UnitGraph, consumer: &CrateName) -> Vec<(String, String)> {
+    let is_kind = |index: usize, kind: &str| {
+        graph.units[index]
+            .target
+            .kind
+            .iter()
+            .flatten()
+            .any(|k| k == kind)
+    };
+    let is_library = |index: usize| {
+        graph.units[index].mode == "build"
+            && !is_kind(index, "custom-build")
+            && !is_kind(index, "proc-macro")
+    };
+    let name = |index: usize| graph.units[index].target.name.as_deref().map(normalize);
+
+    let consumer = normalize(consumer.as_str());
+    let mut dependencies = Vec::new();
+    for index in 0..graph.units.len() {
+        let unit = &graph.units[index];
+        if unit.mode != "build"
+            || is_kind(index, "custom-build")
+            || name(index) != Some(consumer.clone())
+        {
+            continue;
+        }
+        for &dep in &unit.dependencies {
+            let Some(via) = name(dep) else { continue };
+            if is_kind(dep, "proc-macro") {
+                dependencies.push((via.clone(), via));
+            } else if is_library(dep) {
+                for &inner in &graph.units[dep].dependencies {
+                    if is_kind(inner, "proc-macro") {
+                        dependencies.extend(name(inner).map(|n| (via.clone(), n)));
+                    }
+                }
+            }
+        }
+    }
+    dependencies.sort();
+    dependencies.dedup();
+    dependencies
+}
+
+/// Lowercase, without separators, so that `Facet` matches `facet-macros`
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cargo::CargoTarget;
+    use crate::critical_path::Unit;
+
+    fn unit(name: &str, kind: &str, dependencies: Vec<usize>) -> Unit {
+        Unit {
+            package_id: format!("path+file:///{name}#0.1.0"),
+            target: CargoTarget {
+                name: Some(name.to_string()),
+                kind: Some(vec![kind.to_string()]),
+                crate_types: Some(vec![kind.to_string()]),
+            },
+            mode: "build".to_string(),
+            platform: None,
+            dependencies,
+        }
+    }
+
+    fn proc_macro(name: &str) -> Artifact {
+        Artifact {
+            kind: ArtifactKind::ProcMacro,
+            name: CrateName::from(name),
+            path: Utf8PathBuf::from(format!("/target/debug/deps/lib{name}.so")),
+        }
+    }
+
+    fn profile(expansions: &[(&str, u64)]) -> SelfProfile {
+        SelfProfile {
+            queries: Vec::new(),
+            macro_expansions: expansions
+                .iter()
+                .map(|(invocation, millis)| MacroExpansion {
+                    invocation: invocation.to_string(),
+                    time: Duration::from_millis(*millis),
+                    expansions: 1,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_attribution() {
+        // app -> facet -> facet_macros, app -> serde -> serde_derive, app -> thiserror_impl,
+        // tool -> {alpha_derive, beta_derive}
+        let graph = UnitGraph {
+            units: vec![
+                unit("app", "bin", vec![1, 3, 5]),
+                unit("facet", "lib", vec![2]),
+                unit("facet_macros", "proc-macro", vec![]),
+                unit("serde", "lib", vec![4]),
+                unit("serde_derive", "proc-macro", vec![]),
+                unit("thiserror_impl", "proc-macro", vec![]),
+                unit("other", "lib", vec![7]),
+                unit("other_macros", "proc-macro", vec![]),
+                unit("tool", "lib", vec![9, 10]),
+                unit("alpha_derive", "proc-macro", vec![]),
+                unit("beta_derive", "proc-macro", vec![]),
+            ],
+        };
+        let artifacts = [
+            proc_macro("facet_macros"),
+            proc_macro("serde_derive"),
+            proc_macro("thiserror_impl"),
+            proc_macro("other_macros"),
+            proc_macro("alpha_derive"),
+            proc_macro("beta_derive"),
+        ];
+        let profiles = HashMap::from([
+            (
+                CrateName::from("app"),
+                profile(&[
+                    ("#[derive(facet::Facet)]", 30),
+                    ("#[derive(Error)]", 20),
+                    ("#[derive(Serialize)]", 10),
+                    ("#[derive(Deserialize)]", 8),
+                ]),
+            ),
+            (CrateName::from("tool"), profile(&[("#[derive(Gamma)]", 4)])),
+            (
+                CrateName::from("facet"),
+                profile(&[("#[derive(Facet)]", 5)]),
+            ),
+        ]);
+
+        let (crates, unattributed) = attribute(&artifacts, &[], &profiles, Some(&graph));
+        let summary: Vec<(&str, u64, Vec<&str>)> = crates
+            .iter()
+            .map(|c| {
+                (
+                    c.name.as_str(),
+                    c.expansion_time().as_millis() as u64,
+                    c.expansions.iter().map(|e| e.consumer.as_str()).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("facet_macros", 35, vec!["app", "facet"]),
+                ("thiserror_impl", 20, vec!["app"]),
+                ("serde_derive", 18, vec!["app", "app"]),
+                ("alpha_derive", 0, vec![]),
+                ("beta_derive", 0, vec![]),
+                ("other_macros", 0, vec![]),
+            ]
+        );
+        // Two candidates, and `Gamma` doesn't look like either
+        assert_eq!(unattributed.len(), 1);
+        assert_eq!(unattributed[0].expansion.macro_name(), "Gamma");
+    }
+}
@@ -0,0 +1,358 @@
+//! Cargo build profiles.
+//!
+//! Some profile settings get in the way of attributing symbols to crates, so we
+//! resolve the profile a build is going to use, override what we can, and warn
+//! about the rest.
+
+use camino::Utf8Path;
+use log::{debug, warn};
+
+/// Settings of a cargo profile that matter for size analysis. Values are kept
+/// the way they're spelled in `Cargo.toml`, e.g. `opt_level` is `"3"` or `"z"`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileSettings {
+    /// Profile name, e.g. `dev`, `release`, or a custom one
+    pub name: String,
+
+    /// `opt-level`: `0`, `1`, `2`, `3`, `s` or `z`
+    pub opt_level: String,
+
+    /// `debug`: `true`, `false`, `0`, `1`, `2`, `line-tables-only`, etc.
+    pub debug: String,
+
+    /// `strip`: `none`, `debuginfo`, `symbols`, `true` or `false`
+    pub strip: String,
+
+    /// `lto`: `false`, `true`, `fat`, `thin` or `off`
+    pub lto: String,
+
+    /// `codegen-units`, `None` for rustc's default
+    pub codegen_units: Option<u32>,
+
+    /// `panic`: `unwind` or `abort`
+    pub panic: String,
+}
+
+impl ProfileSettings {
+    /// Built-in defaults for `dev` (and `test`), `release` (and `bench`)
+    fn builtin(name: &str) -> Option<Self> {
+        let base = match name {
+            "dev" | "test" => Self {
+                name: name.to_string(),
+                opt_level: "0".to_string(),
+                debug: "true".to_string(),
+                strip: "none".to_string(),
+                lto: "false".to_string(),
+                codegen_units: Some(256),
+                panic: "unwind".to_string(),
+            },
+            "release" | "bench" => Self {
+                name: name.to_string(),
+                opt_level: "3".to_string(),
+                debug: "false".to_string(),
+                strip: "none".to_string(),
+                lto: "false".to_string(),
+                codegen_units: Some(16),
+                panic: "unwind".to_string(),
+            },
+            _ => return None,
+        };
+        Some(base)
+    }
+
+    /// Resolves the settings of profile `name`: built-in defaults, then the
+    /// `[profile.<name>]` table of the manifest (following `inherits`), then
+    /// `CARGO_PROFILE_<NAME>_<KEY>` environment variables.
+    ///
+    /// Only the given manifest is read, which should be the workspace root
+    /// manifest since cargo ignores profiles anywhere else.
+    pub fn resolve(manifest_path: &Utf8Path, name: &str) -> Self {
+        let manifest = std::fs::read_to_string(manifest_path).unwrap_or_else(|e| {
+            warn!("Could not read {manifest_path} to resolve profile {name}: {e}");
+            String::new()
+        });
+        let env = |profile: &str, key: &str| std::env::var(profile_env_var(profile, key)).ok();
+        Self::resolve_from(&manifest, name, &env, 0)
+    }
+
+    fn resolve_from(
+        manifest: &str,
+        name: &str,
+        env: &dyn Fn(&str, &str) -> Option<String>,
+        depth: usize,
+    ) -> Self {
+        let mut entries = manifest_profile_entries(manifest, name);
+        for key in [
+            "inherits",
+            "opt-level",
+            "debug",
+            "strip",
+            "lto",
+            "codegen-units",
+            "panic",
+        ] {
+            if let Some(value) = env(name, key) {
+                entries.push((key.to_string(), value));
+            }
+        }
+        let get = |key: &str| {
+            entries
+                .iter()
+                .rev()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+        };
+
+        let mut settings = match Self::builtin(name) {
+            Some(settings) => settings,
+            None => {
+                let parent = get("inherits").unwrap_or_else(|| {
+                    warn!("Custom profile {name} has no `inherits`, assuming `dev`");
+                    "dev".to_string()
+                });
+                // Cargo rejects inheritance cycles, but don't take chances
+                let parent = if depth < 8 { parent } else { "dev".to_string() };
+                let mut settings = Self::resolve_from(manifest, &parent, env, depth + 1);
+                settings.name = name.to_string();
+                settings
+            }
+        };
+
+        for key in [
+            "opt-level",
+            "debug",
+            "strip",
+            "lto",
+            "codegen-units",
+            "panic",
+        ] {
+            if let Some(value) = get(key) {
+                settings.set(key, value);
+            }
+        }
+        settings
+    }
+
+    /// Sets `key`, spelled like in `Cargo.toml`. Keys that don't matter for size
+    /// analysis are ignored.
+    pub(crate) fn set(&mut self, key: &str, value: String) {
+        match key {
+            "opt-level" => self.opt_level = value,
+            "debug" => self.debug = value,
+            "strip" => self.strip = value,
+            "lto" => self.lto = value,
+            "codegen-units" => self.codegen_units = value.parse().ok(),
+            "panic" => self.panic = value,
+            _ => {}
+        }
+    }
+
+    /// Name of the directory under `target/` (or `target/<triple>/`) that holds
+    /// this profile's artifacts
+    pub fn dir_name(&self) -> &str {
+        match self.name.as_str() {
+            "dev" | "test" => "debug",
+            "bench" => "release",
+            other => other,
+        }
+    }
+
+    /// Whether the symbol table gets stripped, which leaves nothing to analyze
+    pub fn strips_symbols(&self) -> bool {
+        matches!(self.strip.as_str(), "symbols" | "true")
+    }
+
+    /// Whether debug info is disabled entirely. On Windows, symbols are read from
+    /// the PDB, which needs at least line tables.
+    pub fn lacks_debuginfo(&self) -> bool {
+        matches!(self.debug.as_str(), "0" | "false" | "none")
+    }
+
+    /// Whether cross-crate LTO is enabled. LTO internalizes and merges symbols, so
+    /// some of them can't be matched against the rlibs they came from.
+    pub fn uses_lto(&self) -> bool {
+        matches!(self.lto.as_str(), "true" | "fat" | "thin")
+    }
+
+    /// Overrides settings that break symbol attribution. Returns the environment
+    /// variables that apply them, and warns about what can't be overridden without
+    /// changing the binary.
+    pub(crate) fn apply_analysis_overrides(&mut self) -> Vec<(String, String)> {
+        let mut overrides = Vec::new();
+
+        if self.strips_symbols() {
+            warn!(
+                "Profile {} strips symbols (strip = {:?}), overriding it with strip = \"none\"",
+                self.name, self.strip
+            );
+            self.strip = "none".to_string();
+            overrides.push((profile_env_var(&self.name, "strip"), self.strip.clone()));
+        }
+
+        if self.lacks_debuginfo() {
+            debug!(
+                "Profile {} has no debug info, enabling line tables",
+                self.name
+            );
+            self.debug = "line-tables-only".to_string();
+            overrides.push((profile_env_var(&self.name, "debug"), self.debug.clone()));
+        }
+
+        if self.uses_lto() {
+            warn!(
+                "Profile {} enables LTO (lto = {:?}): some symbols will be merged or internalized, \
+                 and attributed to the wrong crate or not at all",
+                self.name, self.lto
+            );
+        }
+
+        overrides
+    }
+}
+
+/// Returns the name of the environment variable cargo reads to override `key`
+/// in profile `profile`, e.g. `CARGO_PROFILE_RELEASE_OPT_LEVEL`
+pub fn profile_env_var(profile: &str, key: &str) -> String {
+    format!("CARGO_PROFILE_{profile}_{key}")
+        .to_uppercase()
+        .replace('-', "_")
+}
+
+/// Spells a profile setting as a TOML value, for `--config`: integers and
+/// booleans are left bare (`opt-level=3`, `lto=true`), anything else is quoted
+/// (`opt-level="z"`, `lto="fat"`).
+pub(crate) fn toml_value(value: &str) -> String {
+    if value.parse::<i64>().is_ok() || value == "true" || value == "false" {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// Returns the `key = value` entries of the `[profile.<name>]` table of a manifest,
+/// with values spelled without quotes. Nested tables (`[profile.<name>.package.*]`,
+/// `[profile.<name>.build-override]`) are skipped.
+fn manifest_profile_entries(manifest: &str, name: &str) -> Vec<(String, String)> {
+    let manifest: toml::Table = match manifest.parse() {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            warn!("Could not parse the manifest to resolve profile {name}: {e}");
+            return Vec::new();
+        }
+    };
+    let profile = manifest
+        .get("profile")
+        .and_then(|profiles| profiles.get(name))
+        .and_then(toml::Value::as_table);
+
+    profile
+        .into_iter()
+        .flatten()
+        .filter_map(|(key, value)| {
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                _ => return None,
+            };
+            Some((key.clone(), value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"
+[package]
+name = "app"
+debug = "not-a-profile-key"
+
+[profile.release]
+strip = "symbols" # keep it "small"
+panic = "abort#1"
+lto = true
+codegen-units = 1
+
+[profile.release.package.foo]
+opt-level = 0
+
+[profile."size"]
+inherits = "release"
+opt-level = 'z'
+"#;
+
+    fn no_env(_profile: &str, _key: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn test_release_profile_from_manifest() {
+        let settings = ProfileSettings::resolve_from(MANIFEST, "release", &no_env, 0);
+        assert_eq!(settings.opt_level, "3");
+        assert_eq!(settings.strip, "symbols");
+        assert_eq!(settings.lto, "true");
+        assert_eq!(settings.codegen_units, Some(1));
+        assert_eq!(settings.panic, "abort#1");
+        assert_eq!(settings.dir_name(), "release");
+        assert!(settings.strips_symbols());
+        assert!(settings.lacks_debuginfo());
+        assert!(settings.uses_lto());
+    }
+
+    #[test]
+    fn test_custom_profile_inherits() {
+        let settings = ProfileSettings::resolve_from(MANIFEST, "size", &no_env, 0);
+        assert_eq!(settings.name, "size");
+        assert_eq!(settings.opt_level, "z");
+        assert_eq!(settings.lto, "true");
+        assert_eq!(settings.dir_name(), "size");
+    }
+
+    #[test]
+    fn test_env_overrides_manifest() {
+        let env = |profile: &str, key: &str| {
+            (profile == "release" && key == "strip").then(|| "debuginfo".to_string())
+        };
+        let settings = ProfileSettings::resolve_from(MANIFEST, "release", &env, 0);
+        assert_eq!(settings.strip, "debuginfo");
+        assert!(!settings.strips_symbols());
+
+        // Overrides of the parent profile are inherited
+        let settings = ProfileSettings::resolve_from(MANIFEST, "size", &env, 0);
+        assert_eq!(settings.strip, "debuginfo");
+    }
+
+    #[test]
+    fn test_analysis_overrides() {
+        let mut settings = ProfileSettings::resolve_from(MANIFEST, "release", &no_env, 0);
+        let overrides = settings.apply_analysis_overrides();
+        assert_eq!(
+            overrides,
+            vec![
+                (
+                    "CARGO_PROFILE_RELEASE_STRIP".to_string(),
+                    "none".to_string()
+                ),
+                (
+                    "CARGO_PROFILE_RELEASE_DEBUG".to_string(),
+                    "line-tables-only".to_string()
+                ),
+            ]
+        );
+        assert!(!settings.strips_symbols());
+
+        let mut settings = ProfileSettings::resolve_from("", "dev", &no_env, 0);
+        assert!(settings.apply_analysis_overrides().is_empty());
+        assert_eq!(settings.dir_name(), "debug");
+    }
+
+    #[test]
+    fn test_toml_value() {
+        assert_eq!(toml_value("3"), "3");
+        assert_eq!(toml_value("false"), "false");
+        assert_eq!(toml_value("z"), "\"z\"");
+        assert_eq!(toml_value("line-tables-only"), "\"line-tables-only\"");
+    }
+}
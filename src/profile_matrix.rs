@@ -0,0 +1,284 @@
+//! Builds of one manifest under several sets of profile overrides.
+//!
+//! Answers questions like "what would `opt-level = "z"` buy us?": the manifest
+//! is built once as-is, then once per [`ProfileConfig`], each in its own target
+//! directory (see [`crate::matrix`]), and every configuration is compared to
+//! the unmodified build.
+
+use camino::Utf8PathBuf;
+use log::{info, warn};
+
+use crate::errors::SubstanceError;
+use crate::formatting::{format_bytes, format_duration, format_duration_diff, format_size_diff};
+use crate::matrix::{BuildDelta, BuildOptions, CrateCost};
+use crate::BuildResult;
+
+/// A named set of profile overrides, see [`BuildRunner::profile_override`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileConfig {
+    pub name: String,
+
+    /// `(key, value)` settings for the whole build
+    pub overrides: Vec<(String, String)>,
+
+    /// `(package, key, value)` settings for one package
+    pub package_overrides: Vec<(String, String, String)>,
+}
+
+impl ProfileConfig {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            overrides: Vec::new(),
+            package_overrides: Vec::new(),
+        }
+    }
+
+    /// Sets `key` for the whole build, e.g. `("lto", "fat")`
+    pub fn set(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.overrides.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets `key` for `package` only, e.g. `("serde_json", "opt-level", "3")`
+    pub fn set_for_package(
+        mut self,
+        package: impl Into<String>,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.package_overrides
+            .push((package.into(), key.into(), value.into()));
+        self
+    }
+
+    /// The usual suspects: `opt-level = "z"`, `lto = "fat"`, `codegen-units = 1`
+    /// and `panic = "abort"`, one at a time
+    pub fn common() -> Vec<Self> {
+        vec![
+            Self::new("opt-level=z").set("opt-level", "z"),
+            Self::new("lto=fat").set("lto", "fat"),
+            Self::new("codegen-units=1").set("codegen-units", "1"),
+            Self::new("panic=abort").set("panic", "abort"),
+        ]
+    }
+}
+
+/// Builds a manifest once per [`ProfileConfig`], see the [module docs](self).
+pub struct ProfileMatrix {
+    /// The profile of the options is the one the overrides apply to
+    options: BuildOptions,
+    configs: Vec<ProfileConfig>,
+}
+
+impl ProfileMatrix {
+    /// A matrix of [`ProfileConfig::common`], use [`ProfileMatrix::configs`] to
+    /// pick others.
+    pub fn for_manifest(manifest_path: impl Into<Utf8PathBuf>) -> Self {
+        Self::new(BuildOptions::for_manifest(manifest_path))
+    }
+
+    /// See [`ProfileMatrix::for_manifest`]. Overrides apply to the profile of
+    /// `options`.
+    pub fn new(options: BuildOptions) -> Self {
+        Self {
+            options,
+            configs: ProfileConfig::common(),
+        }
+    }
+
+    /// Build these configurations instead of the common ones
+    pub fn configs(mut self, configs: impl IntoIterator<Item = ProfileConfig>) -> Self {
+        self.configs = configs.into_iter().collect();
+        self
+    }
+
+    /// Runs the baseline build, then one build per configuration. The baseline
+    /// failing is an error; configurations that fail are reported in
+    /// [`ProfileMatrixReport::failed`].
+    pub fn run(&self) -> Result<ProfileMatrixReport, SubstanceError> {
+        info!("Building baseline, without profile overrides");
+        let baseline = self.options.baseline_runner().run()?;
+
+        let mut configs = Vec::new();
+        let mut failed = Vec::new();
+        for (index, config) in self.configs.iter().enumerate() {
+            info!("Building configuration {}", config.name);
+            let mut runner = self.options.variant_runner(index, &config.name);
+            for (key, value) in &config.overrides {
+                runner = runner.profile_override(key, value);
+            }
+            for (package, key, value) in &config.package_overrides {
+                runner = runner.package_profile_override(package, key, value);
+            }
+            match runner.run() {
+                Ok(result) => configs.push(ConfigOutcome {
+                    config: config.clone(),
+                    delta: BuildDelta::between(baseline.context(), result.context()),
+                    result,
+                }),
+                Err(e) => {
+                    warn!("Configuration {} failed to build: {e}", config.name);
+                    failed.push((config.clone(), e));
+                }
+            }
+        }
+
+        Ok(ProfileMatrixReport {
+            baseline,
+            configs,
+            failed,
+        })
+    }
+}
+
+/// Result of [`ProfileMatrix::run`]
+pub struct ProfileMatrixReport {
+    /// Build without overrides
+    pub baseline: BuildResult,
+
+    /// Configurations that built, in the order they were given
+    pub configs: Vec<ConfigOutcome>,
+
+    pub failed: Vec<(ProfileConfig, SubstanceError)>,
+}
+
+impl ProfileMatrixReport {
+    /// A plain-text table with one row per configuration: file size, `.text`
+    /// size and build time with their change from the baseline, and the crates
+    /// that changed the most.
+    pub fn table(&self, top_crates: usize) -> String {
+        let context = self.baseline.context();
+        let mut rows = vec![
+            [
+                "configuration",
+                "file size",
+                ".text size",
+                "build time",
+                "top crate deltas",
+            ]
+            .map(String::from)
+            .to_vec(),
+            vec![
+                "baseline".to_string(),
+                format_bytes(context.file_size.value()),
+                format_bytes(context.text_size.value()),
+                context
+                    .wall_duration
+                    .as_ref()
+                    .map_or("?".to_string(), format_duration),
+                String::new(),
+            ],
+        ];
+
+        for outcome in &self.configs {
+            let context = outcome.result.context();
+            let mut crates: Vec<&CrateCost> = outcome.delta.crates.iter().collect();
+            crates.sort_by_key(|c| std::cmp::Reverse(c.size.unsigned_abs()));
+            let top: Vec<String> = crates
+                .iter()
+                .filter(|c| c.size != 0)
+                .take(top_crates)
+                .map(|c| format!("{} {}", c.crate_name, format_size_diff(c.size)))
+                .collect();
+            rows.push(vec![
+                outcome.config.name.clone(),
+                format!(
+                    "{} ({})",
+                    format_bytes(context.file_size.value()),
+                    format_size_diff(outcome.delta.file_size)
+                ),
+                format!(
+                    "{} ({})",
+                    format_bytes(context.text_size.value()),
+                    format_size_diff(outcome.delta.text_size)
+                ),
+                format!(
+                    "{} ({})",
+                    context
+                        .wall_duration
+                        .as_ref()
+                        .map_or("?".to_string(), format_duration),
+                    format_duration_diff(outcome.delta.build_time.value())
+                ),
+                top.join(", "),
+            ]);
+        }
+
+        for (config, _) in &self.failed {
+            rows.push(vec![
+                config.name.clone(),
+                "build failed".to_string(),
+                String::new(),
+                String::new(),
+                String::new(),
+            ]);
+        }
+
+        render_table(&rows)
+    }
+}
+
+/// A configuration that built
+pub struct ConfigOutcome {
+    pub config: ProfileConfig,
+    pub result: BuildResult,
+
+    /// Change from the baseline
+    pub delta: BuildDelta,
+}
+
+/// Left-aligned columns separated by two spaces, with a rule under the header
+fn render_table(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|i| {
+            rows.iter()
+                .filter_map(|row| row.get(i))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let mut out = String::new();
+    for (index, row) in rows.iter().enumerate() {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+        if index == 0 {
+            let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+            out.push_str(&rule.join("  "));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_table() {
+        let rows = vec![
+            vec!["configuration".to_string(), "file size".to_string()],
+            vec!["baseline".to_string(), "1.00 MiB".to_string()],
+            vec![
+                "lto=fat".to_string(),
+                "900.00 KiB (-124.00 KiB)".to_string(),
+            ],
+        ];
+        assert_eq!(
+            render_table(&rows),
+            "configuration  file size\n\
+             -------------  ------------------------\n\
+             baseline       1.00 MiB\n\
+             lto=fat        900.00 KiB (-124.00 KiB)\n"
+        );
+    }
+}
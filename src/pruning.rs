@@ -0,0 +1,536 @@
+//! Advisor for the default features of direct dependencies.
+//!
+//! The package is copied to a temporary directory, where each direct dependency
+//! that has its default features enabled is tried with `default-features =
+//! false` and only the features the manifest asks for. If that doesn't build,
+//! each default feature is added back on its own instead. Variants that build
+//! are compared to a build of the unmodified copy, and every build has its own
+//! target directory (see [`crate::matrix`]).
+//!
+//! The copy is built out of its workspace, with the workspace's `Cargo.lock`:
+//! packages that inherit fields from their workspace can't be pruned this way,
+//! and workspace profiles don't apply.
+
+use std::collections::HashMap;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use facet::Facet;
+use ignore::WalkBuilder;
+use log::{debug, info, warn};
+use toml_edit::visit_mut::{self, VisitMut};
+use toml_edit::{DocumentMut, InlineTable, Item, KeyMut, Value};
+
+use crate::env;
+use crate::errors::SubstanceError;
+use crate::matrix::{BuildDelta, BuildOptions};
+use crate::BuildResult;
+
+#[derive(Debug, Facet)]
+struct RawMetadata {
+    packages: Vec<RawPackage>,
+    resolve: Option<RawResolve>,
+    workspace_root: String,
+}
+
+#[derive(Debug, Facet)]
+struct RawPackage {
+    id: String,
+    name: String,
+    manifest_path: String,
+    features: HashMap<String, Vec<String>>,
+    dependencies: Vec<RawDependency>,
+}
+
+#[derive(Debug, Facet)]
+struct RawDependency {
+    /// Package name, which differs from the key in the manifest when renamed
+    name: String,
+
+    /// `None` for normal dependencies, `dev` or `build` otherwise
+    kind: Option<String>,
+    rename: Option<String>,
+    uses_default_features: bool,
+    features: Vec<String>,
+
+    /// Platform, for `[target.<platform>.dependencies]`
+    target: Option<String>,
+}
+
+impl RawDependency {
+    /// Key of the dependency in the manifest
+    fn key(&self) -> &str {
+        self.rename.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Path of the table the dependency is declared in, e.g. `["dependencies"]`
+    fn table(&self) -> Vec<&str> {
+        match &self.target {
+            Some(target) => vec!["target", target, "dependencies"],
+            None => vec!["dependencies"],
+        }
+    }
+}
+
+#[derive(Debug, Facet)]
+struct RawResolve {
+    nodes: Vec<RawNode>,
+}
+
+#[derive(Debug, Facet)]
+struct RawNode {
+    id: String,
+    deps: Vec<RawNodeDep>,
+}
+
+#[derive(Debug, Facet)]
+struct RawNodeDep {
+    pkg: String,
+}
+
+/// Tries to disable the default features of direct dependencies, see the
+/// [module docs](self).
+pub struct PruningAdvisor {
+    options: BuildOptions,
+
+    /// Keys of the dependencies to try, `None` means all of them
+    dependencies: Option<Vec<String>>,
+}
+
+impl PruningAdvisor {
+    pub fn for_manifest(manifest_path: impl Into<Utf8PathBuf>) -> Self {
+        Self::new(BuildOptions::for_manifest(manifest_path))
+    }
+
+    pub fn new(options: BuildOptions) -> Self {
+        Self {
+            options,
+            dependencies: None,
+        }
+    }
+
+    /// Only try these dependencies, by their key in the manifest
+    pub fn dependencies<S: Into<String>>(
+        mut self,
+        dependencies: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.dependencies = Some(dependencies.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Builds the unmodified copy, then every variant. The baseline failing is
+    /// an error, variants failing are expected.
+    pub fn run(&self) -> Result<PruningReport, SubstanceError> {
+        let manifest_path = self.options.manifest_path();
+        let metadata = read_metadata(manifest_path, self.options.selected_toolchain())?;
+        let canonical =
+            |path: &Utf8Path| path.canonicalize_utf8().unwrap_or_else(|_| path.to_owned());
+        let wanted = canonical(manifest_path);
+        let package = metadata
+            .packages
+            .iter()
+            .find(|p| canonical(Utf8Path::new(&p.manifest_path)) == wanted)
+            .ok_or_else(|| {
+                SubstanceError::CargoError(format!(
+                    "{} is not the manifest of a package (is it a virtual manifest?)",
+                    manifest_path
+                ))
+            })?;
+        let resolved: Vec<&String> = metadata
+            .resolve
+            .iter()
+            .flat_map(|r| &r.nodes)
+            .filter(|n| n.id == package.id)
+            .flat_map(|n| n.deps.iter().map(|d| &d.pkg))
+            .collect();
+        let default_features = |dep: &RawDependency| {
+            metadata
+                .packages
+                .iter()
+                .find(|p| p.name == dep.name && resolved.contains(&&p.id))
+                .and_then(|p| p.features.get("default"))
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        let temp_dir = tempfile::Builder::new()
+            .prefix("substance-pruning")
+            .tempdir()
+            .map_err(|e| {
+                SubstanceError::CargoError(format!("Failed to create temporary directory: {e}"))
+            })?;
+        let copy_dir = Utf8Path::from_path(temp_dir.path())
+            .ok_or_else(|| {
+                SubstanceError::CargoError("Temporary directory is not valid UTF-8".to_string())
+            })?
+            .to_owned();
+        let package_dir = wanted.parent().unwrap_or(&wanted);
+        copy_package(package_dir, &copy_dir)?;
+        let lockfile = Utf8Path::new(&metadata.workspace_root).join("Cargo.lock");
+        if lockfile.exists() {
+            copy_file(&lockfile, &copy_dir.join("Cargo.lock"))?;
+        }
+
+        let manifest: DocumentMut = read_file(manifest_path)?.parse().map_err(|e| {
+            SubstanceError::CargoError(format!("Failed to parse {}: {e}", manifest_path))
+        })?;
+        let manifest = standalone_manifest(&manifest, package_dir);
+        let copy = self.options.with_manifest(copy_dir.join("Cargo.toml"));
+        write_file(copy.manifest_path(), &manifest.to_string())?;
+
+        info!("Building baseline, the unmodified copy in {copy_dir}");
+        let baseline = copy.baseline_runner().run()?;
+
+        let mut variants = Vec::new();
+        let mut required = Vec::new();
+        let mut skipped = Vec::new();
+        let mut tried = 0;
+        for dep in package.dependencies.iter().filter(|d| d.kind.is_none()) {
+            let key = dep.key().to_string();
+            if let Some(selected) = &self.dependencies {
+                if !selected.contains(&key) {
+                    continue;
+                }
+            }
+            if !dep.uses_default_features {
+                debug!("{key} already has default features disabled");
+                continue;
+            }
+            let defaults = default_features(dep);
+            if defaults.is_empty() {
+                info!("Skipping {key}: it has no default features");
+                skipped.push((key, "it has no default features".to_string()));
+                continue;
+            }
+
+            if rewrite_dependency(&manifest, &dep.table(), &key, &[]).is_none() {
+                warn!("Could not find {key} in the manifest, skipping it");
+                skipped.push((key, "not found in the manifest".to_string()));
+                continue;
+            }
+
+            // The features the manifest asks for are the minimal set. If that
+            // doesn't build, try adding back each default feature on its own.
+            let mut try_variant = |features: &[String]| {
+                tried += 1;
+                try_variant(&copy, tried - 1, &manifest, dep, features, &baseline)
+            };
+            let mut built = try_variant(&dep.features)?.into_iter().collect::<Vec<_>>();
+            if built.is_empty() {
+                for feature in defaults.iter().filter(|f| !f.starts_with("dep:")) {
+                    if dep.features.contains(feature) {
+                        continue;
+                    }
+                    let mut features = dep.features.clone();
+                    features.push(feature.clone());
+                    built.extend(try_variant(&features)?);
+                }
+            }
+            if built.is_empty() {
+                required.push(key);
+            }
+            variants.extend(built);
+        }
+
+        variants.sort_by(|a, b| {
+            b.saved
+                .text_size
+                .cmp(&a.saved.text_size)
+                .then_with(|| a.dependency.cmp(&b.dependency))
+        });
+        Ok(PruningReport {
+            baseline,
+            variants,
+            required,
+            skipped,
+        })
+    }
+}
+
+/// Builds the copy with `dep` limited to `features`, as the `index`th variant.
+/// `None` if that fails.
+fn try_variant(
+    copy: &BuildOptions,
+    index: usize,
+    manifest: &DocumentMut,
+    dep: &RawDependency,
+    features: &[String],
+    baseline: &BuildResult,
+) -> Result<Option<PrunedVariant>, SubstanceError> {
+    let key = dep.key();
+    let modified = rewrite_dependency(manifest, &dep.table(), key, features)
+        .unwrap_or_else(|| manifest.clone());
+    write_file(copy.manifest_path(), &modified.to_string())?;
+
+    info!("Building with {key} pruned to features {features:?}");
+    match copy.variant_runner(index, key).run() {
+        Ok(result) => Ok(Some(PrunedVariant {
+            dependency: key.to_string(),
+            features: features.to_vec(),
+            saved: BuildDelta::between(result.context(), baseline.context()),
+            result,
+        })),
+        Err(e) => {
+            debug!("{key} with features {features:?} doesn't build: {e}");
+            Ok(None)
+        }
+    }
+}
+
+/// Result of [`PruningAdvisor::run`]
+pub struct PruningReport {
+    /// Build of the unmodified copy
+    pub baseline: BuildResult,
+
+    /// Variants that built, the largest `.text` savings first
+    pub variants: Vec<PrunedVariant>,
+
+    /// Dependencies that need some of their default features we couldn't pin
+    /// down: no variant built
+    pub required: Vec<String>,
+
+    /// Dependencies that weren't tried, and why
+    pub skipped: Vec<(String, String)>,
+}
+
+/// A dependency with default features disabled that still builds
+pub struct PrunedVariant {
+    /// Key of the dependency in the manifest
+    pub dependency: String,
+
+    /// The features it was built with, on top of `default-features = false`
+    pub features: Vec<String>,
+
+    pub result: BuildResult,
+
+    /// Change from this variant to the baseline: what pruning saves. Savings
+    /// are negative when the variant is larger or slower, e.g. because of noise
+    /// or because another dependency enables the same features anyway.
+    pub saved: BuildDelta,
+}
+
+fn read_metadata(
+    manifest_path: &Utf8Path,
+    toolchain: Option<&str>,
+) -> Result<RawMetadata, SubstanceError> {
+    let output = env::cargo(toolchain)
+        .args(["metadata", "--format-version", "1"])
+        .arg("--manifest-path")
+        .arg(manifest_path)
+        .output()
+        .map_err(|e| {
+            log::error!("Failed to execute cargo metadata: {e}");
+            SubstanceError::CargoMetadataFailed
+        })?;
+    if !output.status.success() {
+        return Err(SubstanceError::CargoError(format!(
+            "cargo metadata failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    facet_json::from_str(&String::from_utf8_lossy(&output.stdout))
+        .map_err(|e| SubstanceError::CargoError(format!("Invalid cargo metadata: {e}")))
+}
+
+/// Copies the package's files, minus what's ignored by git and `target/`
+fn copy_package(from: &Utf8Path, to: &Utf8Path) -> Result<(), SubstanceError> {
+    let walker = WalkBuilder::new(from)
+        .filter_entry(|entry| entry.depth() != 1 || entry.file_name() != "target")
+        .build();
+    for entry in walker {
+        let entry = entry.map_err(|e| {
+            SubstanceError::CargoError(format!("Error iterating {from} to copy it: {e}"))
+        })?;
+        let Some(path) = Utf8Path::from_path(entry.path()) else {
+            warn!("Not copying non-UTF8 path {:?}", entry.path());
+            continue;
+        };
+        let Ok(relative) = path.strip_prefix(from) else {
+            continue;
+        };
+        let dest = to.join(relative);
+        if path.is_dir() {
+            std::fs::create_dir_all(&dest)
+                .map_err(|e| SubstanceError::CargoError(format!("Failed to create {dest}: {e}")))?;
+        } else if path.is_file() {
+            copy_file(path, &dest)?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_file(from: &Utf8Path, to: &Utf8Path) -> Result<(), SubstanceError> {
+    std::fs::copy(from, to)
+        .map(|_| ())
+        .map_err(|e| SubstanceError::CargoError(format!("Failed to copy {from} to {to}: {e}")))
+}
+
+fn read_file(path: &Utf8Path) -> Result<String, SubstanceError> {
+    std::fs::read_to_string(path).map_err(|_| SubstanceError::OpenFailed(path.to_owned()))
+}
+
+fn write_file(path: &Utf8Path, contents: &str) -> Result<(), SubstanceError> {
+    std::fs::write(path, contents)
+        .map_err(|e| SubstanceError::CargoError(format!("Failed to write {path}: {e}")))
+}
+
+/// Makes a manifest buildable from another directory: relative `path`s point
+/// back to `package_dir`, and the package becomes its own workspace.
+fn standalone_manifest(manifest: &DocumentMut, package_dir: &Utf8Path) -> DocumentMut {
+    let mut manifest = manifest.clone();
+    AbsolutePaths(package_dir).visit_document_mut(&mut manifest);
+    if !manifest.contains_key("workspace") {
+        manifest.insert("workspace", Item::Table(Default::default()));
+    }
+    manifest
+}
+
+/// Rewrites relative `path = "..."` values to absolute paths, wherever they are
+struct AbsolutePaths<'a>(&'a Utf8Path);
+
+impl VisitMut for AbsolutePaths<'_> {
+    fn visit_table_like_kv_mut(&mut self, key: KeyMut<'_>, node: &mut Item) {
+        if key.get() == "path" {
+            if let Some(Value::String(path)) = node.as_value_mut() {
+                if Utf8Path::new(path.value()).is_relative() {
+                    let decor = path.decor().clone();
+                    let mut absolute =
+                        toml_edit::Formatted::new(self.0.join(path.value()).into_string());
+                    *absolute.decor_mut() = decor;
+                    *path = absolute;
+                }
+            }
+        }
+        visit_mut::visit_table_like_kv_mut(self, key, node);
+    }
+}
+
+/// Returns `manifest` with dependency `key` of `table` (e.g. `["dependencies"]`)
+/// limited to `features`, without default features. The rest of the entry
+/// (`version`, `path`, `git`, `package`...) is kept. Returns `None` if the
+/// dependency isn't declared.
+fn rewrite_dependency(
+    manifest: &DocumentMut,
+    table: &[&str],
+    key: &str,
+    features: &[String],
+) -> Option<DocumentMut> {
+    let mut manifest = manifest.clone();
+    let mut item = manifest.as_item_mut();
+    for name in table {
+        let parent = item.as_table_like_mut()?;
+        // Headers like `[target.'cfg( unix )'.dependencies]` may be spaced out
+        let same = |k: &str| {
+            k.chars()
+                .filter(|c| !c.is_whitespace())
+                .eq(name.chars().filter(|c| !c.is_whitespace()))
+        };
+        let name = parent.iter().map(|(k, _)| k).find(|k| same(k))?.to_string();
+        item = parent.get_mut(&name)?;
+    }
+    let entry = item.as_table_like_mut()?.get_mut(key)?;
+
+    // `dep = "1.0"` becomes `dep = { version = "1.0" }`
+    if let Some(Value::String(version)) = entry.as_value() {
+        let mut inline = InlineTable::new();
+        inline.insert("version", Value::from(version.value().as_str()));
+        let mut value = Value::InlineTable(inline);
+        *value.decor_mut() = version.decor().clone();
+        *entry = Item::Value(value);
+    }
+    let table = entry.as_table_like_mut()?;
+    table.remove("default_features");
+    table.insert("default-features", toml_edit::value(false));
+    let features: toml_edit::Array = features.iter().map(String::as_str).collect();
+    table.insert("features", toml_edit::value(features));
+    // Spacing left over from the removed and replaced values
+    if let Some(inline) = entry.as_inline_table_mut() {
+        inline.fmt();
+    }
+    Some(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"[package]
+name = "app"
+
+[dependencies]
+serde = { version = "1", features = ["derive"] } # for configs
+"regex" = "1.10"
+local = { path = "../local", package = "local-impl" }
+tools = { git = "https://example.com/tools", branch = "main", features = [
+    "cli",
+    "color",
+] }
+
+[dependencies.tokio]
+version = "1"
+features = ["rt"]
+
+[target.'cfg(unix)'.dependencies]
+libc = "0.2"
+"#;
+
+    fn rewrite(table: &[&str], key: &str, features: &[&str]) -> Option<String> {
+        let manifest: DocumentMut = MANIFEST.parse().unwrap();
+        let features: Vec<String> = features.iter().map(|f| f.to_string()).collect();
+        rewrite_dependency(&manifest, table, key, &features).map(|m| m.to_string())
+    }
+
+    #[test]
+    fn test_rewrite_dependency() {
+        let serde = rewrite(&["dependencies"], "serde", &["derive"]).unwrap();
+        assert!(serde.contains(
+            "serde = { version = \"1\", features = [\"derive\"], default-features = false } # for configs\n\"regex\""
+        ), "{}", serde);
+
+        let regex = rewrite(&["dependencies"], "regex", &[]).unwrap();
+        assert!(
+            regex.contains(
+                "\"regex\" = { version = \"1.10\", default-features = false, features = [] }\n"
+            ),
+            "{}",
+            regex
+        );
+
+        let local = rewrite(&["dependencies"], "local", &["fast"]).unwrap();
+        assert!(local.contains(
+            "local = { path = \"../local\", package = \"local-impl\", default-features = false, features = [\"fast\"] }\n"
+        ), "{}", local);
+
+        let tools = rewrite(&["dependencies"], "tools", &[]).unwrap();
+        assert!(tools.contains(
+            "tools = { git = \"https://example.com/tools\", branch = \"main\", features = [], default-features = false }\n"
+        ), "{}", tools);
+
+        let tokio = rewrite(&["dependencies"], "tokio", &["rt"]).unwrap();
+        assert!(tokio.contains(
+            "[dependencies.tokio]\nversion = \"1\"\nfeatures = [\"rt\"]\ndefault-features = false\n\n[target"
+        ), "{}", tokio);
+
+        let libc = rewrite(&["target", "cfg(unix)", "dependencies"], "libc", &[]).unwrap();
+        assert!(
+            libc.ends_with(
+                "libc = { version = \"0.2\", default-features = false, features = [] }\n"
+            ),
+            "{}",
+            libc
+        );
+
+        assert_eq!(rewrite(&["dependencies"], "libc", &[]), None);
+    }
+
+    #[test]
+    fn test_standalone_manifest() {
+        let manifest =
+            standalone_manifest(&MANIFEST.parse().unwrap(), Utf8Path::new("/src/app")).to_string();
+        assert!(
+            manifest.contains("path = \"/src/app/../local\""),
+            "{}",
+            manifest
+        );
+        assert!(manifest.ends_with("\n[workspace]\n"), "{}", manifest);
+    }
+}
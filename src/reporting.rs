@@ -1 +1,168 @@
+//! Reports derived from analysis results.
 
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
+
+use crate::formatting::{format_duration, format_percentage};
+use crate::types::{Crate, CrateName, TypeLayout, TypeNameRef};
+
+/// How a crate's build time splits around the moment its metadata was ready.
+///
+/// With pipelining, dependents start as soon as metadata is ready, so a long
+/// codegen tail overlaps with the rest of the build, while a long frontend holds
+/// up everything downstream. Crates with the longest tails are where trimming
+/// generics or lowering `opt-level` pays off.
+#[derive(Clone, Debug)]
+pub struct CodegenTail {
+    pub crate_name: CrateName,
+    pub frontend: Duration,
+    pub codegen: Duration,
+}
+
+impl CodegenTail {
+    pub fn total(&self) -> Duration {
+        self.frontend + self.codegen
+    }
+
+    /// Share of the crate's build time spent in codegen, between 0 and 1
+    pub fn codegen_ratio(&self) -> f64 {
+        let total = self.total().as_secs_f64();
+        if total == 0.0 {
+            0.0
+        } else {
+            self.codegen.as_secs_f64() / total
+        }
+    }
+}
+
+impl fmt::Display for CodegenTail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} frontend + {} codegen ({} codegen)",
+            self.crate_name,
+            format_duration(&self.frontend),
+            format_duration(&self.codegen),
+            format_percentage(self.codegen_ratio() * 100.0)
+        )
+    }
+}
+
+/// Ranks crates by codegen tail, longest first. Crates without a frontend/codegen
+/// split (binaries, or crates we have no timing for) are left out.
+pub fn codegen_tails<'a>(crates: impl IntoIterator<Item = &'a Crate>) -> Vec<CodegenTail> {
+    let mut tails: Vec<CodegenTail> = crates
+        .into_iter()
+        .filter_map(|krate| {
+            Some(CodegenTail {
+                crate_name: krate.name.clone(),
+                frontend: krate.frontend_duration?,
+                codegen: krate.codegen_duration?,
+            })
+        })
+        .collect();
+    tails.sort_by(|a, b| {
+        b.codegen
+            .cmp(&a.codegen)
+            .then_with(|| a.crate_name.cmp(&b.crate_name))
+    });
+    tails
+}
+
+/// Type layouts of `crates`, largest first. Types used by several crates (like
+/// `Option<String>`) are listed once.
+pub fn largest_types<'a>(crates: impl IntoIterator<Item = &'a Crate>) -> Vec<&'a TypeLayout> {
+    let mut layouts = unique_layouts(crates);
+    layouts.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+    layouts
+}
+
+/// Type layouts of `crates` with padding, most padding first. Reordering fields
+/// doesn't always help, since rustc already does it for `repr(Rust)` types, but
+/// boxing large fields or variants does.
+pub fn most_padded_types<'a>(crates: impl IntoIterator<Item = &'a Crate>) -> Vec<&'a TypeLayout> {
+    let mut layouts: Vec<&TypeLayout> = unique_layouts(crates)
+        .into_iter()
+        .filter(|l| l.total_padding().value() > 0)
+        .collect();
+    layouts.sort_by(|a, b| {
+        b.total_padding()
+            .cmp(&a.total_padding())
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    layouts
+}
+
+fn unique_layouts<'a>(crates: impl IntoIterator<Item = &'a Crate>) -> Vec<&'a TypeLayout> {
+    let mut seen: HashSet<&TypeNameRef> = HashSet::new();
+    crates
+        .into_iter()
+        .flat_map(|krate| krate.type_layouts.values())
+        .filter(|layout| seen.insert(&layout.name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ByteSize, TypeName};
+
+    fn layout(name: &str, size: u64, padding: u64) -> TypeLayout {
+        TypeLayout {
+            name: TypeName::from(name),
+            size: ByteSize::new(size),
+            alignment: ByteSize::new(8u64),
+            discriminant: None,
+            fields: Vec::new(),
+            variants: Vec::new(),
+            padding: ByteSize::new(padding),
+        }
+    }
+
+    #[test]
+    fn test_type_layout_reports() {
+        let a = Crate::named("a")
+            .with_type_layout(layout("Big", 256, 0))
+            .with_type_layout(layout("Shared", 16, 7));
+        let b = Crate::named("b")
+            .with_type_layout(layout("Shared", 16, 7))
+            .with_type_layout(layout("Padded", 24, 12));
+
+        let largest: Vec<&str> = largest_types([&a, &b])
+            .iter()
+            .map(|l| l.name.as_str())
+            .collect();
+        assert_eq!(largest, ["Big", "Padded", "Shared"]);
+        let padded: Vec<&str> = most_padded_types([&a, &b])
+            .iter()
+            .map(|l| l.name.as_str())
+            .collect();
+        assert_eq!(padded, ["Padded", "Shared"]);
+    }
+
+    #[test]
+    fn test_codegen_tails_ranking() {
+        let krate = |name: &str, frontend, codegen| {
+            Crate::named(name).with_durations(
+                Duration::from_millis(frontend),
+                Duration::from_millis(codegen),
+            )
+        };
+        let crates = [
+            krate("serde", 800, 200),
+            Crate::named("app"),
+            krate("regex", 500, 1500),
+            krate("syn", 1000, 1000),
+        ];
+        let tails = codegen_tails(&crates);
+        let names: Vec<&str> = tails.iter().map(|t| t.crate_name.as_str()).collect();
+        assert_eq!(names, ["regex", "syn", "serde"]);
+        assert_eq!(tails[0].total(), Duration::from_secs(2));
+        assert_eq!(tails[0].codegen_ratio(), 0.75);
+        assert_eq!(
+            tails[0].to_string(),
+            "regex: 0.50s frontend + 1.50s codegen (75.0% codegen)"
+        );
+    }
+}
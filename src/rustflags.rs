@@ -0,0 +1,144 @@
+//! Rustflags resolution.
+//!
+//! We need a few flags of our own (to emit LLVM IR and keep symbols around), but
+//! setting `RUSTFLAGS` would silently drop whatever the user configured. Instead,
+//! we resolve the flags cargo would have used, add ours on top, and pass the result
+//! with `CARGO_ENCODED_RUSTFLAGS`, which takes precedence over everything else.
+
+use camino::Utf8Path;
+use log::{debug, warn};
+
+use crate::env;
+use crate::types::TargetTripleRef;
+
+/// Flags substance needs for its analysis.
+pub const ANALYSIS_RUSTFLAGS: &[&str] = &[
+    "--emit=llvm-ir",
+    "-Cdebuginfo=line-tables-only",
+    "-Cstrip=none",
+];
+
+/// Separator used by `CARGO_ENCODED_RUSTFLAGS`
+const ENCODED_SEPARATOR: char = '\x1f';
+
+/// Returns the rustflags cargo would use for a build, following its precedence rules:
+///
+///   1. `CARGO_ENCODED_RUSTFLAGS`
+///   2. `RUSTFLAGS`
+///   3. `target.<triple>.rustflags`
+///   4. `build.rustflags`
+///
+/// Config values are looked up from `cwd`, like cargo does, with the cargo of
+/// `toolchain`. `target.<cfg>.rustflags` entries are not evaluated.
+pub(crate) fn user_rustflags(
+    cwd: &Utf8Path,
+    target: Option<&TargetTripleRef>,
+    toolchain: Option<&str>,
+) -> Vec<String> {
+    if let Ok(encoded) = std::env::var("CARGO_ENCODED_RUSTFLAGS") {
+        return decode(&encoded);
+    }
+    if let Ok(flags) = std::env::var("RUSTFLAGS") {
+        return flags.split_whitespace().map(String::from).collect();
+    }
+    if let Some(target) = target {
+        let key = format!("target.{target}.rustflags");
+        if let Some(flags) = config_get(cwd, &key, toolchain) {
+            return flags;
+        }
+    }
+    config_get(cwd, "build.rustflags", toolchain).unwrap_or_default()
+}
+
+/// Adds `extra` flags on top of `user` flags, then drops every flag listed in `removed`.
+pub(crate) fn merge(user: Vec<String>, extra: &[String], removed: &[String]) -> Vec<String> {
+    let mut flags = user;
+    flags.extend(extra.iter().cloned());
+    flags.retain(|flag| !removed.contains(flag));
+    flags
+}
+
+/// Encodes flags for `CARGO_ENCODED_RUSTFLAGS`
+pub(crate) fn encode(flags: &[String]) -> String {
+    flags.join(&ENCODED_SEPARATOR.to_string())
+}
+
+fn decode(encoded: &str) -> Vec<String> {
+    if encoded.is_empty() {
+        return Vec::new();
+    }
+    encoded.split(ENCODED_SEPARATOR).map(String::from).collect()
+}
+
+/// Reads a rustflags-like config value with `cargo config get`. Returns `None` if it's unset.
+fn config_get(cwd: &Utf8Path, key: &str, toolchain: Option<&str>) -> Option<Vec<String>> {
+    let output = env::cargo(toolchain)
+        .args(["-Z", "unstable-options", "config", "get"])
+        .args(["--format", "json-value", key])
+        .env("RUSTC_BOOTSTRAP", "1")
+        .current_dir(cwd)
+        .output();
+
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            warn!("Failed to execute cargo to read config value {key}: {e}");
+            return None;
+        }
+    };
+    if !output.status.success() {
+        debug!("Config value {key} is not set");
+        return None;
+    }
+
+    let value = String::from_utf8_lossy(&output.stdout);
+    let flags = parse_config_value(&value);
+    if flags.is_none() {
+        warn!("Could not parse config value {key}: {value}");
+    }
+    flags
+}
+
+/// Rustflags in cargo config are either an array of strings, or a single
+/// space-separated string.
+fn parse_config_value(value: &str) -> Option<Vec<String>> {
+    let value = value.trim();
+    if value.starts_with('[') {
+        facet_json::from_str::<Vec<String>>(value).ok()
+    } else {
+        let flags = facet_json::from_str::<String>(value).ok()?;
+        Some(flags.split_whitespace().map(String::from).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let flags = strings(&["--cfg", "tokio_unstable", "-Clink-arg=-fuse-ld=mold a b"]);
+        assert_eq!(decode(&encode(&flags)), flags);
+        assert!(decode("").is_empty());
+    }
+
+    #[test]
+    fn test_merge() {
+        let user = strings(&["-Ctarget-cpu=native", "--cfg", "tokio_unstable"]);
+        let extra = strings(&["--emit=llvm-ir", "-Cstrip=none"]);
+        let removed = strings(&["-Cstrip=none"]);
+        assert_eq!(
+            merge(user, &extra, &removed),
+            strings(&[
+                "-Ctarget-cpu=native",
+                "--cfg",
+                "tokio_unstable",
+                "--emit=llvm-ir"
+            ])
+        );
+    }
+}
@@ -0,0 +1,534 @@
+//! Reading rustc's self-profile (`-Zself-profile`).
+//!
+//! Cargo's timings say how long a crate took to build, not why. Rustc can record
+//! every query and activity it runs in a `.mm_profdata` file: we read those and
+//! sum the time spent in each, like `summarize` from `measureme` does.
+//!
+//! The format is `measureme`'s paged file: a header, then pages of three
+//! interleaved streams (events, string data and string index).
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::time::Duration;
+
+use camino::Utf8Path;
+use log::{debug, warn};
+
+use crate::types::CrateName;
+
+const FILE_MAGIC: &[u8; 4] = b"MMPD";
+const FILE_VERSION: u32 = 9;
+
+/// Every stream starts with a 4 byte magic and a 4 byte version
+const STREAM_HEADER_LEN: usize = 8;
+
+const PAGE_EVENTS: u8 = 0;
+const PAGE_STRING_DATA: u8 = 1;
+const PAGE_STRING_INDEX: u8 = 2;
+
+const RAW_EVENT_LEN: usize = 32;
+const INDEX_ENTRY_LEN: usize = 16;
+
+/// Ends of instant and integer events, which don't take any time
+const MAX_INTERVAL_END: u64 = 0xFFFF_FFFF_FFFD;
+
+/// Ids above this one that aren't in the index point directly into string data
+const FIRST_REGULAR_STRING_ID: u64 = 100_000_003;
+
+const STRING_REF_TAG: u8 = 0xFE;
+const STRING_TERMINATOR: u8 = 0xFF;
+
+/// Separates the label of an event from its arguments
+const ARGUMENT_SEPARATOR: char = '\u{1E}';
+
+/// Activity recorded around each call into a proc macro. With
+/// `-Zself-profile-events=args`, its first argument describes the macro, e.g.
+/// `#[derive(Facet)]`, `#[tokio::main]` or `sql!`.
+const EXPAND_PROC_MACRO: &str = "expand_proc_macro";
+
+/// Time spent in one query or activity, e.g. `typeck`, `mir_borrowck`,
+/// `monomorphization_collector_graph_walk` or `LLVM_module_optimize`
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryTime {
+    pub label: String,
+
+    /// Time spent in the query itself, excluding the queries it called
+    pub self_time: Duration,
+
+    /// How many times the query ran (cache hits are not counted)
+    pub invocations: usize,
+}
+
+/// Time spent running one proc macro while compiling a crate
+#[derive(Clone, Debug, PartialEq)]
+pub struct MacroExpansion {
+    /// How the macro was invoked, e.g. `#[derive(Facet)]` or `sql!`
+    pub invocation: String,
+
+    /// Time spent in the proc macro, over all expansions
+    pub time: Duration,
+
+    pub expansions: usize,
+}
+
+impl MacroExpansion {
+    /// Name of the macro, without the path it was invoked with: `Facet` for
+    /// `#[derive(facet::Facet)]`, `main` for `#[tokio::main]`
+    pub fn macro_name(&self) -> &str {
+        let path = self.path();
+        path.rsplit("::").next().unwrap_or(path).trim()
+    }
+
+    /// The crate the macro was invoked through, if its path names one: `facet`
+    /// for `#[derive(facet::Facet)]`, `None` for `#[derive(Facet)]`
+    pub fn crate_prefix(&self) -> Option<&str> {
+        let path = self.path();
+        let path = path.strip_prefix("::").unwrap_or(path);
+        let (prefix, _) = path.split_once("::")?;
+        let prefix = prefix.trim();
+        (!matches!(prefix, "crate" | "self" | "super" | "$crate")).then_some(prefix)
+    }
+
+    /// The macro's path, e.g. `facet::Facet` for `#[derive(facet::Facet)]`
+    fn path(&self) -> &str {
+        let path = self.invocation.trim();
+        path.strip_prefix("#[derive(")
+            .and_then(|p| p.strip_suffix(")]"))
+            .or_else(|| path.strip_prefix("#[").and_then(|p| p.strip_suffix(']')))
+            .or_else(|| path.strip_suffix('!'))
+            .unwrap_or(path)
+    }
+}
+
+/// A crate's compilation, broken down by query
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SelfProfile {
+    /// Most expensive first
+    pub queries: Vec<QueryTime>,
+
+    /// Proc macros that ran, most expensive first. Empty unless the profile was
+    /// recorded with `-Zself-profile-events=default,args`.
+    pub macro_expansions: Vec<MacroExpansion>,
+}
+
+impl SelfProfile {
+    /// Time covered by the profile. With a parallel frontend or several codegen
+    /// threads this is more than the wall time.
+    pub fn total(&self) -> Duration {
+        self.queries.iter().map(|q| q.self_time).sum()
+    }
+
+    pub fn get(&self, label: &str) -> Option<&QueryTime> {
+        self.queries.iter().find(|q| q.label == label)
+    }
+
+    /// Time spent running proc macros
+    pub fn macro_expansion_time(&self) -> Duration {
+        self.macro_expansions.iter().map(|e| e.time).sum()
+    }
+
+    /// Parses the contents of a `.mm_profdata` file
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let streams = Streams::split(data)?;
+        let strings = StringTable::new(&streams.string_data, &streams.string_index);
+
+        // Intervals per thread, and the label of each
+        let mut threads: HashMap<u32, Vec<(u64, u64, usize)>> = HashMap::new();
+        let mut labels: Vec<String> = Vec::new();
+        let mut label_ids: HashMap<String, usize> = HashMap::new();
+        let mut macro_expansions: Vec<MacroExpansion> = Vec::new();
+        for raw in streams.events.chunks_exact(RAW_EVENT_LEN) {
+            let event_id = u64_at(raw, 8);
+            let thread_id = u32_at(raw, 16);
+            let start_lower = u32_at(raw, 20) as u64;
+            let end_lower = u32_at(raw, 24) as u64;
+            let upper = u32_at(raw, 28) as u64;
+            let start = start_lower | ((upper >> 16) << 32);
+            let end = end_lower | ((upper & 0xFFFF) << 32);
+            if end > MAX_INTERVAL_END {
+                continue;
+            }
+
+            let event_id = strings.get(event_id);
+            let mut parts = event_id.split(ARGUMENT_SEPARATOR);
+            let label = parts.next().unwrap_or_default().to_string();
+            if let (EXPAND_PROC_MACRO, Some(invocation)) = (label.as_str(), parts.next()) {
+                let time = Duration::from_nanos(end.saturating_sub(start));
+                match macro_expansions
+                    .iter_mut()
+                    .find(|e| e.invocation == invocation)
+                {
+                    Some(expansion) => {
+                        expansion.time += time;
+                        expansion.expansions += 1;
+                    }
+                    None => macro_expansions.push(MacroExpansion {
+                        invocation: invocation.to_string(),
+                        time,
+                        expansions: 1,
+                    }),
+                }
+            }
+            let next_id = labels.len();
+            let id = *label_ids.entry(label.clone()).or_insert(next_id);
+            if id == next_id {
+                labels.push(label);
+            }
+            threads
+                .entry(thread_id)
+                .or_default()
+                .push((start, end.max(start), id));
+        }
+
+        // Self time is the duration of an interval minus the duration of the
+        // intervals directly nested in it, on the same thread.
+        let mut self_nanos = vec![0u64; labels.len()];
+        let mut invocations = vec![0usize; labels.len()];
+        for intervals in threads.values_mut() {
+            // Parents before their children
+            intervals.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+            let mut stack: Vec<(u64, u64, usize)> = Vec::new();
+            for &(start, end, id) in intervals.iter() {
+                while stack.last().is_some_and(|parent| parent.1 <= start) {
+                    stack.pop();
+                }
+                let duration = end - start;
+                if let Some(parent) = stack.last() {
+                    self_nanos[parent.2] = self_nanos[parent.2].saturating_sub(duration);
+                }
+                self_nanos[id] += duration;
+                invocations[id] += 1;
+                stack.push((start, end, id));
+            }
+        }
+
+        let mut queries: Vec<QueryTime> = labels
+            .into_iter()
+            .zip(self_nanos)
+            .zip(invocations)
+            .map(|((label, nanos), invocations)| QueryTime {
+                label,
+                self_time: Duration::from_nanos(nanos),
+                invocations,
+            })
+            .collect();
+        queries.sort_by(|a, b| {
+            b.self_time
+                .cmp(&a.self_time)
+                .then_with(|| a.label.cmp(&b.label))
+        });
+        macro_expansions.sort_by(|a, b| {
+            b.time
+                .cmp(&a.time)
+                .then_with(|| a.invocation.cmp(&b.invocation))
+        });
+        Ok(Self {
+            queries,
+            macro_expansions,
+        })
+    }
+}
+
+/// The three streams of a profile, with their headers stripped
+struct Streams {
+    events: Vec<u8>,
+    string_data: Vec<u8>,
+    string_index: Vec<u8>,
+}
+
+impl Streams {
+    fn split(data: &[u8]) -> Result<Self, String> {
+        if data.len() < 8 || &data[..4] != FILE_MAGIC {
+            return Err("not a measureme profile".to_string());
+        }
+        let version = u32_at(data, 4);
+        if version != FILE_VERSION {
+            return Err(format!(
+                "unsupported profile version {version}, expected {FILE_VERSION}"
+            ));
+        }
+
+        let mut streams = [Vec::new(), Vec::new(), Vec::new()];
+        let mut pos = 8;
+        while pos < data.len() {
+            if pos + 5 > data.len() {
+                return Err(format!("truncated page header at offset {pos}"));
+            }
+            let tag = data[pos];
+            let len = u32_at(data, pos + 1) as usize;
+            let page = data
+                .get(pos + 5..pos + 5 + len)
+                .ok_or_else(|| format!("truncated page at offset {pos}"))?;
+            match tag {
+                PAGE_EVENTS | PAGE_STRING_DATA | PAGE_STRING_INDEX => {
+                    streams[tag as usize].extend_from_slice(page)
+                }
+                _ => return Err(format!("unknown page tag {tag} at offset {pos}")),
+            }
+            pos += 5 + len;
+        }
+
+        let [events, string_data, string_index] = streams;
+        if events.len() < STREAM_HEADER_LEN || string_index.len() < STREAM_HEADER_LEN {
+            return Err("missing stream header".to_string());
+        }
+        Ok(Self {
+            events: events[STREAM_HEADER_LEN..].to_vec(),
+            // String addresses include the stream header
+            string_data,
+            string_index: string_index[STREAM_HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+struct StringTable<'a> {
+    data: &'a [u8],
+    index: HashMap<u64, u64>,
+}
+
+impl<'a> StringTable<'a> {
+    fn new(data: &'a [u8], index: &[u8]) -> Self {
+        let index = index
+            .chunks_exact(INDEX_ENTRY_LEN)
+            .map(|entry| (u64_at(entry, 0), u64_at(entry, 8)))
+            .collect();
+        Self { data, index }
+    }
+
+    /// Resolves a string id, or returns `<unknown>`
+    fn get(&self, id: u64) -> String {
+        let mut out = String::new();
+        if !self.append(id, &mut out, 0) {
+            return "<unknown>".to_string();
+        }
+        out
+    }
+
+    fn append(&self, id: u64, out: &mut String, depth: usize) -> bool {
+        let addr = match self.index.get(&id) {
+            Some(addr) => *addr,
+            None if id >= FIRST_REGULAR_STRING_ID => id - FIRST_REGULAR_STRING_ID,
+            None => return false,
+        };
+        // References can nest, but not that deep
+        if depth > 16 {
+            return false;
+        }
+
+        let mut pos = addr as usize;
+        loop {
+            match self.data.get(pos) {
+                None => return false,
+                Some(&STRING_TERMINATOR) => return true,
+                Some(&STRING_REF_TAG) => {
+                    let Some(reference) = self.data.get(pos + 1..pos + 9) else {
+                        return false;
+                    };
+                    if !self.append(u64_at(reference, 0), out, depth + 1) {
+                        return false;
+                    }
+                    pos += 9;
+                }
+                Some(_) => {
+                    let len = self.data[pos..]
+                        .iter()
+                        .position(|b| *b == STRING_REF_TAG || *b == STRING_TERMINATOR)
+                        .unwrap_or(self.data.len() - pos);
+                    out.push_str(&String::from_utf8_lossy(&self.data[pos..pos + len]));
+                    pos += len;
+                }
+            }
+        }
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Reads the profiles rustc wrote in `dir`, named `<crate>-<pid>.mm_profdata`.
+/// When a crate was compiled more than once (a lib and a bin with the same name),
+/// the longest profile wins.
+pub(crate) fn read_profiles(dir: &Utf8Path) -> HashMap<CrateName, SelfProfile> {
+    let mut profiles: HashMap<CrateName, SelfProfile> = HashMap::new();
+    let entries = match dir.read_dir_utf8() {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("No self-profile data in {dir}: {e}");
+            return profiles;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension() != Some("mm_profdata") {
+            continue;
+        }
+        let Some((crate_name, _pid)) = path.file_stem().and_then(|s| s.rsplit_once('-')) else {
+            continue;
+        };
+        // Every build script is called `build_script_build`
+        if crate_name.starts_with("build_script_") {
+            continue;
+        }
+        let profile = match std::fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| SelfProfile::parse(&data))
+        {
+            Ok(profile) => profile,
+            Err(e) => {
+                warn!("Failed to read self-profile {path}: {e}");
+                continue;
+            }
+        };
+        debug!(
+            "Read self-profile of {crate_name}: {} queries, {:?}",
+            profile.queries.len(),
+            profile.total()
+        );
+
+        let crate_name = CrateName::from(crate_name);
+        let replace = profiles
+            .get(&crate_name)
+            .is_none_or(|existing| profile.total() > existing.total());
+        if replace {
+            profiles.insert(crate_name, profile);
+        }
+    }
+    profiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a profile with one string per label, and the given
+    /// `(label, thread, start, end)` events
+    fn profile_data(labels: &[&str], events: &[(usize, u32, u64, u64)]) -> Vec<u8> {
+        let mut string_data = b"MMSD\0\0\0\0".to_vec();
+        let mut string_index = b"MMSI\0\0\0\0".to_vec();
+        let mut ids = Vec::new();
+        for (i, label) in labels.iter().enumerate() {
+            let id = i as u64 + 1;
+            string_index.extend_from_slice(&id.to_le_bytes());
+            string_index.extend_from_slice(&(string_data.len() as u64).to_le_bytes());
+            string_data.extend_from_slice(label.as_bytes());
+            string_data.push(STRING_TERMINATOR);
+            ids.push(id);
+        }
+
+        let mut event_data = b"MMES\0\0\0\0".to_vec();
+        for &(label, thread, start, end) in events {
+            event_data.extend_from_slice(&0u64.to_le_bytes());
+            event_data.extend_from_slice(&ids[label].to_le_bytes());
+            event_data.extend_from_slice(&thread.to_le_bytes());
+            event_data.extend_from_slice(&(start as u32).to_le_bytes());
+            event_data.extend_from_slice(&(end as u32).to_le_bytes());
+            let upper = ((start >> 32) << 16) as u32 | (end >> 32) as u32;
+            event_data.extend_from_slice(&upper.to_le_bytes());
+        }
+
+        let mut data = FILE_MAGIC.to_vec();
+        data.extend_from_slice(&FILE_VERSION.to_le_bytes());
+        for (tag, stream) in [
+            (PAGE_STRING_INDEX, string_index),
+            (PAGE_EVENTS, event_data),
+            (PAGE_STRING_DATA, string_data),
+        ] {
+            data.push(tag);
+            data.extend_from_slice(&(stream.len() as u32).to_le_bytes());
+            data.extend_from_slice(&stream);
+        }
+        data
+    }
+
+    #[test]
+    fn test_self_time_excludes_nested_queries() {
+        let data = profile_data(
+            &["typeck", "mir_borrowck", "LLVM_passes\u{1E}arg"],
+            &[
+                (0, 1, 0, 100),
+                (1, 1, 10, 40),
+                (0, 1, 50, 60),
+                (1, 1, 200, 230),
+                (2, 2, 0, 500),
+                // Instant event
+                (2, 2, 10, 0xFFFF_FFFF_FFFF),
+            ],
+        );
+        let profile = SelfProfile::parse(&data).unwrap();
+        let summary: Vec<(&str, u64, usize)> = profile
+            .queries
+            .iter()
+            .map(|q| {
+                (
+                    q.label.as_str(),
+                    q.self_time.as_nanos() as u64,
+                    q.invocations,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("LLVM_passes", 500, 1),
+                ("typeck", 70, 2),
+                ("mir_borrowck", 60, 2),
+            ]
+        );
+        assert_eq!(profile.total(), Duration::from_nanos(630));
+    }
+
+    #[test]
+    fn test_macro_expansions() {
+        let data = profile_data(
+            &[
+                "expand_proc_macro\u{1E}#[derive(facet::Facet)]\u{1E}src/lib.rs:2:10: 2:15",
+                "expand_proc_macro\u{1E}#[derive(facet::Facet)]\u{1E}src/lib.rs:8:10: 8:15",
+                "expand_proc_macro\u{1E}sql!\u{1E}src/lib.rs:12:1: 12:5",
+                "expand_proc_macro",
+            ],
+            &[
+                (0, 1, 0, 30),
+                (1, 1, 40, 60),
+                (2, 1, 100, 110),
+                (3, 1, 200, 300),
+            ],
+        );
+        let profile = SelfProfile::parse(&data).unwrap();
+        let expansions: Vec<(&str, &str, Option<&str>, u64, usize)> = profile
+            .macro_expansions
+            .iter()
+            .map(|e| {
+                (
+                    e.invocation.as_str(),
+                    e.macro_name(),
+                    e.crate_prefix(),
+                    e.time.as_nanos() as u64,
+                    e.expansions,
+                )
+            })
+            .collect();
+        assert_eq!(
+            expansions,
+            [
+                ("#[derive(facet::Facet)]", "Facet", Some("facet"), 50, 2),
+                ("sql!", "sql", None, 10, 1),
+            ]
+        );
+        assert_eq!(profile.macro_expansion_time(), Duration::from_nanos(60));
+        assert_eq!(profile.get("expand_proc_macro").unwrap().invocations, 4);
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        assert!(SelfProfile::parse(b"\x7fELF\x02\x01\x01\0").is_err());
+        let mut data = profile_data(&[], &[]);
+        data[4] = 8;
+        assert!(SelfProfile::parse(&data).is_err());
+    }
+}
@@ -0,0 +1,756 @@
+//! Saving a [`BuildContext`] to disk, and loading it back.
+//!
+//! Snapshots are JSON files with a `format` and a `version` field, so that an
+//! analysis saved on CI can be compared later, on another machine. The schema
+//! is separate from the in-memory types: it only changes along with
+//! [`SNAPSHOT_VERSION`], and loading a snapshot of another version is an error
+//! rather than a silently incomplete context.
+//!
+//! Maps are written as lists sorted by name, so that snapshots of identical
+//! builds are identical. Durations are written in nanoseconds.
+
+use std::time::Duration;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use facet::Facet;
+use log::info;
+use multimap::MultiMap;
+
+use crate::cargo::{CargoTarget, TimingInfo};
+use crate::errors::SubstanceError;
+use crate::profile::ProfileSettings;
+use crate::self_profile::{MacroExpansion, QueryTime, SelfProfile};
+use crate::types::{
+    Artifact, ArtifactKind, BuildContext, ByteSize, Crate, CrateName, DemangledSymbol, FieldLayout,
+    LlvmFunction, LlvmFunctionName, LlvmIrLines, MangledSymbol, NumberOfCopies, Symbol,
+    TargetTriple, TypeLayout, TypeName, VariantLayout,
+};
+
+/// Value of the `format` field of every snapshot
+const SNAPSHOT_FORMAT: &str = "substance-snapshot";
+
+/// Version of the snapshot schema written by this version of substance, and
+/// the only one it reads
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// What goes in a snapshot, see [`BuildContext::save_with`]
+#[derive(Debug, Clone)]
+pub struct SnapshotOptions {
+    /// Save [`BuildContext::deps_symbols`], which lists every symbol of every
+    /// dependency and is usually most of the snapshot. Contexts loaded from a
+    /// snapshot without it have no `deps_symbols`.
+    pub deps_symbols: bool,
+}
+
+impl Default for SnapshotOptions {
+    fn default() -> Self {
+        Self { deps_symbols: true }
+    }
+}
+
+impl BuildContext {
+    /// Saves the whole context to `path`, see the [module docs](crate::snapshot)
+    pub fn save(&self, path: impl AsRef<Utf8Path>) -> Result<(), SubstanceError> {
+        self.save_with(path, &SnapshotOptions::default())
+    }
+
+    /// Saves the context to `path`, leaving out what `options` says to
+    pub fn save_with(
+        &self,
+        path: impl AsRef<Utf8Path>,
+        options: &SnapshotOptions,
+    ) -> Result<(), SubstanceError> {
+        let path = path.as_ref();
+        let snapshot = RawSnapshot::from_context(self, options);
+        std::fs::write(path, facet_json::to_string(&snapshot))
+            .map_err(|_| SubstanceError::WriteFailed(path.to_owned()))?;
+        info!("Saved a snapshot of {} to {path}", self.artifact.name);
+        Ok(())
+    }
+
+    /// Loads a context saved with [`BuildContext::save`]
+    pub fn load(path: impl AsRef<Utf8Path>) -> Result<Self, SubstanceError> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .map_err(|_| SubstanceError::OpenFailed(path.to_owned()))?;
+        let invalid = |reason: String| SubstanceError::InvalidSnapshot(path.to_owned(), reason);
+
+        let header: RawHeader = facet_json::from_str(&json).map_err(|e| invalid(e.to_string()))?;
+        header.check(path)?;
+        let snapshot: RawSnapshot =
+            facet_json::from_str(&json).map_err(|e| invalid(e.to_string()))?;
+        snapshot.into_context().map_err(invalid)
+    }
+}
+
+/// The fields every version has, read before the rest to check the version
+#[derive(Debug, Facet)]
+struct RawHeader {
+    #[facet(default)]
+    format: Option<String>,
+
+    #[facet(default)]
+    version: Option<u32>,
+}
+
+impl RawHeader {
+    fn check(&self, path: &Utf8Path) -> Result<(), SubstanceError> {
+        if self.format.as_deref() != Some(SNAPSHOT_FORMAT) {
+            return Err(SubstanceError::InvalidSnapshot(
+                path.to_owned(),
+                format!("the format field should be {SNAPSHOT_FORMAT:?}, is it a snapshot?"),
+            ));
+        }
+        match self.version {
+            Some(SNAPSHOT_VERSION) => Ok(()),
+            Some(found) => Err(SubstanceError::SnapshotVersionMismatch {
+                path: path.to_owned(),
+                found,
+                expected: SNAPSHOT_VERSION,
+            }),
+            None => Err(SubstanceError::InvalidSnapshot(
+                path.to_owned(),
+                "missing version field".to_string(),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawSnapshot {
+    format: String,
+    version: u32,
+    artifact: RawArtifact,
+    target_triple: String,
+    profile: Option<RawProfile>,
+    std_crates: Vec<String>,
+    dep_crates: Vec<String>,
+
+    /// `None` when saved without them
+    deps_symbols: Option<Vec<RawDepSymbol>>,
+
+    wall_duration_ns: Option<u64>,
+    file_size: u64,
+    text_size: u64,
+    crates: Vec<RawCrate>,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawArtifact {
+    /// See [`kind_name`]
+    kind: String,
+    name: String,
+    path: String,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawProfile {
+    name: String,
+    opt_level: String,
+    debug: String,
+    strip: String,
+    lto: String,
+    codegen_units: Option<u32>,
+    panic: String,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawDepSymbol {
+    mangled: String,
+    crates: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawCrate {
+    name: String,
+    timing_info: Option<RawTimingInfo>,
+    frontend_duration_ns: Option<u64>,
+    codegen_duration_ns: Option<u64>,
+    self_profile: Option<RawSelfProfile>,
+    symbols: Vec<RawSymbol>,
+    llvm_functions: Vec<RawLlvmFunction>,
+    type_layouts: Vec<RawTypeLayout>,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawTimingInfo {
+    package_id: Option<String>,
+    target: RawTarget,
+    mode: Option<String>,
+    duration: f64,
+    rmeta_time: Option<f64>,
+    finished_at_ns: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawTarget {
+    name: Option<String>,
+    kind: Option<Vec<String>>,
+    crate_types: Option<Vec<String>>,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawSelfProfile {
+    queries: Vec<RawQueryTime>,
+    macro_expansions: Vec<RawMacroExpansion>,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawQueryTime {
+    label: String,
+    self_time_ns: u64,
+    invocations: usize,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawMacroExpansion {
+    invocation: String,
+    time_ns: u64,
+    expansions: usize,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawSymbol {
+    name: String,
+    size: u64,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawLlvmFunction {
+    name: String,
+    lines: usize,
+    copies: usize,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawTypeLayout {
+    name: String,
+    size: u64,
+    alignment: u64,
+    discriminant: Option<u64>,
+    fields: Vec<RawFieldLayout>,
+    variants: Vec<RawVariantLayout>,
+    padding: u64,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawVariantLayout {
+    name: String,
+    size: u64,
+    fields: Vec<RawFieldLayout>,
+    padding: u64,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+struct RawFieldLayout {
+    name: String,
+    size: u64,
+    alignment: Option<u64>,
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos().min(u64::MAX as u128) as u64
+}
+
+fn kind_name(kind: ArtifactKind) -> &'static str {
+    match kind {
+        ArtifactKind::Binary => "binary",
+        ArtifactKind::Library => "library",
+        ArtifactKind::DynLib => "dylib",
+        ArtifactKind::CDylib => "cdylib",
+        ArtifactKind::ProcMacro => "proc-macro",
+        ArtifactKind::BuildScript => "build-script",
+    }
+}
+
+fn kind_from_name(name: &str) -> Option<ArtifactKind> {
+    Some(match name {
+        "binary" => ArtifactKind::Binary,
+        "library" => ArtifactKind::Library,
+        "dylib" => ArtifactKind::DynLib,
+        "cdylib" => ArtifactKind::CDylib,
+        "proc-macro" => ArtifactKind::ProcMacro,
+        "build-script" => ArtifactKind::BuildScript,
+        _ => return None,
+    })
+}
+
+fn names<T: AsRef<str>>(items: &[T]) -> Vec<String> {
+    items.iter().map(|i| i.as_ref().to_string()).collect()
+}
+
+impl RawSnapshot {
+    fn from_context(context: &BuildContext, options: &SnapshotOptions) -> Self {
+        let deps_symbols = options.deps_symbols.then(|| {
+            let mut symbols: Vec<RawDepSymbol> = context
+                .deps_symbols
+                .iter_all()
+                .map(|(mangled, crates)| RawDepSymbol {
+                    mangled: mangled.to_string(),
+                    crates: names(crates),
+                })
+                .collect();
+            symbols.sort_by(|a, b| a.mangled.cmp(&b.mangled));
+            symbols
+        });
+
+        Self {
+            format: SNAPSHOT_FORMAT.to_string(),
+            version: SNAPSHOT_VERSION,
+            artifact: RawArtifact {
+                kind: kind_name(context.artifact.kind).to_string(),
+                name: context.artifact.name.to_string(),
+                path: context.artifact.path.to_string(),
+            },
+            target_triple: context.target_triple.to_string(),
+            profile: context.profile.as_ref().map(|p| RawProfile {
+                name: p.name.clone(),
+                opt_level: p.opt_level.clone(),
+                debug: p.debug.clone(),
+                strip: p.strip.clone(),
+                lto: p.lto.clone(),
+                codegen_units: p.codegen_units,
+                panic: p.panic.clone(),
+            }),
+            std_crates: names(&context.std_crates),
+            dep_crates: names(&context.dep_crates),
+            deps_symbols,
+            wall_duration_ns: context.wall_duration.map(nanos),
+            file_size: context.file_size.value(),
+            text_size: context.text_size.value(),
+            crates: context.crates.iter().map(RawCrate::from_crate).collect(),
+        }
+    }
+
+    fn into_context(self) -> Result<BuildContext, String> {
+        let kind = kind_from_name(&self.artifact.kind)
+            .ok_or_else(|| format!("unknown artifact kind {:?}", self.artifact.kind))?;
+
+        let mut deps_symbols = MultiMap::new();
+        for symbol in self.deps_symbols.into_iter().flatten() {
+            for krate in symbol.crates {
+                deps_symbols.insert(
+                    MangledSymbol::from(symbol.mangled.clone()),
+                    CrateName::from(krate),
+                );
+            }
+        }
+
+        Ok(BuildContext {
+            artifact: Artifact {
+                kind,
+                name: CrateName::from(self.artifact.name),
+                path: Utf8PathBuf::from(self.artifact.path),
+            },
+            target_triple: TargetTriple::from(self.target_triple),
+            profile: self.profile.map(|p| ProfileSettings {
+                name: p.name,
+                opt_level: p.opt_level,
+                debug: p.debug,
+                strip: p.strip,
+                lto: p.lto,
+                codegen_units: p.codegen_units,
+                panic: p.panic,
+            }),
+            std_crates: self.std_crates.into_iter().map(CrateName::from).collect(),
+            dep_crates: self.dep_crates.into_iter().map(CrateName::from).collect(),
+            deps_symbols,
+            wall_duration: self.wall_duration_ns.map(Duration::from_nanos),
+            file_size: ByteSize::new(self.file_size),
+            text_size: ByteSize::new(self.text_size),
+            crates: self.crates.into_iter().map(RawCrate::into_crate).collect(),
+        })
+    }
+}
+
+impl RawCrate {
+    fn from_crate(krate: &Crate) -> Self {
+        let mut symbols: Vec<RawSymbol> = krate
+            .symbols
+            .values()
+            .map(|s| RawSymbol {
+                name: s.name.to_string(),
+                size: s.size.value(),
+            })
+            .collect();
+        symbols.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut llvm_functions: Vec<RawLlvmFunction> = krate
+            .llvm_functions
+            .values()
+            .map(|f| RawLlvmFunction {
+                name: f.name.to_string(),
+                lines: f.lines.value(),
+                copies: f.copies.value(),
+            })
+            .collect();
+        llvm_functions.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut type_layouts: Vec<RawTypeLayout> = krate
+            .type_layouts
+            .values()
+            .map(RawTypeLayout::from_layout)
+            .collect();
+        type_layouts.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Self {
+            name: krate.name.to_string(),
+            timing_info: krate.timing_info.as_ref().map(|t| RawTimingInfo {
+                package_id: t.package_id.clone(),
+                target: RawTarget {
+                    name: t.target.name.clone(),
+                    kind: t.target.kind.clone(),
+                    crate_types: t.target.crate_types.clone(),
+                },
+                mode: t.mode.clone(),
+                duration: t.duration,
+                rmeta_time: t.rmeta_time,
+                finished_at_ns: t.finished_at.map(nanos),
+            }),
+            frontend_duration_ns: krate.frontend_duration.map(nanos),
+            codegen_duration_ns: krate.codegen_duration.map(nanos),
+            self_profile: krate.self_profile.as_ref().map(|p| RawSelfProfile {
+                queries: p
+                    .queries
+                    .iter()
+                    .map(|q| RawQueryTime {
+                        label: q.label.clone(),
+                        self_time_ns: nanos(q.self_time),
+                        invocations: q.invocations,
+                    })
+                    .collect(),
+                macro_expansions: p
+                    .macro_expansions
+                    .iter()
+                    .map(|e| RawMacroExpansion {
+                        invocation: e.invocation.clone(),
+                        time_ns: nanos(e.time),
+                        expansions: e.expansions,
+                    })
+                    .collect(),
+            }),
+            symbols,
+            llvm_functions,
+            type_layouts,
+        }
+    }
+
+    fn into_crate(self) -> Crate {
+        Crate {
+            name: CrateName::from(self.name),
+            timing_info: self.timing_info.map(|t| TimingInfo {
+                package_id: t.package_id,
+                target: CargoTarget {
+                    name: t.target.name,
+                    kind: t.target.kind,
+                    crate_types: t.target.crate_types,
+                },
+                mode: t.mode,
+                duration: t.duration,
+                rmeta_time: t.rmeta_time,
+                finished_at: t.finished_at_ns.map(Duration::from_nanos),
+            }),
+            frontend_duration: self.frontend_duration_ns.map(Duration::from_nanos),
+            codegen_duration: self.codegen_duration_ns.map(Duration::from_nanos),
+            self_profile: self.self_profile.map(|p| SelfProfile {
+                queries: p
+                    .queries
+                    .into_iter()
+                    .map(|q| QueryTime {
+                        label: q.label,
+                        self_time: Duration::from_nanos(q.self_time_ns),
+                        invocations: q.invocations,
+                    })
+                    .collect(),
+                macro_expansions: p
+                    .macro_expansions
+                    .into_iter()
+                    .map(|e| MacroExpansion {
+                        invocation: e.invocation,
+                        time: Duration::from_nanos(e.time_ns),
+                        expansions: e.expansions,
+                    })
+                    .collect(),
+            }),
+            symbols: self
+                .symbols
+                .into_iter()
+                .map(|s| {
+                    let name = DemangledSymbol::from(s.name);
+                    let symbol = Symbol {
+                        name: name.clone(),
+                        size: ByteSize::new(s.size),
+                    };
+                    (name, symbol)
+                })
+                .collect(),
+            llvm_functions: self
+                .llvm_functions
+                .into_iter()
+                .map(|f| {
+                    let name = LlvmFunctionName::from(f.name);
+                    let function = LlvmFunction {
+                        name: name.clone(),
+                        lines: LlvmIrLines::new(f.lines),
+                        copies: NumberOfCopies::new(f.copies),
+                    };
+                    (name, function)
+                })
+                .collect(),
+            type_layouts: self
+                .type_layouts
+                .into_iter()
+                .map(|l| {
+                    let layout = l.into_layout();
+                    (layout.name.clone(), layout)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl RawTypeLayout {
+    fn from_layout(layout: &TypeLayout) -> Self {
+        Self {
+            name: layout.name.to_string(),
+            size: layout.size.value(),
+            alignment: layout.alignment.value(),
+            discriminant: layout.discriminant.map(|d| d.value()),
+            fields: layout
+                .fields
+                .iter()
+                .map(RawFieldLayout::from_field)
+                .collect(),
+            variants: layout
+                .variants
+                .iter()
+                .map(|v| RawVariantLayout {
+                    name: v.name.clone(),
+                    size: v.size.value(),
+                    fields: v.fields.iter().map(RawFieldLayout::from_field).collect(),
+                    padding: v.padding.value(),
+                })
+                .collect(),
+            padding: layout.padding.value(),
+        }
+    }
+
+    fn into_layout(self) -> TypeLayout {
+        TypeLayout {
+            name: TypeName::from(self.name),
+            size: ByteSize::new(self.size),
+            alignment: ByteSize::new(self.alignment),
+            discriminant: self.discriminant.map(ByteSize::new),
+            fields: self
+                .fields
+                .into_iter()
+                .map(RawFieldLayout::into_field)
+                .collect(),
+            variants: self
+                .variants
+                .into_iter()
+                .map(|v| VariantLayout {
+                    name: v.name,
+                    size: ByteSize::new(v.size),
+                    fields: v
+                        .fields
+                        .into_iter()
+                        .map(RawFieldLayout::into_field)
+                        .collect(),
+                    padding: ByteSize::new(v.padding),
+                })
+                .collect(),
+            padding: ByteSize::new(self.padding),
+        }
+    }
+}
+
+impl RawFieldLayout {
+    fn from_field(field: &FieldLayout) -> Self {
+        Self {
+            name: field.name.clone(),
+            size: field.size.value(),
+            alignment: field.alignment.map(|a| a.value()),
+        }
+    }
+
+    fn into_field(self) -> FieldLayout {
+        FieldLayout {
+            name: self.name,
+            size: ByteSize::new(self.size),
+            alignment: self.alignment.map(ByteSize::new),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> BuildContext {
+        let mut deps_symbols = MultiMap::new();
+        deps_symbols.insert(
+            MangledSymbol::from("_ZN5serde2de5Error6custom17h0123456789abcdefE"),
+            CrateName::from("serde"),
+        );
+        let layout = TypeLayout {
+            name: TypeName::from("app::State"),
+            size: ByteSize::new(16u64),
+            alignment: ByteSize::new(8u64),
+            discriminant: Some(ByteSize::new(1u64)),
+            fields: Vec::new(),
+            variants: vec![VariantLayout {
+                name: "Ready".to_string(),
+                size: ByteSize::new(9u64),
+                fields: vec![FieldLayout {
+                    name: "0".to_string(),
+                    size: ByteSize::new(8u64),
+                    alignment: Some(ByteSize::new(8u64)),
+                }],
+                padding: ByteSize::new(0u64),
+            }],
+            padding: ByteSize::new(7u64),
+        };
+
+        let krate = RawCrate {
+            name: "app".to_string(),
+            timing_info: Some(RawTimingInfo {
+                package_id: Some("path+file:///app#0.1.0".to_string()),
+                target: RawTarget {
+                    name: Some("app".to_string()),
+                    kind: Some(vec!["bin".to_string()]),
+                    crate_types: Some(vec!["bin".to_string()]),
+                },
+                mode: Some("build".to_string()),
+                duration: 1.25,
+                rmeta_time: Some(0.5),
+                finished_at_ns: Some(3_000_000_123),
+            }),
+            frontend_duration_ns: Some(500_000_000),
+            codegen_duration_ns: Some(750_000_000),
+            self_profile: Some(RawSelfProfile {
+                queries: vec![RawQueryTime {
+                    label: "typeck".to_string(),
+                    self_time_ns: 42_000,
+                    invocations: 3,
+                }],
+                macro_expansions: vec![RawMacroExpansion {
+                    invocation: "#[derive(Facet)]".to_string(),
+                    time_ns: 1_000,
+                    expansions: 2,
+                }],
+            }),
+            symbols: vec![
+                RawSymbol {
+                    name: "app::main".to_string(),
+                    size: 120,
+                },
+                RawSymbol {
+                    name: "app::run".to_string(),
+                    size: 80,
+                },
+            ],
+            llvm_functions: vec![RawLlvmFunction {
+                name: "app::run".to_string(),
+                lines: 40,
+                copies: 1,
+            }],
+            type_layouts: vec![RawTypeLayout::from_layout(&layout)],
+        }
+        .into_crate();
+
+        BuildContext {
+            profile: Some(ProfileSettings {
+                name: "release".to_string(),
+                opt_level: "z".to_string(),
+                debug: "false".to_string(),
+                strip: "none".to_string(),
+                lto: "fat".to_string(),
+                codegen_units: Some(1),
+                panic: "abort".to_string(),
+            }),
+            std_crates: vec![CrateName::from("std"), CrateName::from("core")],
+            dep_crates: vec![CrateName::from("serde")],
+            deps_symbols,
+            wall_duration: Some(Duration::from_nanos(4_500_000_001)),
+            ..BuildContext::for_test(4096, vec![krate])
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let snapshot = RawSnapshot::from_context(&context(), &SnapshotOptions::default());
+        let loaded = RawSnapshot::from_context(&context(), &SnapshotOptions::default())
+            .into_context()
+            .unwrap();
+        assert_eq!(
+            RawSnapshot::from_context(&loaded, &SnapshotOptions::default()),
+            snapshot
+        );
+        assert_eq!(loaded.deps_symbols.len(), 1);
+        assert_eq!(
+            loaded.crates[0].type_layouts[&TypeName::from("app::State")].total_padding(),
+            ByteSize::new(7u64)
+        );
+
+        let without = RawSnapshot::from_context(
+            &context(),
+            &SnapshotOptions {
+                deps_symbols: false,
+            },
+        );
+        assert_eq!(without.deps_symbols, None);
+        assert!(without.into_context().unwrap().deps_symbols.is_empty());
+    }
+
+    #[test]
+    fn test_header() {
+        let path = Utf8Path::new("before.json");
+        let header = |format: Option<&str>, version| RawHeader {
+            format: format.map(String::from),
+            version,
+        };
+        assert!(header(Some(SNAPSHOT_FORMAT), Some(SNAPSHOT_VERSION))
+            .check(path)
+            .is_ok());
+        assert!(matches!(
+            header(Some(SNAPSHOT_FORMAT), Some(SNAPSHOT_VERSION + 1)).check(path),
+            Err(SubstanceError::SnapshotVersionMismatch { found, .. }) if found == SNAPSHOT_VERSION + 1
+        ));
+        assert!(matches!(
+            header(None, Some(SNAPSHOT_VERSION)).check(path),
+            Err(SubstanceError::InvalidSnapshot(..))
+        ));
+        assert!(matches!(
+            header(Some(SNAPSHOT_FORMAT), None).check(path),
+            Err(SubstanceError::InvalidSnapshot(..))
+        ));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::from_path_buf(dir.path().join("snapshot.json")).unwrap();
+        context().save(&path).unwrap();
+        let loaded = BuildContext::load(&path).unwrap();
+        assert_eq!(
+            RawSnapshot::from_context(&loaded, &SnapshotOptions::default()),
+            RawSnapshot::from_context(&context(), &SnapshotOptions::default())
+        );
+
+        // The version is checked before anything else is read
+        std::fs::write(
+            &path,
+            format!(r#"{{"format": "{SNAPSHOT_FORMAT}", "version": 2, "crates": "?"}}"#),
+        )
+        .unwrap();
+        assert!(matches!(
+            BuildContext::load(&path),
+            Err(SubstanceError::SnapshotVersionMismatch {
+                found: 2,
+                expected: SNAPSHOT_VERSION,
+                ..
+            })
+        ));
+    }
+}
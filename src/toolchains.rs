@@ -0,0 +1,150 @@
+//! Builds of one manifest with several rustup toolchains.
+//!
+//! Toolchain upgrades can change binary size a lot. Each toolchain builds the
+//! manifest in its own target directory (see [`crate::matrix`]), and the
+//! results are handed back in the order the toolchains were given, so that
+//! consecutive ones can be diffed.
+
+use std::process::Command;
+
+use camino::Utf8PathBuf;
+use log::{info, warn};
+
+use crate::errors::SubstanceError;
+use crate::matrix::BuildOptions;
+use crate::types::BuildContext;
+use crate::BuildResult;
+
+/// Returns the toolchains installed with rustup, as listed by `rustup toolchain
+/// list`, e.g. `stable-x86_64-unknown-linux-gnu`.
+pub fn installed_toolchains() -> Result<Vec<String>, SubstanceError> {
+    let output = Command::new("rustup")
+        .args(["toolchain", "list"])
+        .output()
+        .map_err(|e| {
+            SubstanceError::CargoError(format!("Failed to execute rustup toolchain list: {e}"))
+        })?;
+    if !output.status.success() {
+        return Err(SubstanceError::CargoError(format!(
+            "rustup toolchain list failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(parse_toolchain_list(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+fn parse_toolchain_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("no installed toolchains"))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(String::from)
+        .collect()
+}
+
+/// Builds a manifest once per toolchain, see the [module docs](self).
+pub struct ToolchainMatrix {
+    /// The toolchain of the options is replaced by each of the matrix's
+    options: BuildOptions,
+
+    /// `None` means every installed toolchain
+    toolchains: Option<Vec<String>>,
+}
+
+impl ToolchainMatrix {
+    pub fn for_manifest(manifest_path: impl Into<Utf8PathBuf>) -> Self {
+        Self::new(BuildOptions::for_manifest(manifest_path))
+    }
+
+    /// See [`ToolchainMatrix::for_manifest`]. The toolchain of `options` is
+    /// ignored.
+    pub fn new(options: BuildOptions) -> Self {
+        Self {
+            options,
+            toolchains: None,
+        }
+    }
+
+    /// Build with these toolchains, in this order, instead of every installed one
+    pub fn toolchains<S: Into<String>>(mut self, toolchains: impl IntoIterator<Item = S>) -> Self {
+        self.toolchains = Some(toolchains.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Builds with every toolchain. Toolchains that fail to build, e.g. because
+    /// they're too old for the project, are reported in
+    /// [`ToolchainReport::failed`].
+    pub fn run(&self) -> Result<ToolchainReport, SubstanceError> {
+        let toolchains = match &self.toolchains {
+            Some(toolchains) => toolchains.clone(),
+            None => installed_toolchains()?,
+        };
+
+        let mut builds = Vec::new();
+        let mut failed = Vec::new();
+        for (index, toolchain) in toolchains.into_iter().enumerate() {
+            info!("Building with toolchain {toolchain}");
+            let runner = self
+                .options
+                .variant_runner(index, &toolchain)
+                .toolchain(&toolchain);
+            match runner.run() {
+                Ok(result) => builds.push(ToolchainBuild { toolchain, result }),
+                Err(e) => {
+                    warn!("Toolchain {toolchain} failed to build: {e}");
+                    failed.push((toolchain, e));
+                }
+            }
+        }
+
+        Ok(ToolchainReport { builds, failed })
+    }
+}
+
+/// Result of [`ToolchainMatrix::run`]
+pub struct ToolchainReport {
+    /// Toolchains that built, in the order they were given
+    pub builds: Vec<ToolchainBuild>,
+
+    pub failed: Vec<(String, SubstanceError)>,
+}
+
+impl ToolchainReport {
+    /// Context of the main artifact for each toolchain that built
+    pub fn contexts(&self) -> impl Iterator<Item = (&str, &BuildContext)> {
+        self.builds
+            .iter()
+            .map(|b| (b.toolchain.as_str(), b.result.context()))
+    }
+}
+
+/// A build with one toolchain
+pub struct ToolchainBuild {
+    pub toolchain: String,
+    pub result: BuildResult,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_toolchain_list() {
+        let output = "stable-x86_64-unknown-linux-gnu (active, default)
+nightly-2026-09-01-x86_64-unknown-linux-gnu
+1.80.0-x86_64-unknown-linux-gnu
+";
+        assert_eq!(
+            parse_toolchain_list(output),
+            [
+                "stable-x86_64-unknown-linux-gnu",
+                "nightly-2026-09-01-x86_64-unknown-linux-gnu",
+                "1.80.0-x86_64-unknown-linux-gnu",
+            ]
+        );
+        assert!(parse_toolchain_list("no installed toolchains\n").is_empty());
+    }
+}
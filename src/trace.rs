@@ -0,0 +1,278 @@
+//! Export of build timings in the Trace Event Format, for Perfetto and
+//! `chrome://tracing`.
+//!
+//! Units go in a "cargo build" process, with one track per concurrency slot:
+//! units are packed into the first track that's free when they start, so the
+//! number of tracks is the peak parallelism of the build. Units are split in
+//! frontend and codegen from `rmeta_time`. The phases of the build and analysis
+//! go in a "substance" process.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use facet::Facet;
+
+use crate::cargo::TimingInfo;
+use crate::observer::PhaseSpan;
+use crate::BuildResult;
+
+const UNITS_PID: u32 = 1;
+const PHASES_PID: u32 = 2;
+
+// Fields are only read through reflection, when serializing
+#[allow(dead_code)]
+#[derive(Debug, Facet)]
+struct TraceFile {
+    #[facet(rename = "traceEvents")]
+    trace_events: Vec<TraceEvent>,
+
+    #[facet(rename = "displayTimeUnit")]
+    display_time_unit: String,
+}
+
+/// A complete (`X`) or metadata (`M`) event
+#[allow(dead_code)]
+#[derive(Debug, Facet)]
+struct TraceEvent {
+    name: String,
+    cat: String,
+    ph: String,
+
+    /// Start, in microseconds
+    ts: f64,
+
+    /// Duration, in microseconds
+    dur: f64,
+
+    pid: u32,
+    tid: u32,
+    args: HashMap<String, String>,
+}
+
+impl TraceEvent {
+    fn complete(
+        name: impl Into<String>,
+        cat: &str,
+        pid: u32,
+        tid: u32,
+        start: Duration,
+        end: Duration,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            cat: cat.to_string(),
+            ph: "X".to_string(),
+            ts: micros(start),
+            dur: micros(end.saturating_sub(start)),
+            pid,
+            tid,
+            args: HashMap::new(),
+        }
+    }
+
+    /// Names a process (`tid` is `None`) or a thread
+    fn name_of(pid: u32, tid: Option<u32>, name: impl Into<String>) -> Self {
+        let kind = if tid.is_some() {
+            "thread_name"
+        } else {
+            "process_name"
+        };
+        Self {
+            name: kind.to_string(),
+            cat: "__metadata".to_string(),
+            ph: "M".to_string(),
+            ts: 0.0,
+            dur: 0.0,
+            pid,
+            tid: tid.unwrap_or(0),
+            args: HashMap::from([("name".to_string(), name.into())]),
+        }
+    }
+}
+
+fn micros(d: Duration) -> f64 {
+    d.as_secs_f64() * 1_000_000.0
+}
+
+/// Writes the unit timings and phases of `result` as Trace Event Format JSON.
+///
+/// Units end when their timing message arrived, so this needs a result from
+/// [`BuildRunner`](crate::BuildRunner). Cargo doesn't say when units start:
+/// starts are derived from the end and the duration cargo reports (see
+/// [`TimingInfo::started_at`]), and may be off by however late the message
+/// arrived.
+pub fn write_chrome_trace(result: &BuildResult, mut writer: impl Write) -> io::Result<()> {
+    let timings: Vec<&TimingInfo> = result
+        .target_timings
+        .iter()
+        .chain(&result.host_timings)
+        .collect();
+    let file = TraceFile {
+        trace_events: trace_events(&timings, &result.phases),
+        display_time_unit: "ms".to_string(),
+    };
+    writer.write_all(facet_json::to_string(&file).as_bytes())
+}
+
+fn trace_events(timings: &[&TimingInfo], phases: &[PhaseSpan]) -> Vec<TraceEvent> {
+    let mut events = vec![
+        TraceEvent::name_of(UNITS_PID, None, "cargo build"),
+        TraceEvent::name_of(PHASES_PID, None, "substance"),
+        TraceEvent::name_of(PHASES_PID, Some(1), "phases"),
+    ];
+
+    let spans: Vec<(&TimingInfo, Duration, Duration)> = timings
+        .iter()
+        .filter_map(|t| Some((*t, t.started_at()?, t.finished_at?)))
+        .collect();
+    let slots = assign_slots(&spans.iter().map(|(_, s, e)| (*s, *e)).collect::<Vec<_>>());
+    let slot_count = slots.iter().max().map_or(0, |max| max + 1);
+    for slot in 0..slot_count {
+        events.push(TraceEvent::name_of(
+            UNITS_PID,
+            Some(slot as u32 + 1),
+            format!("slot {}", slot + 1),
+        ));
+    }
+
+    for ((timing, start, end), slot) in spans.into_iter().zip(slots) {
+        let tid = slot as u32 + 1;
+        let name = timing.target.name.as_deref().unwrap_or("unknown");
+        let mode = timing.mode.as_deref().unwrap_or("build");
+        let name = if mode == "build" {
+            name.to_string()
+        } else {
+            format!("{name} ({mode})")
+        };
+
+        let mut event = TraceEvent::complete(name, "unit", UNITS_PID, tid, start, end);
+        if let Some(package_id) = &timing.package_id {
+            event
+                .args
+                .insert("package_id".to_string(), package_id.clone());
+        }
+        if let Some(kind) = &timing.target.kind {
+            event.args.insert("kind".to_string(), kind.join(", "));
+        }
+        event.args.insert("mode".to_string(), mode.to_string());
+        event
+            .args
+            .insert("host".to_string(), timing.is_host_unit().to_string());
+        events.push(event);
+
+        if let Some(frontend) = timing.frontend_duration() {
+            let rmeta = (start + frontend).min(end);
+            events.push(TraceEvent::complete(
+                "frontend", "phase", UNITS_PID, tid, start, rmeta,
+            ));
+            events.push(TraceEvent::complete(
+                "codegen", "phase", UNITS_PID, tid, rmeta, end,
+            ));
+        }
+    }
+
+    for span in phases {
+        events.push(TraceEvent::complete(
+            span.phase.to_string(),
+            "substance",
+            PHASES_PID,
+            1,
+            span.start,
+            span.end,
+        ));
+    }
+
+    events
+}
+
+/// Packs `(start, end)` spans into as few slots as possible, in start order.
+/// Returns the slot of each span.
+fn assign_slots(spans: &[(Duration, Duration)]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..spans.len()).collect();
+    order.sort_by_key(|&i| spans[i]);
+
+    let mut slot_ends: Vec<Duration> = Vec::new();
+    let mut slots = vec![0; spans.len()];
+    for i in order {
+        let (start, end) = spans[i];
+        let slot = match slot_ends.iter().position(|&free_at| free_at <= start) {
+            Some(slot) => slot,
+            None => {
+                slot_ends.push(Duration::ZERO);
+                slot_ends.len() - 1
+            }
+        };
+        slot_ends[slot] = end;
+        slots[i] = slot;
+    }
+    slots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cargo::CargoTarget;
+    use crate::observer::BuildPhase;
+
+    fn secs(s: f64) -> Duration {
+        Duration::from_secs_f64(s)
+    }
+
+    #[test]
+    fn test_assign_slots() {
+        let spans = [
+            (secs(0.0), secs(3.0)),
+            (secs(0.0), secs(1.0)),
+            (secs(1.0), secs(2.0)),
+            (secs(2.5), secs(4.0)),
+            (secs(2.0), secs(5.0)),
+        ];
+        assert_eq!(assign_slots(&spans), vec![1, 0, 0, 2, 0]);
+    }
+
+    #[test]
+    fn test_trace_events() {
+        let timing = TimingInfo {
+            package_id: Some("path+file:///app#0.1.0".to_string()),
+            target: CargoTarget {
+                name: Some("app".to_string()),
+                kind: Some(vec!["lib".to_string()]),
+                crate_types: Some(vec!["lib".to_string()]),
+            },
+            mode: Some("build".to_string()),
+            duration: 2.0,
+            rmeta_time: Some(0.5),
+            finished_at: Some(secs(3.0)),
+        };
+        let phases = [PhaseSpan {
+            phase: BuildPhase::RlibSymbolScan,
+            start: secs(3.0),
+            end: secs(4.0),
+        }];
+        let events = trace_events(&[&timing], &phases);
+
+        let complete: Vec<(&str, f64, f64, u32)> = events
+            .iter()
+            .filter(|e| e.ph == "X")
+            .map(|e| (e.name.as_str(), e.ts, e.dur, e.pid))
+            .collect();
+        assert_eq!(
+            complete,
+            vec![
+                ("app", 1_000_000.0, 2_000_000.0, UNITS_PID),
+                ("frontend", 1_000_000.0, 500_000.0, UNITS_PID),
+                ("codegen", 1_500_000.0, 1_500_000.0, UNITS_PID),
+                (
+                    "scanning rlib symbols",
+                    3_000_000.0,
+                    1_000_000.0,
+                    PHASES_PID
+                ),
+            ]
+        );
+        assert!(events
+            .iter()
+            .any(|e| e.ph == "M" && e.args.get("name").map(String::as_str) == Some("slot 1")));
+    }
+}
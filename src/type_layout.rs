@@ -0,0 +1,219 @@
+//! Parsing of `-Zprint-type-sizes` output.
+//!
+//! Rustc prints the layout of every type it had to compute one for, to stdout,
+//! in this form:
+//!
+//! ```text
+//! print-type-size type: `E`: 16 bytes, alignment: 8 bytes
+//! print-type-size     discriminant: 1 bytes
+//! print-type-size     variant `B`: 15 bytes
+//! print-type-size         field `.1`: 1 bytes
+//! print-type-size         padding: 6 bytes
+//! print-type-size         field `.0`: 8 bytes, alignment: 8 bytes
+//! print-type-size     variant `C`: 0 bytes
+//! print-type-size type: `S`: 16 bytes, alignment: 8 bytes
+//! print-type-size     field `.b`: 8 bytes
+//! print-type-size     field `.a`: 1 bytes
+//! print-type-size     end padding: 7 bytes
+//! ```
+
+use std::collections::HashMap;
+
+use log::debug;
+
+use crate::types::{ByteSize, FieldLayout, TypeLayout, TypeName, VariantLayout};
+
+/// Prefix of every line rustc prints
+pub(crate) const LINE_PREFIX: &str = "print-type-size ";
+
+/// Parses the lines printed by rustc for one crate. Lines that aren't part of
+/// the output are ignored.
+pub fn parse_type_sizes<'a>(
+    lines: impl IntoIterator<Item = &'a str>,
+) -> HashMap<TypeName, TypeLayout> {
+    let mut layouts = HashMap::new();
+    let mut current: Option<TypeLayout> = None;
+
+    for line in lines {
+        let Some(line) = line.strip_prefix(LINE_PREFIX) else {
+            continue;
+        };
+        let indent = line.len() - line.trim_start().len();
+        let line = line.trim();
+
+        if let Some(rest) = line.strip_prefix("type: ") {
+            if let Some(layout) = current.take() {
+                layouts.insert(layout.name.clone(), layout);
+            }
+            current = parse_type_line(rest);
+            if current.is_none() {
+                debug!("Unrecognized type line: {line}");
+            }
+            continue;
+        }
+
+        let Some(layout) = current.as_mut() else {
+            continue;
+        };
+        // Fields and padding of variants are indented twice
+        let in_variant = indent > 4 && !layout.variants.is_empty();
+
+        if let Some(size) = line.strip_prefix("discriminant: ") {
+            layout.discriminant = parse_bytes(size);
+        } else if let Some(rest) = line.strip_prefix("variant ") {
+            if let Some((name, rest)) = parse_quoted(rest) {
+                layout.variants.push(VariantLayout {
+                    name: name.to_string(),
+                    size: parse_bytes(rest.trim_start_matches(':')).unwrap_or(ByteSize::new(0u64)),
+                    fields: Vec::new(),
+                    padding: ByteSize::new(0u64),
+                });
+            }
+        } else if let Some(size) = line
+            .strip_prefix("padding: ")
+            .or_else(|| line.strip_prefix("end padding: "))
+        {
+            let size = parse_bytes(size).unwrap_or(ByteSize::new(0u64));
+            match layout.variants.last_mut() {
+                Some(variant) if in_variant => variant.padding += size,
+                _ => layout.padding += size,
+            }
+        } else if let Some(field) = parse_field(line) {
+            match layout.variants.last_mut() {
+                Some(variant) if in_variant => variant.fields.push(field),
+                _ => layout.fields.push(field),
+            }
+        } else {
+            debug!("Unrecognized type size line: {line}");
+        }
+    }
+
+    if let Some(layout) = current {
+        layouts.insert(layout.name.clone(), layout);
+    }
+    for layout in layouts.values_mut() {
+        layout.variants.sort_by_key(|v| std::cmp::Reverse(v.size));
+    }
+    layouts
+}
+
+/// Parses ``  `S`: 16 bytes, alignment: 8 bytes``
+fn parse_type_line(rest: &str) -> Option<TypeLayout> {
+    // Type names can contain backticks and colons, so split from the end
+    let (name, sizes) = rest.strip_prefix('`')?.rsplit_once("`: ")?;
+    let (size, alignment) = sizes.split_once(", alignment: ")?;
+    Some(TypeLayout {
+        name: TypeName::from(name),
+        size: parse_bytes(size)?,
+        alignment: parse_bytes(alignment)?,
+        discriminant: None,
+        fields: Vec::new(),
+        variants: Vec::new(),
+        padding: ByteSize::new(0u64),
+    })
+}
+
+/// Parses ``field `.b`: 8 bytes, alignment: 8 bytes``, as well as the `local`
+/// and `upvar` fields of futures and closures
+fn parse_field(line: &str) -> Option<FieldLayout> {
+    let (kind, rest) = line.split_once(' ')?;
+    if !matches!(kind, "field" | "local" | "upvar") {
+        return None;
+    }
+    let (name, rest) = parse_quoted(rest)?;
+    let mut parts = rest.trim_start_matches(':').split(", ");
+    let size = parse_bytes(parts.next()?)?;
+    let alignment = parts
+        .find_map(|part| part.strip_prefix("alignment: "))
+        .and_then(parse_bytes);
+    Some(FieldLayout {
+        name: name.trim_start_matches('.').to_string(),
+        size,
+        alignment,
+    })
+}
+
+/// Splits `` `name`: rest`` into `name` and `: rest`
+fn parse_quoted(s: &str) -> Option<(&str, &str)> {
+    let s = s.strip_prefix('`')?;
+    let end = s.find('`')?;
+    Some((&s[..end], &s[end + 1..]))
+}
+
+/// Parses `16 bytes`
+fn parse_bytes(s: &str) -> Option<ByteSize> {
+    s.trim()
+        .strip_suffix(" bytes")?
+        .parse::<u64>()
+        .ok()
+        .map(ByteSize::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TypeNameRef;
+
+    const OUTPUT: &str = "\
+print-type-size type: `{async fn body of f()}`: 102 bytes, alignment: 1 bytes
+print-type-size     discriminant: 1 bytes
+print-type-size     variant `Unresumed`: 0 bytes
+print-type-size     variant `Suspend0`: 101 bytes
+print-type-size         local `.x`: 100 bytes
+print-type-size         local `.__awaitee`: 1 bytes, type: {async fn body of g()}
+print-type-size type: `E`: 16 bytes, alignment: 8 bytes
+print-type-size     discriminant: 1 bytes
+print-type-size     variant `A`: 1 bytes
+print-type-size         field `.0`: 1 bytes
+print-type-size     variant `B`: 15 bytes
+print-type-size         field `.1`: 1 bytes
+print-type-size         padding: 6 bytes
+print-type-size         field `.0`: 8 bytes, alignment: 8 bytes
+print-type-size     variant `C`: 0 bytes
+not a type size line
+print-type-size type: `S`: 16 bytes, alignment: 8 bytes
+print-type-size     field `.b`: 8 bytes
+print-type-size     field `.c`: 2 bytes
+print-type-size     field `.a`: 1 bytes
+print-type-size     end padding: 5 bytes
+";
+
+    #[test]
+    fn test_parse_type_sizes() {
+        let layouts = parse_type_sizes(OUTPUT.lines());
+        assert_eq!(layouts.len(), 3);
+
+        let s = &layouts[TypeNameRef::from_str("S")];
+        assert_eq!(s.size.value(), 16);
+        assert_eq!(s.alignment.value(), 8);
+        assert_eq!(s.discriminant, None);
+        let fields: Vec<(&str, u64)> = s
+            .fields
+            .iter()
+            .map(|f| (f.name.as_str(), f.size.value()))
+            .collect();
+        assert_eq!(fields, [("b", 8), ("c", 2), ("a", 1)]);
+        assert_eq!(s.total_padding().value(), 5);
+
+        let e = &layouts[TypeNameRef::from_str("E")];
+        assert_eq!(e.discriminant.map(|d| d.value()), Some(1));
+        let variants: Vec<(&str, u64)> = e
+            .variants
+            .iter()
+            .map(|v| (v.name.as_str(), v.size.value()))
+            .collect();
+        assert_eq!(variants, [("B", 15), ("A", 1), ("C", 0)]);
+        let b = e.largest_variant().unwrap();
+        assert_eq!(b.padding.value(), 6);
+        assert_eq!(b.fields[1].alignment.map(|a| a.value()), Some(8));
+        assert_eq!(e.total_padding().value(), 6);
+
+        let future = &layouts[TypeNameRef::from_str("{async fn body of f()}")];
+        let locals: Vec<&str> = future.variants[0]
+            .fields
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect();
+        assert_eq!(locals, ["x", "__awaitee"]);
+    }
+}
@@ -11,6 +11,8 @@ use multimap::MultiMap;
 use strong_type::StrongType;
 
 use crate::cargo::TimingInfo;
+use crate::profile::ProfileSettings;
+use crate::self_profile::SelfProfile;
 
 // Strongly-typed quantities
 #[derive(StrongType)]
@@ -33,6 +35,10 @@ pub struct BuildTimeSeconds(f64);
 #[braid]
 pub struct CrateName;
 
+/// A target triple, e.g. `x86_64-unknown-linux-gnu` or `thumbv7em-none-eabihf`
+#[braid]
+pub struct TargetTriple;
+
 /// A mangled symbol name as it appears in the binary (e.g., "_ZN5serde3ser9Serialize9serialize17h...")
 #[braid]
 pub struct MangledSymbol;
@@ -81,7 +87,21 @@ pub struct LlvmFunctionName;
 #[braid]
 pub struct LlvmFilePath;
 
+/// A type as rustc prints it (e.g., "std::option::Option<u32>", "{async fn body of main()}")
+#[braid]
+pub struct TypeName;
+
 pub struct BuildContext {
+    /// The artifact (binary, cdylib or dylib) this context describes
+    pub artifact: Artifact,
+
+    /// The triple the artifact was built for
+    pub target_triple: TargetTriple,
+
+    /// Effective settings of the profile the artifact was built with, after the
+    /// overrides needed for analysis. `None` if the binary wasn't built by us.
+    pub profile: Option<ProfileSettings>,
+
     /// Crate names of libraries found under the libstd `target-libdir`,
     /// something like: `$RUSTUP_HOME/toolchains/stable-$TRIPLE/lib/rustlib/$TRIPLE/lib`
     pub std_crates: Vec<CrateName>,
@@ -92,8 +112,9 @@ pub struct BuildContext {
     /// Maps mangled symbols to the crate names they belong to
     pub deps_symbols: MultiMap<MangledSymbol, CrateName>,
 
-    /// Optional global timing information (e.g. total build time)
-    pub wall_duration: Duration,
+    /// Optional global timing information (e.g. total build time). `None` when
+    /// the binary wasn't built by us, see [`crate::Analyzer`].
+    pub wall_duration: Option<Duration>,
 
     /// Total file size of the binary
     pub file_size: ByteSize,
@@ -213,23 +234,67 @@ impl BuildContext {
     }
 }
 
+/// A made-up build for tests
+#[cfg(test)]
+impl BuildContext {
+    /// A binary named `app` whose `.text` is half of it
+    pub(crate) fn for_test(file_size: u64, crates: Vec<Crate>) -> Self {
+        Self {
+            artifact: Artifact {
+                kind: ArtifactKind::Binary,
+                name: CrateName::from("app"),
+                path: "/target/release/app".into(),
+            },
+            target_triple: "x86_64-unknown-linux-gnu".into(),
+            profile: None,
+            std_crates: Vec::new(),
+            dep_crates: Vec::new(),
+            deps_symbols: Default::default(),
+            wall_duration: None,
+            file_size: ByteSize::new(file_size),
+            text_size: ByteSize::new(file_size / 2),
+            crates,
+        }
+    }
+}
+
 /// An artifact generated by the build — a single `.rlib` file, etc.
+#[derive(Clone, Debug)]
 pub struct Artifact {
-    /// binary, library, or dynlib
+    /// binary, library, dynlib etc.
     pub kind: ArtifactKind,
 
-    /// crate name, e.g. `facet` or `core`
+    /// target name, e.g. `facet`, `core` or `my-app`
     pub name: CrateName,
 
     /// absolute path to the artifact
     pub path: Utf8PathBuf,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum ArtifactKind {
+    /// An executable (`bin` or `example` target)
     Binary,
+    /// An `.rlib`
     Library,
+    /// A Rust `dylib`
     DynLib,
+    /// A `cdylib`, e.g. a plugin or a library loaded over FFI
+    CDylib,
+    /// A proc-macro, loaded by the compiler
+    ProcMacro,
+    /// A compiled `build.rs`
+    BuildScript,
+}
+
+impl ArtifactKind {
+    /// Whether this is a linked output we can analyze the symbols of
+    pub fn is_linkable(&self) -> bool {
+        matches!(
+            self,
+            ArtifactKind::Binary | ArtifactKind::DynLib | ArtifactKind::CDylib
+        )
+    }
 }
 
 /// Info about a given crate
@@ -240,14 +305,45 @@ pub struct Crate {
     /// Timing info
     pub timing_info: Option<TimingInfo>,
 
-    /// Symbols found in the binary
+    /// Time spent compiling the crate before its metadata was ready, see
+    /// [`TimingInfo::frontend_duration`]
+    pub frontend_duration: Option<Duration>,
+
+    /// Time spent compiling the crate after its metadata was ready, see
+    /// [`TimingInfo::codegen_duration`]
+    pub codegen_duration: Option<Duration>,
+
+    /// Compile time by query, for crates passed to
+    /// [`BuildRunner::self_profile`](crate::BuildRunner::self_profile)
+    pub self_profile: Option<SelfProfile>,
+
+    /// Symbols found in the binary. None for crates that are only here for their
+    /// self-profile or type layouts.
     pub symbols: HashMap<DemangledSymbol, Symbol>,
 
     /// LLVM functions found in .ll files
     pub llvm_functions: HashMap<LlvmFunctionName, LlvmFunction>,
+
+    /// Layouts of the types used by the crate, for crates passed to
+    /// [`BuildRunner::print_type_sizes`](crate::BuildRunner::print_type_sizes)
+    pub type_layouts: HashMap<TypeName, TypeLayout>,
 }
 
 impl Crate {
+    /// A crate we know nothing about yet
+    pub(crate) fn new(name: CrateName) -> Self {
+        Self {
+            name,
+            timing_info: None,
+            frontend_duration: None,
+            codegen_duration: None,
+            self_profile: None,
+            symbols: HashMap::new(),
+            llvm_functions: HashMap::new(),
+            type_layouts: HashMap::new(),
+        }
+    }
+
     pub fn num_llvm_lines(&self) -> usize {
         self.llvm_functions
             .values()
@@ -256,6 +352,63 @@ impl Crate {
     }
 }
 
+/// Builders for the crates tests make up
+#[cfg(test)]
+impl Crate {
+    pub(crate) fn named(name: &str) -> Self {
+        Self::new(CrateName::from(name))
+    }
+
+    pub(crate) fn with_symbol(mut self, name: &str, size: u64) -> Self {
+        let name = DemangledSymbol::from(name);
+        let symbol = Symbol {
+            name: name.clone(),
+            size: ByteSize::new(size),
+        };
+        self.symbols.insert(name, symbol);
+        self
+    }
+
+    pub(crate) fn with_llvm_function(mut self, name: &str, lines: usize, copies: usize) -> Self {
+        let name = LlvmFunctionName::from(name);
+        let function = LlvmFunction {
+            name: name.clone(),
+            lines: LlvmIrLines::new(lines),
+            copies: NumberOfCopies::new(copies),
+        };
+        self.llvm_functions.insert(name, function);
+        self
+    }
+
+    /// Timing info as cargo reports it for a unit named after the crate
+    pub(crate) fn with_build_time(mut self, seconds: f64) -> Self {
+        self.timing_info = Some(TimingInfo {
+            package_id: None,
+            target: crate::cargo::CargoTarget {
+                name: Some(self.name.to_string()),
+                kind: None,
+                crate_types: None,
+            },
+            mode: None,
+            duration: seconds,
+            rmeta_time: None,
+            finished_at: None,
+        });
+        self
+    }
+
+    pub(crate) fn with_durations(mut self, frontend: Duration, codegen: Duration) -> Self {
+        self.frontend_duration = Some(frontend);
+        self.codegen_duration = Some(codegen);
+        self
+    }
+
+    pub(crate) fn with_type_layout(mut self, layout: TypeLayout) -> Self {
+        self.type_layouts.insert(layout.name.clone(), layout);
+        self
+    }
+}
+
 /// Info about a symbol
 pub struct Symbol {
     /// A fully demangled symbol name including crate path (e.g., "serde::ser::Serialize::serialize")
@@ -266,6 +419,7 @@ pub struct Symbol {
 }
 
 /// Info about an LLVM function
+#[derive(Clone)]
 pub struct LlvmFunction {
     /// An LLVM function name
     pub name: LlvmFunctionName,
@@ -276,3 +430,67 @@ pub struct LlvmFunction {
     /// How many copies of this function exist in the binary
     pub copies: NumberOfCopies,
 }
+
+/// Memory layout of a type, from `-Zprint-type-sizes`
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeLayout {
+    pub name: TypeName,
+
+    pub size: ByteSize,
+
+    pub alignment: ByteSize,
+
+    /// Size of the discriminant of an enum or future, `None` for structs
+    pub discriminant: Option<ByteSize>,
+
+    /// Fields of a struct, in memory order. Empty for enums.
+    pub fields: Vec<FieldLayout>,
+
+    /// Variants of an enum, or states of a future, largest first
+    pub variants: Vec<VariantLayout>,
+
+    /// Padding between fields and at the end, outside of variants
+    pub padding: ByteSize,
+}
+
+impl TypeLayout {
+    /// Bytes that don't hold data: padding, plus the padding of the largest variant
+    pub fn total_padding(&self) -> ByteSize {
+        let variant_padding = self
+            .variants
+            .first()
+            .map_or(ByteSize::new(0u64), |v| v.padding);
+        self.padding + variant_padding
+    }
+
+    /// The largest variant, which decides the size of an enum
+    pub fn largest_variant(&self) -> Option<&VariantLayout> {
+        self.variants.first()
+    }
+}
+
+/// A variant of an enum, or a state of a future (e.g. `Suspend0`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct VariantLayout {
+    pub name: String,
+
+    pub size: ByteSize,
+
+    /// In memory order
+    pub fields: Vec<FieldLayout>,
+
+    /// Padding between fields of the variant
+    pub padding: ByteSize,
+}
+
+/// A field of a struct or variant, or a local held across an `.await`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldLayout {
+    /// `a` for `.a`, `0` for tuple fields
+    pub name: String,
+
+    pub size: ByteSize,
+
+    /// Only known when aligning the field required padding before it
+    pub alignment: Option<ByteSize>,
+}